@@ -5,6 +5,30 @@ use ::std::ffi::CStr;
 use ::std::ptr;
 use ::std::os::raw::c_char;
 use ::std::slice;
+use ::std::time::{Duration, UNIX_EPOCH};
+use ::std::collections::HashMap;
+use ::std::sync::{Arc, Mutex};
+
+use ::error::CError;
+use ::{Subscription, Channel};
+
+lazy_static! {
+    /// Holds the subscriptions made via the C API (C can't hold on to our
+    /// handles, so it gets an id instead)
+    static ref SUBSCRIPTIONS: Mutex<HashMap<u64, Arc<Subscription>>> = Mutex::new(HashMap::new());
+}
+
+/// Hand a message over to C
+fn give_msg(mut msg: Vec<u8>, len_c: *mut usize) -> *const u8 {
+    // make len == capacity
+    msg.shrink_to_fit();
+    let ptr = msg.as_mut_ptr();
+    unsafe {
+        *len_c = msg.len();
+        mem::forget(msg);
+    }
+    ptr
+}
 
 #[no_mangle]
 pub extern fn carrier_send(channel_c: *const c_char, message_bytes: *const u8, message_len: usize) -> i32 {
@@ -98,6 +122,204 @@ pub extern fn carrier_recv_nb(channel_c: *const c_char, len_c: *mut usize) -> *c
     }
 }
 
+/// Like `carrier_recv`, but gives up after `timeout_ms` milliseconds. On
+/// timeout, returns null and sets `len_c` to 0. On error, returns null and sets
+/// `len_c` to 1.
+#[no_mangle]
+pub extern fn carrier_recv_timeout(channel_c: *const c_char, timeout_ms: u64, len_c: *mut usize) -> *const u8 {
+    let null = ptr::null_mut();
+    unsafe { *len_c = 0; }
+    if channel_c.is_null() {
+        unsafe { *len_c = 1; }
+        return null;
+    }
+    let channel_res = unsafe { CStr::from_ptr(channel_c).to_str() };
+    let channel = match channel_res {
+        Ok(x) => x,
+        Err(e) => {
+            println!("carrier: recv_timeout: error: {}", e);
+            unsafe { *len_c = 1; }
+            return null;
+        },
+    };
+    match ::recv_timeout(channel, Duration::from_millis(timeout_ms)) {
+        Ok(mut x) => {
+            // make len == capacity
+            x.shrink_to_fit();
+            let ptr = x.as_mut_ptr();
+            unsafe {
+                *len_c = x.len();
+                mem::forget(x);
+            }
+            ptr
+        },
+        Err(CError::Timeout) => null,
+        Err(e) => {
+            println!("carrier: recv_timeout: error: {}", e);
+            unsafe { *len_c = 1; }
+            return null;
+        },
+    }
+}
+
+/// Subscribe to a broadcast channel. Returns the subscription id, or 0 on
+/// error.
+#[no_mangle]
+pub extern fn carrier_subscribe(channel_c: *const c_char) -> u64 {
+    if channel_c.is_null() { return 0; }
+    let channel_res = unsafe { CStr::from_ptr(channel_c).to_str() };
+    let channel = match channel_res {
+        Ok(x) => x,
+        Err(e) => {
+            println!("carrier: subscribe: error: {}", e);
+            return 0;
+        },
+    };
+    let sub = ::subscribe(channel);
+    let id = sub.id();
+    SUBSCRIPTIONS.lock().unwrap().insert(id, Arc::new(sub));
+    id
+}
+
+/// Remove a subscription. Returns -1 if there was no such subscription.
+///
+/// Note that anyone currently blocked receiving on the subscription stays
+/// blocked, so use non-blocking or timeout receives if you plan on
+/// unsubscribing from another thread.
+#[no_mangle]
+pub extern fn carrier_unsubscribe(sub_id: u64) -> i32 {
+    let sub = SUBSCRIPTIONS.lock().unwrap().remove(&sub_id);
+    match sub {
+        Some(x) => {
+            x.unsubscribe();
+            0
+        }
+        None => -1,
+    }
+}
+
+/// Publish a message to a broadcast channel. Returns the number of
+/// subscribers the message went to, or a negative number on error.
+#[no_mangle]
+pub extern fn carrier_publish(channel_c: *const c_char, message_bytes: *const u8, message_len: usize) -> i32 {
+    if channel_c.is_null() { return -1; }
+    if message_bytes.is_null() { return -1; }
+    let channel_res = unsafe { CStr::from_ptr(channel_c).to_str() };
+    let channel = match channel_res {
+        Ok(x) => x,
+        Err(e) => {
+            println!("carrier: publish: error: {}", e);
+            return -3;
+        },
+    };
+    let message = Vec::from(unsafe { slice::from_raw_parts(message_bytes, message_len) });
+    ::publish(channel, message) as i32
+}
+
+/// Receive a message from a subscription. `timeout_ms` works like so:
+///
+/// - `< 0` blocks until a message comes in
+/// - `0` returns right away
+/// - `> 0` waits at most that many milliseconds
+///
+/// If no message is available, returns null and sets `len_c` to 0. On error
+/// (such as a bad subscription id), returns null and sets `len_c` to 1.
+#[no_mangle]
+pub extern fn carrier_recv_subscription(sub_id: u64, timeout_ms: i64, len_c: *mut usize) -> *const u8 {
+    let null = ptr::null_mut();
+    unsafe { *len_c = 0; }
+    let sub = SUBSCRIPTIONS.lock().unwrap().get(&sub_id).map(|x| x.clone());
+    let sub = match sub {
+        Some(x) => x,
+        None => {
+            println!("carrier: recv_subscription: error: no such subscription {}", sub_id);
+            unsafe { *len_c = 1; }
+            return null;
+        }
+    };
+    let msg = if timeout_ms < 0 {
+        Some(sub.recv())
+    } else if timeout_ms == 0 {
+        sub.recv_nb()
+    } else {
+        match sub.recv_timeout(Duration::from_millis(timeout_ms as u64)) {
+            Ok(x) => Some(x),
+            Err(CError::Timeout) => None,
+            Err(e) => {
+                println!("carrier: recv_subscription: error: {}", e);
+                unsafe { *len_c = 1; }
+                return null;
+            }
+        }
+    };
+    match msg {
+        Some(x) => give_msg(x, len_c),
+        None => null,
+    }
+}
+
+/// Quote a string for JSON
+fn json_string(val: &str) -> String {
+    let mut out = String::with_capacity(val.len() + 2);
+    out.push('"');
+    for ch in val.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Turn a channel listing into JSON. Carrier doesn't depend on serde, so we
+/// build it by hand.
+fn channels_json(channels: &Vec<Channel>) -> String {
+    let items = channels.iter().map(|chan| {
+        let stats = &chan.stats;
+        let capacity = match stats.capacity {
+            Some(x) => x.to_string(),
+            None => String::from("null"),
+        };
+        let overflow = match stats.overflow {
+            Some(x) => json_string(&x.to_string()),
+            None => String::from("null"),
+        };
+        let last_activity = match stats.last_activity.map(|x| x.duration_since(UNIX_EPOCH)) {
+            Some(Ok(x)) => ((x.as_secs() * 1000) + (x.subsec_nanos() / 1_000_000) as u64).to_string(),
+            _ => String::from("null"),
+        };
+        format!(
+            "{{\"name\":{},\"type\":\"{}\",\"messages\":{},\"listeners\":{},\"sent\":{},\"received\":{},\"dropped\":{},\"capacity\":{},\"overflow\":{},\"last_activity\":{}}}",
+            json_string(&chan.name),
+            if chan.broadcast { "broadcast" } else { "queue" },
+            stats.messages,
+            stats.listeners,
+            stats.sent,
+            stats.received,
+            stats.dropped,
+            capacity,
+            overflow,
+            last_activity
+        )
+    }).collect::<Vec<_>>();
+    format!("[{}]", items.join(","))
+}
+
+/// Get a JSON listing of all active channels and their stats (see
+/// `carrier::channels()`). `last_activity` is in milliseconds since the unix
+/// epoch. Free the result with `carrier_free`.
+#[no_mangle]
+pub extern fn carrier_channels(len_c: *mut usize) -> *const u8 {
+    let json = channels_json(&::channels());
+    give_msg(Vec::from(json.as_bytes()), len_c)
+}
+
 #[no_mangle]
 pub extern fn carrier_free(msg: *const u8, len: usize) -> i32 {
     let vec = unsafe { Vec::from_raw_parts(msg as *mut u8, len, len) };
@@ -105,3 +327,22 @@ pub extern fn carrier_free(msg: *const u8, len: usize) -> i32 {
     0
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lists_channels_as_json() {
+        let mut stats = ::stats("c:nothing-here");
+        stats.sent = 3;
+        stats.capacity = Some(10);
+        stats.overflow = Some(::Overflow::DropOldest);
+        let chans = vec![Channel { name: String::from("c:\"quoted\""), broadcast: false, stats: stats }];
+        assert_eq!(
+            channels_json(&chans),
+            r#"[{"name":"c:\"quoted\"","type":"queue","messages":0,"listeners":0,"sent":3,"received":0,"dropped":0,"capacity":10,"overflow":"drop_oldest","last_activity":null}]"#
+        );
+        let empty: Vec<Channel> = Vec::new();
+        assert_eq!(channels_json(&empty), "[]");
+    }
+}
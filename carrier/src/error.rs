@@ -8,6 +8,20 @@ quick_error! {
             description(str)
             display("error: {}", str)
         }
+        Full(capacity: usize) {
+            description("channel full")
+            display("error: channel is full (capacity {})", capacity)
+        }
+        Io(err: ::std::io::Error) {
+            cause(err)
+            description("io error")
+            display("error: io: {}", err)
+            from()
+        }
+        Timeout {
+            description("timed out")
+            display("error: timed out waiting for message")
+        }
     }
 }
 
@@ -22,8 +22,25 @@
 //!      used. Once a channel has no messages on it and also has no listeners,
 //!      it is recycled (removed entirely). This allows you to very cheaply make
 //!      and use new channels that clean themselves up when finished.
+//!   4. Channels are unbounded by default. A channel can be given a capacity
+//!      (see `set_capacity()`) along with an `Overflow` policy that says what
+//!      happens when someone sends to it while it's full. Bounded channels are
+//!      never recycled, so their settings and `stats()` stick around.
+//!
+//! Sometimes you *do* want every listener to get every message (say, two UI
+//! windows that both want to hear about events). For this, carrier also has
+//! broadcast channels: `subscribe()` to a channel to get a `Subscription`
+//! handle with its own private queue, and anything `publish()`ed on that
+//! channel is copied to every subscriber. Dropping the handle (or calling
+//! `unsubscribe()` on it) removes the subscriber, and once a broadcast channel
+//! has no subscribers left it's recycled. Messages published to a channel
+//! with no subscribers go nowhere. Broadcast channels live in their own
+//! namespace, so they never interfere with regular `send()`/`recv()` channels
+//! of the same name.
+//!
+//! On unix, the `unix` module can serve all of this over a Unix domain socket
+//! so other processes can send/receive on the same channels.
 
-extern crate crossbeam;
 #[macro_use]
 extern crate lazy_static;
 #[macro_use]
@@ -31,11 +48,15 @@ extern crate quick_error;
 
 mod error;
 pub mod c;
+#[cfg(unix)]
+pub mod unix;
 
-use ::std::sync::{Arc, RwLock};
-use ::std::collections::HashMap;
-
-use ::crossbeam::sync::MsQueue;
+use ::std::sync::{Arc, RwLock, Mutex, Condvar};
+use ::std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use ::std::collections::{HashMap, VecDeque};
+use ::std::fmt;
+use ::std::str::FromStr;
+use ::std::time::{Duration, Instant, SystemTime};
 
 pub use ::error::CError;
 use ::error::CResult;
@@ -44,87 +65,273 @@ lazy_static! {
     static ref CONN: Carrier = Carrier::new().unwrap();
 }
 
-/// The carrier Queue is a quick and simple wrapper around MsQueue that keeps
-/// track of a bit more state than MsQueue does.
-struct Queue<T> {
-    internal: MsQueue<T>,
-    messages: RwLock<i32>,
-    users: RwLock<i32>,
+/// Used to generate subscription ids
+static SUBSCRIPTION_ID: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// What happens when a message is sent to a channel that's full
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Overflow {
+    /// The sender waits until there's room
+    Block,
+    /// The oldest message in the channel is thrown out to make room
+    DropOldest,
+    /// The message being sent is thrown out
+    DropNewest,
+    /// The sender gets a `CError::Full` error
+    Error,
 }
 
-impl<T> Queue<T> {
-    /// Create a new carrier queue.
-    fn new() -> Queue<T> {
-        Queue {
-            internal: MsQueue::new(),
-            messages: RwLock::new(0),
-            users: RwLock::new(0),
+impl fmt::Display for Overflow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Overflow::Block => "block",
+            Overflow::DropOldest => "drop_oldest",
+            Overflow::DropNewest => "drop_newest",
+            Overflow::Error => "error",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Overflow {
+    type Err = CError;
+    fn from_str(s: &str) -> CResult<Overflow> {
+        match s {
+            "block" => Ok(Overflow::Block),
+            "drop_oldest" => Ok(Overflow::DropOldest),
+            "drop_newest" => Ok(Overflow::DropNewest),
+            "error" => Ok(Overflow::Error),
+            _ => Err(CError::Msg(format!("unknown overflow policy: {}", s))),
         }
     }
+}
+
+/// A channel's capacity and what to do when it's reached
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Limits {
+    capacity: usize,
+    overflow: Overflow,
+}
 
-    /// Increment the number of messages this queue has by a certain amount (1).
-    fn inc_messages(&self, val: i32) {
-        let mut mguard = self.messages.write().unwrap();
-        (*mguard) += val;
+/// Describes the state of a channel
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    /// How many messages are waiting in the channel
+    pub messages: usize,
+    /// The channel's capacity (None if unbounded)
+    pub capacity: Option<usize>,
+    /// What happens when the channel is full (None if unbounded)
+    pub overflow: Option<Overflow>,
+    /// How many messages have been thrown out because the channel was full
+    pub dropped: u64,
+    /// How many listeners are blocked waiting on a message
+    pub listeners: usize,
+    /// How many messages have been sent to the channel
+    pub sent: u64,
+    /// How many messages have been received from the channel
+    pub received: u64,
+    /// The last time a message was sent or received
+    pub last_activity: Option<SystemTime>,
+}
+
+impl Stats {
+    /// Stats for a channel that doesn't exist
+    fn empty() -> Stats {
+        Stats {
+            messages: 0,
+            capacity: None,
+            overflow: None,
+            dropped: 0,
+            listeners: 0,
+            sent: 0,
+            received: 0,
+            last_activity: None,
+        }
     }
+}
 
-    /// Increment the number of users this queue has by a certain amount (1).
-    fn inc_users(&self, val: i32) {
-        let mut uguard = self.users.write().unwrap();
-        (*uguard) += val;
+/// Describes a channel, as returned by `channels()`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Channel {
+    /// The channel's name
+    pub name: String,
+    /// Whether this is a broadcast channel. A broadcast channel's stats are
+    /// added up across all of its subscribers (and its listener count is its
+    /// subscriber count).
+    pub broadcast: bool,
+    /// The channel's stats
+    pub stats: Stats,
+}
+
+/// Holds a queue's messages and how many people are waiting on them
+struct QueueState<T> {
+    messages: VecDeque<T>,
+    users: i32,
+    limits: Option<Limits>,
+    dropped: u64,
+    sent: u64,
+    received: u64,
+    last_activity: Option<SystemTime>,
+}
+
+impl<T> QueueState<T> {
+    /// Take the next message off the queue, updating our counters
+    fn take(&mut self) -> Option<T> {
+        let res = self.messages.pop_front();
+        if res.is_some() {
+            self.received += 1;
+            self.last_activity = Some(SystemTime::now());
+        }
+        res
     }
+}
+
+/// The carrier Queue is a simple blocking queue (a VecDeque guarded by a
+/// mutex, with a condvar to wake up listeners) that also keeps track of how
+/// many listeners it has so abandoned queues can be cleaned up.
+struct Queue<T> {
+    state: Mutex<QueueState<T>>,
+    cond: Condvar,
+    /// Wakes up senders blocked on a full queue
+    not_full: Condvar,
+}
 
-    /// Get how many messages this queue currently has listening to it.
-    fn num_messages(&self) -> i32 {
-        let mguard = self.messages.read().unwrap();
-        (*mguard).clone()
+impl<T> Queue<T> {
+    /// Create a new carrier queue.
+    fn new() -> Queue<T> {
+        Queue {
+            state: Mutex::new(QueueState {
+                messages: VecDeque::new(),
+                users: 0,
+                limits: None,
+                dropped: 0,
+                sent: 0,
+                received: 0,
+                last_activity: None,
+            }),
+            cond: Condvar::new(),
+            not_full: Condvar::new(),
+        }
     }
 
-    /// Get how many users this queue currently has listening to it.
-    fn num_users(&self) -> i32 {
-        let uguard = self.users.read().unwrap();
-        (*uguard).clone()
+    /// Set (or clear) this queue's limits. Messages already in the queue are
+    /// left alone, even if there are more than the new capacity allows.
+    fn set_limits(&self, limits: Option<Limits>) {
+        let mut guard = self.state.lock().unwrap();
+        guard.limits = limits;
+        // let blocked senders re-check against the new limits
+        self.not_full.notify_all();
     }
 
-    /// MsQueue.push()
-    fn push(&self, val: T) {
-        self.internal.push(val);
-        self.inc_messages(1);
+    /// Add a message to the queue, waking up a listener (if any). If the queue
+    /// is full, its overflow policy decides what happens.
+    fn push(&self, val: T) -> CResult<()> {
+        let mut guard = self.state.lock().unwrap();
+        loop {
+            let limits = match guard.limits {
+                Some(x) => x,
+                None => break,
+            };
+            if guard.messages.len() < limits.capacity { break; }
+            match limits.overflow {
+                Overflow::Block => {
+                    guard = self.not_full.wait(guard).unwrap();
+                }
+                Overflow::DropOldest => {
+                    while guard.messages.len() >= limits.capacity && guard.messages.pop_front().is_some() {
+                        guard.dropped += 1;
+                    }
+                    break;
+                }
+                Overflow::DropNewest => {
+                    guard.dropped += 1;
+                    return Ok(());
+                }
+                Overflow::Error => {
+                    guard.dropped += 1;
+                    return Err(CError::Full(limits.capacity));
+                }
+            }
+        }
+        guard.messages.push_back(val);
+        guard.sent += 1;
+        guard.last_activity = Some(SystemTime::now());
+        self.cond.notify_one();
+        Ok(())
     }
 
-    /// MsQueue.try_pop()
+    /// Grab the next message, if there is one.
     fn try_pop(&self) -> Option<T> {
-        let res = self.internal.try_pop();
-        if res.is_some() {
-            self.inc_messages(-1);
-        } else {
-            *(self.messages.write().unwrap()) = 0;
-        }
+        let mut guard = self.state.lock().unwrap();
+        let res = guard.take();
+        if res.is_some() { self.not_full.notify_one(); }
         res
     }
 
-    /// MsQueue.pop()
+    /// Grab the next message, blocking until one is available.
     fn pop(&self) -> T {
-        self.inc_users(1);
-        let res = self.internal.pop();
-        self.inc_users(-1);
-        self.inc_messages(-1);
-        res
+        let mut guard = self.state.lock().unwrap();
+        guard.users += 1;
+        loop {
+            if let Some(x) = guard.take() {
+                guard.users -= 1;
+                self.not_full.notify_one();
+                return x;
+            }
+            guard = self.cond.wait(guard).unwrap();
+        }
+    }
+
+    /// Grab the next message, blocking until one is available or until the
+    /// timeout passes (in which case we return None).
+    fn pop_timeout(&self, timeout: Duration) -> Option<T> {
+        let deadline = Instant::now() + timeout;
+        let mut guard = self.state.lock().unwrap();
+        guard.users += 1;
+        loop {
+            if let Some(x) = guard.take() {
+                guard.users -= 1;
+                self.not_full.notify_one();
+                return Some(x);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                guard.users -= 1;
+                return None;
+            }
+            guard = self.cond.wait_timeout(guard, deadline - now).unwrap().0;
+        }
     }
 
     /// Determine if this queue has been "abandoned" ...meaning it has no
-    /// messages in it and there is nobody listening to it.
+    /// messages in it and there is nobody listening to it. Bounded queues are
+    /// never abandoned.
     fn is_abandoned(&self) -> bool {
-        if self.num_messages() <= 0 && self.num_users() <= 0 {
-            true
-        } else {
-            false
+        let guard = self.state.lock().unwrap();
+        guard.messages.is_empty() && guard.users <= 0 && guard.limits.is_none()
+    }
+
+    /// Get this queue's stats
+    fn stats(&self) -> Stats {
+        let guard = self.state.lock().unwrap();
+        Stats {
+            messages: guard.messages.len(),
+            capacity: guard.limits.map(|x| x.capacity),
+            overflow: guard.limits.map(|x| x.overflow),
+            dropped: guard.dropped,
+            listeners: if guard.users > 0 { guard.users as usize } else { 0 },
+            sent: guard.sent,
+            received: guard.received,
+            last_activity: guard.last_activity,
         }
     }
 }
 
 pub struct Carrier {
     queues: RwLock<HashMap<String, Arc<Queue<Vec<u8>>>>>,
+    /// Our broadcast channels, each holding its subscribers' queues by
+    /// subscription id
+    broadcasts: RwLock<HashMap<String, HashMap<u64, Arc<Queue<Vec<u8>>>>>>,
 }
 
 //unsafe impl Send for Carrier {}
@@ -135,6 +342,7 @@ impl Carrier {
     pub fn new() -> CResult<Carrier> {
         Ok(Carrier {
             queues: RwLock::new(HashMap::new()),
+            broadcasts: RwLock::new(HashMap::new()),
         })
     }
 
@@ -150,11 +358,54 @@ impl Carrier {
         }
     }
 
+    /// Grab a channel, if it exists
+    fn get(&self, channel: &String) -> Option<Arc<Queue<Vec<u8>>>> {
+        let guard = self.queues.read().unwrap();
+        (*guard).get(channel).map(|x| x.clone())
+    }
+
     fn exists(&self, channel: &String) -> bool {
         let guard = self.queues.read().unwrap();
         (*guard).contains_key(channel)
     }
 
+    /// List our channels along with their stats
+    fn channels(&self) -> Vec<Channel> {
+        let queues = {
+            let guard = self.queues.read().unwrap();
+            (*guard).iter().map(|(name, queue)| (name.clone(), queue.clone())).collect::<Vec<_>>()
+        };
+        let broadcasts = {
+            let guard = self.broadcasts.read().unwrap();
+            (*guard).iter()
+                .map(|(name, subs)| (name.clone(), subs.values().map(|x| x.clone()).collect::<Vec<_>>()))
+                .collect::<Vec<_>>()
+        };
+        let mut channels = Vec::with_capacity(queues.len() + broadcasts.len());
+        for (name, queue) in queues {
+            channels.push(Channel { name: name, broadcast: false, stats: queue.stats() });
+        }
+        for (name, subs) in broadcasts {
+            let mut stats = Stats::empty();
+            stats.listeners = subs.len();
+            for sub in subs {
+                let substats = sub.stats();
+                stats.messages += substats.messages;
+                stats.dropped += substats.dropped;
+                stats.sent += substats.sent;
+                stats.received += substats.received;
+                stats.last_activity = match (stats.last_activity, substats.last_activity) {
+                    (Some(x), Some(y)) => Some(if x > y { x } else { y }),
+                    (x, None) => x,
+                    (None, y) => y,
+                };
+            }
+            channels.push(Channel { name: name, broadcast: true, stats: stats });
+        }
+        channels.sort_by(|a, b| a.name.cmp(&b.name).then(a.broadcast.cmp(&b.broadcast)));
+        channels
+    }
+
     /// Count how many active channels there are
     fn count(&self) -> u32 {
         let guard = self.queues.read().unwrap();
@@ -167,17 +418,66 @@ impl Carrier {
         (*guard).remove(channel);
     }
 
+    /// Add a subscriber to a broadcast channel
+    fn subscribe(&self, channel: &String, id: u64, queue: Arc<Queue<Vec<u8>>>) {
+        let mut guard = self.broadcasts.write().unwrap();
+        let subscribers = (*guard).entry(channel.clone()).or_insert_with(|| HashMap::new());
+        subscribers.insert(id, queue);
+    }
+
+    /// Remove a subscriber from a broadcast channel, recycling the channel if
+    /// nobody's left
+    fn unsubscribe(&self, channel: &String, id: u64) -> bool {
+        let mut guard = self.broadcasts.write().unwrap();
+        let (removed, empty) = match (*guard).get_mut(channel) {
+            Some(subscribers) => (subscribers.remove(&id).is_some(), subscribers.is_empty()),
+            None => (false, false),
+        };
+        if empty { (*guard).remove(channel); }
+        removed
+    }
+
+    /// Send a message to everyone subscribed to a broadcast channel
+    fn publish(&self, channel: &String, message: Vec<u8>) -> usize {
+        // grab the queues and let go of the lock so we're not holding it while
+        // we push (subscriptions can't block, see `Subscription::set_capacity()`)
+        let queues = {
+            let guard = self.broadcasts.read().unwrap();
+            match (*guard).get(channel) {
+                Some(x) => x.values().map(|x| x.clone()).collect::<Vec<_>>(),
+                None => return 0,
+            }
+        };
+        let mut delivered = 0;
+        for queue in queues {
+            // one full subscriber doesn't keep the others from getting the
+            // message
+            match queue.push(message.clone()) {
+                Ok(_) => delivered += 1,
+                Err(_) => {}
+            }
+        }
+        delivered
+    }
+
+    /// Count a broadcast channel's subscribers
+    fn subscribers(&self, channel: &String) -> usize {
+        let guard = self.broadcasts.read().unwrap();
+        (*guard).get(channel).map(|x| x.len()).unwrap_or(0)
+    }
+
     fn wipe(&self) {
         let mut guard = self.queues.write().unwrap();
         guard.clear();
+        let mut guard = self.broadcasts.write().unwrap();
+        guard.clear();
     }
 }
 
 /// Send a message on a channel
 pub fn send(channel: &str, message: Vec<u8>) -> CResult<()> {
     let queue = (*CONN).ensure(&String::from(channel));
-    queue.push(message);
-    Ok(())
+    queue.push(message)
 }
 
 /// Send a message on a channel
@@ -194,6 +494,18 @@ pub fn recv(channel: &str) -> CResult<Vec<u8>> {
     res
 }
 
+/// Blocking receive that gives up after `timeout`, returning a
+/// `CError::Timeout` error if no message came in.
+pub fn recv_timeout(channel: &str, timeout: Duration) -> CResult<Vec<u8>> {
+    let queue = (*CONN).ensure(&String::from(channel));
+    let res = match queue.pop_timeout(timeout) {
+        Some(x) => Ok(x),
+        None => Err(CError::Timeout),
+    };
+    if queue.is_abandoned() { (*CONN).remove(&String::from(channel)); }
+    res
+}
+
 /// Non-blocking receive
 pub fn recv_nb(channel: &str) -> CResult<Option<Vec<u8>>> {
     let channel = String::from(channel);
@@ -206,6 +518,136 @@ pub fn recv_nb(channel: &str) -> CResult<Option<Vec<u8>>> {
     res
 }
 
+/// Limit how many messages can wait in a channel. Once the channel is full,
+/// `overflow` decides what happens to new messages. Creates the channel if it
+/// doesn't exist yet.
+pub fn set_capacity(channel: &str, capacity: usize, overflow: Overflow) -> CResult<()> {
+    if capacity == 0 {
+        return Err(CError::Msg(String::from("channel capacity must be at least 1")));
+    }
+    let queue = (*CONN).ensure(&String::from(channel));
+    queue.set_limits(Some(Limits { capacity: capacity, overflow: overflow }));
+    Ok(())
+}
+
+/// Make a channel unbounded again
+pub fn clear_capacity(channel: &str) {
+    let channel = String::from(channel);
+    if let Some(queue) = (*CONN).get(&channel) {
+        queue.set_limits(None);
+        if queue.is_abandoned() { (*CONN).remove(&channel); }
+    }
+}
+
+/// Get a channel's stats. Channels that don't exist are empty and unbounded.
+pub fn stats(channel: &str) -> Stats {
+    match (*CONN).get(&String::from(channel)) {
+        Some(queue) => queue.stats(),
+        None => Stats::empty(),
+    }
+}
+
+/// A handle to a broadcast channel subscription. Every message published on
+/// the channel after subscribing gets copied into this subscription's queue.
+/// The subscription ends when the handle is dropped.
+pub struct Subscription {
+    id: u64,
+    channel: String,
+    queue: Arc<Queue<Vec<u8>>>,
+}
+
+impl Subscription {
+    /// This subscription's (globally unique) id
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The broadcast channel we're subscribed to
+    pub fn channel(&self) -> &str {
+        self.channel.as_str()
+    }
+
+    /// Blocking receive
+    pub fn recv(&self) -> Vec<u8> {
+        self.queue.pop()
+    }
+
+    /// Non-blocking receive
+    pub fn recv_nb(&self) -> Option<Vec<u8>> {
+        self.queue.try_pop()
+    }
+
+    /// Blocking receive that gives up after `timeout`, returning a
+    /// `CError::Timeout` error if no message came in.
+    pub fn recv_timeout(&self, timeout: Duration) -> CResult<Vec<u8>> {
+        match self.queue.pop_timeout(timeout) {
+            Some(x) => Ok(x),
+            None => Err(CError::Timeout),
+        }
+    }
+
+    /// Limit how many messages can wait in this subscription's queue (see
+    /// `set_capacity()`). `Overflow::Block` isn't allowed: a full subscriber
+    /// would stall whoever publishes to the channel.
+    pub fn set_capacity(&self, capacity: usize, overflow: Overflow) -> CResult<()> {
+        if capacity == 0 {
+            return Err(CError::Msg(String::from("channel capacity must be at least 1")));
+        }
+        if overflow == Overflow::Block {
+            return Err(CError::Msg(String::from("subscriptions can't use a blocking overflow policy")));
+        }
+        self.queue.set_limits(Some(Limits { capacity: capacity, overflow: overflow }));
+        Ok(())
+    }
+
+    /// Get this subscription's stats
+    pub fn stats(&self) -> Stats {
+        self.queue.stats()
+    }
+
+    /// Stop receiving messages. Any messages already queued can still be
+    /// received. Returns false if we were already unsubscribed.
+    pub fn unsubscribe(&self) -> bool {
+        (*CONN).unsubscribe(&self.channel, self.id)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.unsubscribe();
+    }
+}
+
+/// Subscribe to a broadcast channel
+pub fn subscribe(channel: &str) -> Subscription {
+    let id = (SUBSCRIPTION_ID.fetch_add(1, Ordering::SeqCst) + 1) as u64;
+    let channel = String::from(channel);
+    let queue = Arc::new(Queue::new());
+    (*CONN).subscribe(&channel, id, queue.clone());
+    Subscription {
+        id: id,
+        channel: channel,
+        queue: queue,
+    }
+}
+
+/// Send a copy of a message to everyone subscribed to a broadcast channel.
+/// Returns how many subscribers the message went to.
+pub fn publish(channel: &str, message: Vec<u8>) -> usize {
+    (*CONN).publish(&String::from(channel), message)
+}
+
+/// Get the number of subscribers a broadcast channel has
+pub fn subscribers(channel: &str) -> usize {
+    (*CONN).subscribers(&String::from(channel))
+}
+
+/// List all active channels (regular and broadcast) along with their stats.
+/// Handy for figuring out which channel nobody's listening to.
+pub fn channels() -> Vec<Channel> {
+    (*CONN).channels()
+}
+
 /// Returns the number of active channels
 pub fn count() -> u32 {
     (*CONN).count()
@@ -222,6 +664,7 @@ mod tests {
 
     use super::*;
     use ::std::sync::{Arc, RwLock};
+    use ::std::time::Duration;
 
     #[test]
     fn send_recv_simple() {
@@ -250,6 +693,151 @@ mod tests {
         handle.join().unwrap();
     }
 
+    #[test]
+    fn recv_with_timeout() {
+        match recv_timeout("timeout", Duration::from_millis(10)) {
+            Err(CError::Timeout) => {}
+            x => panic!("expected timeout, got {:?}", x),
+        }
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            send_string("timeout", String::from("made it")).unwrap();
+        });
+        let msg = String::from_utf8(recv_timeout("timeout", Duration::from_secs(5)).unwrap()).unwrap();
+        assert_eq!(msg, "made it");
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn bounded_channels() {
+        set_capacity("bounded:oldest", 2, Overflow::DropOldest).unwrap();
+        for msg in &["one", "two", "three"] {
+            send_string("bounded:oldest", String::from(*msg)).unwrap();
+        }
+        let stats1 = stats("bounded:oldest");
+        assert_eq!(stats1.messages, 2);
+        assert_eq!(stats1.capacity, Some(2));
+        assert_eq!(stats1.overflow, Some(Overflow::DropOldest));
+        assert_eq!(stats1.dropped, 1);
+        assert_eq!(String::from_utf8(recv("bounded:oldest").unwrap()).unwrap(), "two");
+        assert_eq!(String::from_utf8(recv("bounded:oldest").unwrap()).unwrap(), "three");
+        // bounded channels stick around when empty
+        assert_eq!(stats("bounded:oldest").dropped, 1);
+        clear_capacity("bounded:oldest");
+        assert_eq!(stats("bounded:oldest"), Stats::empty());
+
+        set_capacity("bounded:newest", 1, Overflow::DropNewest).unwrap();
+        send_string("bounded:newest", String::from("one")).unwrap();
+        send_string("bounded:newest", String::from("two")).unwrap();
+        assert_eq!(stats("bounded:newest").dropped, 1);
+        assert_eq!(String::from_utf8(recv("bounded:newest").unwrap()).unwrap(), "one");
+        clear_capacity("bounded:newest");
+
+        set_capacity("bounded:error", 1, Overflow::Error).unwrap();
+        send_string("bounded:error", String::from("one")).unwrap();
+        match send_string("bounded:error", String::from("two")) {
+            Err(CError::Full(1)) => {}
+            x => panic!("expected full error, got {:?}", x),
+        }
+        assert_eq!(stats("bounded:error").dropped, 1);
+        clear_capacity("bounded:error");
+        recv("bounded:error").unwrap();
+
+        assert!(set_capacity("bounded:zero", 0, Overflow::Block).is_err());
+        assert_eq!("drop_oldest".parse::<Overflow>().unwrap(), Overflow::DropOldest);
+        assert_eq!(format!("{}", Overflow::DropNewest), "drop_newest");
+        assert!("nope".parse::<Overflow>().is_err());
+    }
+
+    #[test]
+    fn bounded_channels_block() {
+        set_capacity("bounded:block", 1, Overflow::Block).unwrap();
+        send_string("bounded:block", String::from("one")).unwrap();
+        let handle = thread::spawn(|| {
+            // blocks until "one" is received
+            send_string("bounded:block", String::from("two")).unwrap();
+        });
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(stats("bounded:block").messages, 1);
+        assert_eq!(String::from_utf8(recv("bounded:block").unwrap()).unwrap(), "one");
+        handle.join().unwrap();
+        assert_eq!(String::from_utf8(recv("bounded:block").unwrap()).unwrap(), "two");
+        assert_eq!(stats("bounded:block").dropped, 0);
+        clear_capacity("bounded:block");
+    }
+
+    #[test]
+    fn broadcasts() {
+        assert_eq!(publish("broadcast", Vec::from("nobody home".as_bytes())), 0);
+        let sub1 = subscribe("broadcast");
+        let sub2 = subscribe("broadcast");
+        assert!(sub1.id() != sub2.id());
+        assert_eq!(sub1.channel(), "broadcast");
+        assert_eq!(subscribers("broadcast"), 2);
+        assert_eq!(publish("broadcast", Vec::from("hello".as_bytes())), 2);
+        assert_eq!(String::from_utf8(sub1.recv()).unwrap(), "hello");
+        assert_eq!(String::from_utf8(sub2.recv_nb().unwrap()).unwrap(), "hello");
+        assert_eq!(sub1.recv_nb(), None);
+        // regular channels of the same name are separate
+        assert_eq!(recv_nb("broadcast").unwrap(), None);
+
+        assert!(sub1.unsubscribe());
+        assert!(!sub1.unsubscribe());
+        assert_eq!(publish("broadcast", Vec::from("just you".as_bytes())), 1);
+        assert_eq!(sub1.recv_nb(), None);
+        assert_eq!(String::from_utf8(sub2.recv_timeout(Duration::from_secs(5)).unwrap()).unwrap(), "just you");
+        drop(sub2);
+        assert_eq!(subscribers("broadcast"), 0);
+        assert_eq!(publish("broadcast", Vec::from("anyone?".as_bytes())), 0);
+
+        let sub = subscribe("broadcast:bounded");
+        sub.set_capacity(1, Overflow::DropOldest).unwrap();
+        assert!(sub.set_capacity(1, Overflow::Block).is_err());
+        publish("broadcast:bounded", Vec::from("one".as_bytes()));
+        publish("broadcast:bounded", Vec::from("two".as_bytes()));
+        assert_eq!(sub.stats().dropped, 1);
+        assert_eq!(String::from_utf8(sub.recv()).unwrap(), "two");
+    }
+
+    #[test]
+    fn lists_channels() {
+        send_string("listing", String::from("one")).unwrap();
+        send_string("listing", String::from("two")).unwrap();
+        recv("listing").unwrap();
+        let _sub1 = subscribe("listing");
+        let sub2 = subscribe("listing");
+        publish("listing", Vec::from("hi".as_bytes()));
+        sub2.recv();
+
+        let listing = channels().into_iter().filter(|x| x.name == "listing").collect::<Vec<_>>();
+        assert_eq!(listing.len(), 2);
+        let queue = &listing[0];
+        assert!(!queue.broadcast);
+        assert_eq!(queue.stats.messages, 1);
+        assert_eq!(queue.stats.sent, 2);
+        assert_eq!(queue.stats.received, 1);
+        assert_eq!(queue.stats.listeners, 0);
+        assert!(queue.stats.last_activity.is_some());
+        let broadcast = &listing[1];
+        assert!(broadcast.broadcast);
+        assert_eq!(broadcast.stats.listeners, 2);
+        assert_eq!(broadcast.stats.messages, 1);
+        assert_eq!(broadcast.stats.sent, 2);
+        assert_eq!(broadcast.stats.received, 1);
+
+        let handle = thread::spawn(|| recv("listing:listeners").unwrap());
+        let mut listeners = 0;
+        for _ in 0..500 {
+            listeners = stats("listing:listeners").listeners;
+            if listeners > 0 { break; }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(listeners, 1);
+        send_string("listing:listeners", String::from("done")).unwrap();
+        handle.join().unwrap();
+        recv("listing").unwrap();
+    }
+
     #[test]
     fn lock_testing() {
         let num_tests = 999;
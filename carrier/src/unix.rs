@@ -0,0 +1,549 @@
+//! Exposes carrier's channels over a Unix domain socket, so a process that
+//! doesn't link carrier (a separate UI, a CLI tool) can talk to one that does.
+//!
+//! Every message on the socket is a frame:
+//!
+//! ```text
+//! [u32 length][u8 op][u16 channel length][channel][payload]
+//! ```
+//!
+//! where `length` covers everything after itself, and all numbers are big
+//! endian. The client sends a request frame and the server answers with a
+//! single response frame:
+//!
+//!   - `OP_SEND`: payload is the message to send on the channel. Answered with
+//!     `OP_OK` or `OP_ERR`.
+//!   - `OP_RECV`: payload is a u64 timeout in milliseconds (0 means don't
+//!     block, `u64::MAX` means block until a message comes in). Answered with
+//!     `OP_MSG` (payload is the message), `OP_EMPTY` if nothing came in, or
+//!     `OP_ERR`.
+//!   - `OP_SUBSCRIBE`: subscribes to the broadcast channel. Answered with
+//!     `OP_OK`, after which the server sends an `OP_MSG` frame for every
+//!     message published on the channel. The connection can't be used for
+//!     anything else after that.
+//!
+//! `OP_ERR` frames hold a UTF8 error message as their payload. Response frames
+//! echo back the request's channel.
+//!
+//! Requests on a connection are handled one at a time, so a blocking receive
+//! holds up the connection it was made on (and the client shouldn't send
+//! anything else on it until the response comes in). Clients that want to wait
+//! on more than one channel at once should open a connection per channel.
+//!
+//! The socket is created with owner-only (0600) permissions. Anyone who can
+//! connect to it has full access to every channel, so keep it somewhere only
+//! the user running the server can get to.
+
+use ::std::fs;
+use ::std::io::{self, Read, Write, ErrorKind};
+use ::std::os::unix::fs::PermissionsExt;
+use ::std::os::unix::net::{UnixListener, UnixStream};
+use ::std::path::{Path, PathBuf};
+use ::std::sync::Arc;
+use ::std::sync::atomic::{AtomicBool, Ordering};
+use ::std::thread;
+use ::std::time::Duration;
+
+use ::error::{CResult, CError};
+
+pub const OP_SEND: u8 = 1;
+pub const OP_RECV: u8 = 2;
+pub const OP_SUBSCRIBE: u8 = 3;
+pub const OP_OK: u8 = 128;
+pub const OP_MSG: u8 = 129;
+pub const OP_EMPTY: u8 = 130;
+pub const OP_ERR: u8 = 131;
+
+/// Passed as a receive timeout to block until a message comes in
+pub const BLOCK: u64 = ::std::u64::MAX;
+
+/// How long a blocking receive waits at a time before checking whether the
+/// client is still around
+const HANGUP_CHECK_MS: u64 = 1000;
+
+/// The biggest frame we'll accept. Big enough for file uploads, small enough
+/// that a garbage length doesn't make us try to allocate the world.
+const MAX_FRAME: usize = 512 * 1024 * 1024;
+
+/// A single message on the socket
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub op: u8,
+    pub channel: String,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// Create a new frame
+    pub fn new(op: u8, channel: &str, payload: Vec<u8>) -> Frame {
+        Frame {
+            op: op,
+            channel: String::from(channel),
+            payload: payload,
+        }
+    }
+}
+
+fn read_u16(bytes: &[u8]) -> u16 {
+    ((bytes[0] as u16) << 8) | (bytes[1] as u16)
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    bytes[0..4].iter().fold(0, |acc, x| (acc << 8) | (*x as u32))
+}
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    bytes[0..8].iter().fold(0, |acc, x| (acc << 8) | (*x as u64))
+}
+
+fn write_uint(out: &mut Vec<u8>, val: u64, bytes: usize) {
+    for i in (0..bytes).rev() {
+        out.push(((val >> (i * 8)) & 0xff) as u8);
+    }
+}
+
+/// Write a frame to a stream
+pub fn write_frame<W: Write>(stream: &mut W, frame: &Frame) -> CResult<()> {
+    let channel = frame.channel.as_bytes();
+    if channel.len() > (::std::u16::MAX as usize) {
+        return Err(CError::Msg(format!("channel name too long ({} bytes)", channel.len())));
+    }
+    let len = 1 + 2 + channel.len() + frame.payload.len();
+    if len > MAX_FRAME {
+        return Err(CError::Msg(format!("frame too large ({} bytes)", len)));
+    }
+    let mut out = Vec::with_capacity(4 + len);
+    write_uint(&mut out, len as u64, 4);
+    out.push(frame.op);
+    write_uint(&mut out, channel.len() as u64, 2);
+    out.extend_from_slice(channel);
+    out.extend_from_slice(&frame.payload[..]);
+    stream.write_all(&out[..])?;
+    stream.flush()?;
+    Ok(())
+}
+
+/// Read a frame from a stream. Returns None if the other side hung up
+/// cleanly (between frames).
+pub fn read_frame<R: Read>(stream: &mut R) -> CResult<Option<Frame>> {
+    let mut header = [0u8; 4];
+    match stream.read_exact(&mut header) {
+        Ok(_) => {}
+        Err(ref e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(From::from(e)),
+    }
+    let len = read_u32(&header) as usize;
+    if len < 3 || len > MAX_FRAME {
+        return Err(CError::Msg(format!("bad frame length: {}", len)));
+    }
+    let mut body = vec![0u8; len];
+    stream.read_exact(&mut body[..])?;
+    let channel_len = read_u16(&body[1..3]) as usize;
+    if 3 + channel_len > len {
+        return Err(CError::Msg(format!("bad channel length: {}", channel_len)));
+    }
+    let channel = match String::from_utf8(Vec::from(&body[3..(3 + channel_len)])) {
+        Ok(x) => x,
+        Err(e) => return Err(CError::Msg(format!("channel name is not utf8: {}", e))),
+    };
+    Ok(Some(Frame {
+        op: body[0],
+        channel: channel,
+        payload: Vec::from(&body[(3 + channel_len)..]),
+    }))
+}
+
+/// Check whether the client hung up. Only call this while the client is
+/// waiting on us (and so shouldn't be sending anything).
+fn hung_up(stream: &mut UnixStream) -> CResult<bool> {
+    let mut buf = [0u8; 1];
+    stream.set_read_timeout(Some(Duration::from_millis(1)))?;
+    let read = stream.read(&mut buf);
+    stream.set_read_timeout(None)?;
+    match read {
+        Ok(0) => Ok(true),
+        Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => Ok(false),
+        Ok(_) => Err(CError::Msg(String::from("unexpected data from client"))),
+        Err(e) => Err(From::from(e)),
+    }
+}
+
+/// Block until a message comes in on the channel. We wait in slices so we
+/// notice if the client goes away, otherwise we'd keep listening on its behalf
+/// and eat a message nobody's going to get. Returns None if the client hung up.
+fn recv_blocking(stream: &mut UnixStream, channel: &str) -> CResult<Option<Vec<u8>>> {
+    loop {
+        match ::recv_timeout(channel, Duration::from_millis(HANGUP_CHECK_MS)) {
+            Ok(x) => return Ok(Some(x)),
+            Err(CError::Timeout) => {
+                if hung_up(stream)? { return Ok(None); }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Run a single request, returning the response frame. Returns None if the
+/// client hung up while we were waiting on a blocking receive.
+fn handle_request(stream: &mut UnixStream, frame: Frame) -> CResult<Option<Frame>> {
+    let channel = frame.channel.as_str();
+    let res = match frame.op {
+        OP_SEND => {
+            ::send(channel, frame.payload).map(|_| Frame::new(OP_OK, channel, Vec::new()))
+        }
+        OP_RECV => {
+            if frame.payload.len() != 8 {
+                Err(CError::Msg(format!("recv: bad timeout ({} bytes)", frame.payload.len())))
+            } else {
+                let timeout = read_u64(&frame.payload[..]);
+                let msg = if timeout == BLOCK {
+                    match recv_blocking(stream, channel) {
+                        Ok(None) => return Ok(None),
+                        x => x,
+                    }
+                } else if timeout == 0 {
+                    ::recv_nb(channel)
+                } else {
+                    match ::recv_timeout(channel, Duration::from_millis(timeout)) {
+                        Ok(x) => Ok(Some(x)),
+                        Err(CError::Timeout) => Ok(None),
+                        Err(e) => Err(e),
+                    }
+                };
+                msg.map(|x| match x {
+                    Some(x) => Frame::new(OP_MSG, channel, x),
+                    None => Frame::new(OP_EMPTY, channel, Vec::new()),
+                })
+            }
+        }
+        _ => Err(CError::Msg(format!("unknown op: {}", frame.op))),
+    };
+    match res {
+        Ok(x) => Ok(Some(x)),
+        Err(e) => Ok(Some(Frame::new(OP_ERR, channel, Vec::from(format!("{}", e).as_bytes())))),
+    }
+}
+
+/// Stream every message published on a broadcast channel to the client until
+/// it hangs up
+fn stream_subscription(stream: &mut UnixStream, channel: &str) -> CResult<()> {
+    let sub = ::subscribe(channel);
+    write_frame(stream, &Frame::new(OP_OK, channel, Vec::new()))?;
+    loop {
+        // wake up every so often so we notice if the client is gone even if
+        // nothing's being published
+        match sub.recv_timeout(Duration::from_secs(5)) {
+            Ok(msg) => write_frame(stream, &Frame::new(OP_MSG, channel, msg))?,
+            Err(CError::Timeout) => {
+                // the client never sends anything after subscribing, so a
+                // read only returns if they hung up
+                if hung_up(stream)? { return Ok(()); }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Handle a client connection until it hangs up
+fn handle_conn(mut stream: UnixStream) -> CResult<()> {
+    loop {
+        let frame = match read_frame(&mut stream)? {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        if frame.op == OP_SUBSCRIBE {
+            return stream_subscription(&mut stream, frame.channel.as_str());
+        }
+        let res = match handle_request(&mut stream, frame)? {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        match write_frame(&mut stream, &res) {
+            Ok(_) => {}
+            Err(e) => {
+                // put a received message back so it isn't lost along with the
+                // client
+                if res.op == OP_MSG {
+                    match ::send(res.channel.as_str(), res.payload) {
+                        Ok(_) => {}
+                        Err(e) => println!("carrier: unix: could not requeue message on {}: {}", res.channel, e),
+                    }
+                }
+                return Err(e);
+            }
+        }
+    }
+}
+
+/// A running socket server. The socket file is removed when the server is
+/// shut down.
+pub struct Server {
+    path: PathBuf,
+    running: Arc<AtomicBool>,
+    handle: Option<thread::JoinHandle<()>>,
+}
+
+impl Server {
+    /// Where this server is listening
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+
+    /// Stop accepting connections and remove the socket file. Connections that
+    /// are already open are left alone.
+    pub fn shutdown(&mut self) {
+        if !self.running.swap(false, Ordering::SeqCst) { return; }
+        // poke the listener so it notices we're done
+        let _ = UnixStream::connect(&self.path);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+/// Start serving carrier's channels on a Unix socket at the given path. If a
+/// stale socket file is sitting at that path, it's replaced. The socket is
+/// only accessible to the current user.
+pub fn serve<P: AsRef<Path>>(path: P) -> CResult<Server> {
+    let path = PathBuf::from(path.as_ref());
+    if path.exists() {
+        // if someone's listening, don't steal their socket out from under them
+        match UnixStream::connect(&path) {
+            Ok(_) => return Err(CError::Msg(format!("socket {} is already in use", path.display()))),
+            Err(_) => fs::remove_file(&path)?,
+        }
+    }
+    let listener = UnixListener::bind(&path)?;
+    // anyone who can connect gets full access to our channels
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+    let running = Arc::new(AtomicBool::new(true));
+    let running2 = running.clone();
+    let handle = thread::Builder::new().name(String::from("carrier:unix")).spawn(move || {
+        for conn in listener.incoming() {
+            if !running2.load(Ordering::SeqCst) { break; }
+            let stream = match conn {
+                Ok(x) => x,
+                Err(e) => {
+                    println!("carrier: unix: accept error: {}", e);
+                    continue;
+                }
+            };
+            let spawned = thread::Builder::new().name(String::from("carrier:unix:conn")).spawn(move || {
+                match handle_conn(stream) {
+                    Ok(_) => {}
+                    Err(e) => println!("carrier: unix: connection error: {}", e),
+                }
+            });
+            match spawned {
+                Ok(_) => {}
+                Err(e) => println!("carrier: unix: could not start connection thread: {}", e),
+            }
+        }
+    })?;
+    Ok(Server {
+        path: path,
+        running: running,
+        handle: Some(handle),
+    })
+}
+
+/// A connection to a carrier socket server, with the same send/recv interface
+/// as carrier itself
+pub struct Client {
+    stream: UnixStream,
+}
+
+impl Client {
+    /// Connect to a carrier socket server
+    pub fn connect<P: AsRef<Path>>(path: P) -> CResult<Client> {
+        Ok(Client { stream: UnixStream::connect(path)? })
+    }
+
+    /// Send a request and grab the response
+    fn request(&mut self, frame: Frame) -> CResult<Frame> {
+        write_frame(&mut self.stream, &frame)?;
+        match read_frame(&mut self.stream)? {
+            Some(x) => {
+                if x.op == OP_ERR {
+                    Err(CError::Msg(String::from_utf8_lossy(&x.payload[..]).into_owned()))
+                } else {
+                    Ok(x)
+                }
+            }
+            None => Err(From::from(io::Error::new(ErrorKind::UnexpectedEof, "server hung up"))),
+        }
+    }
+
+    /// Receive with the given timeout (see `OP_RECV`)
+    fn recv_any(&mut self, channel: &str, timeout: u64) -> CResult<Option<Vec<u8>>> {
+        let mut payload = Vec::with_capacity(8);
+        write_uint(&mut payload, timeout, 8);
+        let res = self.request(Frame::new(OP_RECV, channel, payload))?;
+        match res.op {
+            OP_MSG => Ok(Some(res.payload)),
+            OP_EMPTY => Ok(None),
+            x => Err(CError::Msg(format!("recv: unexpected op in response: {}", x))),
+        }
+    }
+
+    /// Send a message on a channel
+    pub fn send(&mut self, channel: &str, message: Vec<u8>) -> CResult<()> {
+        self.request(Frame::new(OP_SEND, channel, message))?;
+        Ok(())
+    }
+
+    /// Blocking receive
+    pub fn recv(&mut self, channel: &str) -> CResult<Vec<u8>> {
+        match self.recv_any(channel, BLOCK)? {
+            Some(x) => Ok(x),
+            None => Err(CError::Msg(String::from("recv: got empty response to a blocking receive"))),
+        }
+    }
+
+    /// Non-blocking receive
+    pub fn recv_nb(&mut self, channel: &str) -> CResult<Option<Vec<u8>>> {
+        self.recv_any(channel, 0)
+    }
+
+    /// Blocking receive that gives up after `timeout`, returning a
+    /// `CError::Timeout` error if no message came in.
+    pub fn recv_timeout(&mut self, channel: &str, timeout: Duration) -> CResult<Vec<u8>> {
+        // a zero timeout would turn into a non-blocking receive, which is
+        // close enough
+        let ms = (timeout.as_secs() * 1000) + (timeout.subsec_nanos() / 1_000_000) as u64;
+        let ms = if ms == BLOCK { BLOCK - 1 } else { ms };
+        match self.recv_any(channel, ms)? {
+            Some(x) => Ok(x),
+            None => Err(CError::Timeout),
+        }
+    }
+
+    /// Subscribe to a broadcast channel, turning this connection into a
+    /// stream of that channel's messages
+    pub fn subscribe(mut self, channel: &str) -> CResult<RemoteSubscription> {
+        self.request(Frame::new(OP_SUBSCRIBE, channel, Vec::new()))?;
+        Ok(RemoteSubscription {
+            stream: self.stream,
+            channel: String::from(channel),
+        })
+    }
+}
+
+/// A subscription to a broadcast channel over a socket. Dropping it closes the
+/// connection, which ends the subscription.
+pub struct RemoteSubscription {
+    stream: UnixStream,
+    channel: String,
+}
+
+impl RemoteSubscription {
+    /// The broadcast channel we're subscribed to
+    pub fn channel(&self) -> &str {
+        self.channel.as_str()
+    }
+
+    /// Blocking receive
+    pub fn recv(&mut self) -> CResult<Vec<u8>> {
+        match read_frame(&mut self.stream)? {
+            Some(x) => Ok(x.payload),
+            None => Err(From::from(io::Error::new(ErrorKind::UnexpectedEof, "server hung up"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::std::env;
+    use ::std::io::Cursor;
+
+    fn socket_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("carrier-test-{}.sock", name))
+    }
+
+    #[test]
+    fn frames_roundtrip() {
+        let frame = Frame::new(OP_SEND, "turtl-events", Vec::from("hello".as_bytes()));
+        let mut bytes = Vec::new();
+        write_frame(&mut bytes, &frame).unwrap();
+        assert_eq!(&bytes[0..4], &[0, 0, 0, 20]);
+        let mut cursor = Cursor::new(bytes);
+        assert_eq!(read_frame(&mut cursor).unwrap(), Some(frame));
+        assert_eq!(read_frame(&mut cursor).unwrap(), None);
+
+        let mut cursor = Cursor::new(vec![0, 0, 0, 1, 1]);
+        assert!(read_frame(&mut cursor).is_err());
+    }
+
+    #[test]
+    fn serves_channels() {
+        let path = socket_path("serve");
+        let mut server = serve(&path).unwrap();
+        assert!(serve(&path).is_err());
+        let mut client = Client::connect(&path).unwrap();
+
+        client.send("unix:in", Vec::from("ping".as_bytes())).unwrap();
+        assert_eq!(::recv("unix:in").unwrap(), Vec::from("ping".as_bytes()));
+        ::send("unix:out", Vec::from("pong".as_bytes())).unwrap();
+        assert_eq!(client.recv("unix:out").unwrap(), Vec::from("pong".as_bytes()));
+        assert_eq!(client.recv_nb("unix:out").unwrap(), None);
+        match client.recv_timeout("unix:out", Duration::from_millis(10)) {
+            Err(CError::Timeout) => {}
+            x => panic!("expected timeout, got {:?}", x),
+        }
+
+        let sub = Client::connect(&path).unwrap();
+        let mut sub = sub.subscribe("unix:broadcast").unwrap();
+        assert_eq!(sub.channel(), "unix:broadcast");
+        assert_eq!(::publish("unix:broadcast", Vec::from("to all".as_bytes())), 1);
+        assert_eq!(sub.recv().unwrap(), Vec::from("to all".as_bytes()));
+
+        server.shutdown();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn restricts_socket_permissions() {
+        let path = socket_path("perms");
+        let _server = serve(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    /// Wait for the number of receivers blocked on a channel to settle at
+    /// `count`. The server stops listening for a moment every time it checks on
+    /// its client, so a count has to hold for a few reads in a row.
+    fn wait_for_listeners(channel: &str, count: usize) {
+        let mut settled = 0;
+        for _ in 0..2000 {
+            if ::stats(channel).listeners == count { settled += 1; } else { settled = 0; }
+            if settled >= 10 { return; }
+            thread::sleep(Duration::from_millis(5));
+        }
+        panic!("{} never settled at {} listener(s)", channel, count);
+    }
+
+    #[test]
+    fn stops_receiving_for_clients_that_hang_up() {
+        let path = socket_path("hangup");
+        let _server = serve(&path).unwrap();
+        {
+            let mut client = Client::connect(&path).unwrap();
+            let mut payload = Vec::with_capacity(8);
+            write_uint(&mut payload, BLOCK, 8);
+            write_frame(&mut client.stream, &Frame::new(OP_RECV, "unix:hangup", payload)).unwrap();
+            wait_for_listeners("unix:hangup", 1);
+        }
+        // wait for the server to notice the client is gone
+        wait_for_listeners("unix:hangup", 0);
+        ::send("unix:hangup", Vec::from("still here".as_bytes())).unwrap();
+        assert_eq!(::recv_timeout("unix:hangup", Duration::from_secs(5)).unwrap(), Vec::from("still here".as_bytes()));
+    }
+}
@@ -0,0 +1,109 @@
+//! Parses our command line options
+
+use ::std::env;
+
+/// How to use this thing
+pub const USAGE: &'static str = "\
+Usage: turtl_client [options]
+
+Options:
+  --script <file>   run the commands in <file> (use - for stdin) instead of
+                    starting the interactive prompt
+  --json            print one JSON object per line instead of friendly output
+  --keep-going      in a script, keep running after a command fails
+  --timeout <secs>  how long to wait for a response or event (default 300)
+  --history <file>  where the prompt keeps its history (default
+                    <data_folder>/client_history.txt)
+  -h, --help        show this message
+
+Scripts are made up of the same commands you'd type at the prompt, one per
+line. Blank lines and lines starting with # are skipped. On top of core's
+commands, you can use:
+
+  wait-event <event> [secs]   wait until the core sends the given event
+  sleep <ms>                  wait a bit
+  help                        list the shortcuts below
+  quit                        stop
+
+When a command fails, the exit code is the error's code (see the
+`app:api-schema` command), or 1 if it doesn't have one.
+";
+
+/// Our command line options
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    /// Run this script (- for stdin) instead of starting the prompt
+    pub script: Option<String>,
+    /// Print JSON instead of friendly output
+    pub json: bool,
+    /// Keep running a script after a command fails
+    pub keep_going: bool,
+    /// Seconds to wait for responses/events
+    pub timeout: u64,
+    /// Where to keep our prompt history
+    pub history: Option<String>,
+    /// Show the usage and quit
+    pub help: bool,
+}
+
+impl Options {
+    /// Parse options from a list of arguments (not including the program name)
+    pub fn parse(args: Vec<String>) -> Result<Options, String> {
+        let mut opts = Options {
+            script: None,
+            json: false,
+            keep_going: false,
+            timeout: 300,
+            history: None,
+            help: false,
+        };
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_ref() {
+                "--script" => opts.script = Some(args.next().ok_or("--script needs a file")?),
+                "--json" => opts.json = true,
+                "--keep-going" => opts.keep_going = true,
+                "--timeout" => {
+                    let secs = args.next().ok_or("--timeout needs a number of seconds")?;
+                    opts.timeout = secs.parse().map_err(|_| format!("bad --timeout: {}", secs))?;
+                }
+                "--history" => opts.history = Some(args.next().ok_or("--history needs a file")?),
+                "-h" | "--help" => opts.help = true,
+                _ => return Err(format!("unknown option: {}", arg)),
+            }
+        }
+        Ok(opts)
+    }
+
+    /// Parse our options from the command line
+    pub fn from_env() -> Result<Options, String> {
+        Options::parse(env::args().skip(1).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|x| String::from(*x)).collect()
+    }
+
+    #[test]
+    fn parses_options() {
+        let opts = Options::parse(args(&[])).unwrap();
+        assert_eq!(opts.script, None);
+        assert!(!opts.json);
+        assert_eq!(opts.timeout, 300);
+
+        let opts = Options::parse(args(&["--script", "-", "--json", "--timeout", "10", "--keep-going"])).unwrap();
+        assert_eq!(opts.script, Some(String::from("-")));
+        assert!(opts.json);
+        assert!(opts.keep_going);
+        assert_eq!(opts.timeout, 10);
+
+        assert!(Options::parse(args(&["--script"])).is_err());
+        assert!(Options::parse(args(&["--timeout", "soon"])).is_err());
+        assert!(Options::parse(args(&["--lol"])).is_err());
+    }
+}
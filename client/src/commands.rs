@@ -0,0 +1,536 @@
+//! Friendlier versions of the core's commands, so working with notes, boards,
+//! and spaces doesn't mean hand-writing `profile:sync:model` JSON:
+//!
+//!     space list
+//!     board add "Recipes" --space <space_id>
+//!     note add --title "Pancakes" --text "flour, eggs" --board <board_id>
+//!     note list --board <board_id>
+//!     attach-file <note_id> ./pancakes.jpg
+//!
+//! Everything here is built on the core's regular commands (`profile:load`,
+//! `profile:sync:model`, `profile:find-notes`, etc).
+
+use ::std::collections::HashMap;
+use ::std::fs::File;
+use ::std::io::{Read, Write};
+use ::std::path::Path;
+
+use ::base64;
+use ::jedi::{self, Value};
+
+use ::runner::{Runner, Failure};
+use ::table;
+
+/// Describes our commands for `help`
+pub const USAGE: &'static str = "\
+  space list
+  space show <space_id>
+  space add <title> [--color <color>]
+  space edit <space_id> [--title <title>] [--color <color>]
+  space rm <space_id>
+  board list [--space <space_id>]
+  board show <board_id>
+  board add <title> [--space <space_id>]
+  board edit <board_id> [--title <title>]
+  board rm <board_id>
+  note list [--space <space_id>] [--board <board_id>] [--tags <a,b>] [--page <n>]
+  note show <note_id>
+  note add [--space <space_id>] [--board <board_id>] [--type <type>]
+           [--title <title>] [--text <text>] [--url <url>] [--tags <a,b>]
+           [--file <path>]
+  note edit <note_id> [same options as add]
+  note rm <note_id>
+  search <text> [--space <space_id>] [--board <board_id>] [--tags <a,b>]
+  export <file>
+  import <file> [--mode restore|replace|full]
+  attach-file <note_id> <file>";
+
+/// Positional args and `--flag value` options for a command
+#[derive(Debug)]
+pub struct Params {
+    positional: Vec<String>,
+    flags: HashMap<String, String>,
+}
+
+impl Params {
+    /// Split args into positionals and flags
+    pub fn parse(args: &[String]) -> Result<Params, Failure> {
+        let mut positional = Vec::new();
+        let mut flags = HashMap::new();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            if arg.starts_with("--") && arg.len() > 2 {
+                let name = String::from(&arg[2..]);
+                match iter.next() {
+                    Some(val) => { flags.insert(name, val.clone()); }
+                    None => return Err(Failure::new("bad_value", format!("--{} needs a value", name))),
+                }
+            } else {
+                positional.push(arg.clone());
+            }
+        }
+        Ok(Params {
+            positional: positional,
+            flags: flags,
+        })
+    }
+
+    /// Grab a positional arg, erroring if it's missing
+    pub fn arg(&self, idx: usize, usage: &str) -> Result<&String, Failure> {
+        self.positional.get(idx)
+            .ok_or_else(|| Failure::new("missing_field", format!("usage: {}", usage)))
+    }
+
+    /// Grab a flag's value
+    pub fn flag(&self, name: &str) -> Option<&String> {
+        self.flags.get(name)
+    }
+
+    /// Make sure we only got flags we know about
+    pub fn allow(&self, names: &[&str]) -> Result<(), Failure> {
+        for name in self.flags.keys() {
+            if !names.contains(&name.as_str()) {
+                return Err(Failure::new("bad_value", format!("unknown option: --{}", name)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Split a comma-separated list of tags
+pub fn split_tags(tags: &str) -> Vec<String> {
+    tags.split(',')
+        .map(|x| String::from(x.trim()))
+        .filter(|x| x.len() > 0)
+        .collect()
+}
+
+/// Guess a file's mime type from its extension
+pub fn mime_type(path: &Path) -> &'static str {
+    let ext = path.extension()
+        .and_then(|x| x.to_str())
+        .map(|x| x.to_lowercase())
+        .unwrap_or(String::new());
+    match ext.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "pdf" => "application/pdf",
+        "txt" | "md" => "text/plain",
+        "html" | "htm" => "text/html",
+        "json" => "application/json",
+        "zip" => "application/zip",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Read a local file into the `file` object a note expects
+pub fn file_val(path: &Path) -> Result<Value, Failure> {
+    let io_err = |e| Failure::new("io_error", format!("problem reading {}: {}", path.display(), e));
+    let mut contents = Vec::new();
+    File::open(path).map_err(&io_err)?
+        .read_to_end(&mut contents).map_err(&io_err)?;
+    let name = path.file_name()
+        .and_then(|x| x.to_str())
+        .unwrap_or("file");
+    Ok(json!({
+        "name": name,
+        "type": mime_type(path),
+        "size": contents.len(),
+        "filedata": {
+            "data": base64::encode(&contents),
+        },
+    }))
+}
+
+/// Apply `note add`/`note edit` options to a note's data
+pub fn apply_note_flags(note: &mut Value, params: &Params) -> Result<(), Failure> {
+    let json_err = |e: jedi::JSONError| Failure::new("json_error", format!("{}", e));
+    for &(flag, field) in [("title", "title"), ("text", "text"), ("url", "url"), ("type", "type"), ("board", "board_id"), ("space", "space_id")].iter() {
+        if let Some(val) = params.flag(flag) {
+            jedi::set(&[field], note, val).map_err(&json_err)?;
+        }
+    }
+    if let Some(tags) = params.flag("tags") {
+        jedi::set(&["tags"], note, &split_tags(tags)).map_err(&json_err)?;
+    }
+    if let Some(path) = params.flag("file") {
+        jedi::set(&["file"], note, &file_val(Path::new(path))?).map_err(&json_err)?;
+    }
+    Ok(())
+}
+
+/// Grab the profile (user, spaces, boards)
+fn profile(runner: &mut Runner) -> Result<Value, Failure> {
+    runner.call("profile:load", vec![])
+}
+
+/// Grab a list of items (spaces, boards) from the profile
+fn items(profile: &Value, kind: &str) -> Vec<Value> {
+    jedi::get_opt::<Vec<Value>>(&[kind], profile).unwrap_or(Vec::new())
+}
+
+/// Find an item (space, board) in the profile by id
+fn find(profile: &Value, kind: &str, id: &str) -> Result<Value, Failure> {
+    items(profile, &format!("{}s", kind)).into_iter()
+        .find(|x| jedi::get_opt::<String>(&["id"], x).as_ref().map(|x| x.as_str()) == Some(id))
+        .ok_or_else(|| Failure::new("not_found", format!("couldn't find {} {}", kind, id)))
+}
+
+/// Use the --space flag, or fall back on the user's first space
+fn space_id(profile: &Value, params: &Params) -> Result<String, Failure> {
+    if let Some(id) = params.flag("space") {
+        return Ok(id.clone());
+    }
+    items(profile, "spaces").get(0)
+        .and_then(|x| jedi::get_opt::<String>(&["id"], x))
+        .ok_or_else(|| Failure::new("missing_data", String::from("you don't have any spaces, pass --space")))
+}
+
+fn user_id(profile: &Value) -> Result<String, Failure> {
+    jedi::get_opt::<String>(&["user", "id"], profile)
+        .ok_or_else(|| Failure::new("missing_data", String::from("not logged in")))
+}
+
+/// Load a note by id
+fn note(runner: &mut Runner, id: &str) -> Result<Value, Failure> {
+    let notes = runner.call("profile:get-notes", vec![json!([id])])?;
+    jedi::get_opt::<Value>(&["0"], &notes)
+        .ok_or_else(|| Failure::new("not_found", format!("couldn't find note {}", id)))
+}
+
+/// Save a model via `profile:sync:model`
+fn sync(runner: &mut Runner, action: &str, ty: &str, data: Value) -> Result<Value, Failure> {
+    runner.call("profile:sync:model", vec![json!(action), json!(ty), data])
+}
+
+fn set(item: &mut Value, field: &str, val: &str) -> Result<(), Failure> {
+    jedi::set(&[field], item, &val)
+        .map_err(|e| Failure::new("json_error", format!("{}", e)))
+}
+
+/// Print a list of items as a table (or a JSON array)
+fn print_list(runner: &Runner, list: Vec<Value>, columns: &[(&str, &[&str])]) {
+    let headers = columns.iter().map(|&(name, _)| name).collect::<Vec<_>>();
+    let rows = list.iter()
+        .map(|item| columns.iter().map(|&(_, path)| table::cell(item, path)).collect())
+        .collect::<Vec<_>>();
+    runner.print(table::render(&headers[..], &rows), Value::Array(list));
+}
+
+/// Print a single item
+fn print_item(runner: &Runner, item: Value, fields: &[(&str, &[&str])]) {
+    runner.print(table::render_item(&item, fields), item);
+}
+
+/// Print a short confirmation (or the item, for JSON)
+fn print_done(runner: &Runner, what: String, item: Value) {
+    runner.print(what, item);
+}
+
+static SPACE_COLUMNS: &'static [(&'static str, &'static [&'static str])] = &[
+    ("id", &["id"]),
+    ("title", &["title"]),
+    ("color", &["color"]),
+    ("members", &["members"]),
+];
+
+static BOARD_COLUMNS: &'static [(&'static str, &'static [&'static str])] = &[
+    ("id", &["id"]),
+    ("title", &["title"]),
+    ("space", &["space_id"]),
+];
+
+static NOTE_COLUMNS: &'static [(&'static str, &'static [&'static str])] = &[
+    ("id", &["id"]),
+    ("type", &["type"]),
+    ("title", &["title"]),
+    ("tags", &["tags"]),
+    ("board", &["board_id"]),
+];
+
+static NOTE_FIELDS: &'static [(&'static str, &'static [&'static str])] = &[
+    ("id", &["id"]),
+    ("type", &["type"]),
+    ("title", &["title"]),
+    ("space", &["space_id"]),
+    ("board", &["board_id"]),
+    ("tags", &["tags"]),
+    ("url", &["url"]),
+    ("file", &["file", "name"]),
+    ("text", &["text"]),
+];
+
+fn space_cmd(runner: &mut Runner, params: &Params) -> Result<(), Failure> {
+    let sub = params.arg(0, "space list|show|add|edit|rm")?.clone();
+    match sub.as_str() {
+        "list" => {
+            params.allow(&[])?;
+            let profile = profile(runner)?;
+            let mut spaces = items(&profile, "spaces");
+            // a member count is more useful in a table than the members
+            for space in &mut spaces {
+                let count = jedi::get_opt::<Vec<Value>>(&["members"], space).map(|x| x.len()).unwrap_or(0);
+                let _ = jedi::set(&["members"], space, &count);
+            }
+            print_list(runner, spaces, SPACE_COLUMNS);
+        }
+        "show" => {
+            let id = params.arg(1, "space show <space_id>")?;
+            let space = find(&profile(runner)?, "space", id)?;
+            print_item(runner, space, &[("id", &["id"]), ("title", &["title"]), ("color", &["color"]), ("owner", &["user_id"])]);
+        }
+        "add" => {
+            params.allow(&["color"])?;
+            let title = params.arg(1, "space add <title> [--color <color>]")?;
+            let profile = profile(runner)?;
+            let mut space = json!({"user_id": user_id(&profile)?, "title": title});
+            if let Some(color) = params.flag("color") { set(&mut space, "color", color)?; }
+            let saved = sync(runner, "add", "space", space)?;
+            print_done(runner, format!("added space {}", table::cell(&saved, &["id"])), saved);
+        }
+        "edit" => {
+            params.allow(&["title", "color"])?;
+            let id = params.arg(1, "space edit <space_id> [--title <title>] [--color <color>]")?;
+            let mut space = find(&profile(runner)?, "space", id)?;
+            if let Some(title) = params.flag("title") { set(&mut space, "title", title)?; }
+            if let Some(color) = params.flag("color") { set(&mut space, "color", color)?; }
+            let saved = sync(runner, "edit", "space", space)?;
+            print_done(runner, format!("saved space {}", id), saved);
+        }
+        "rm" => {
+            let id = params.arg(1, "space rm <space_id>")?;
+            sync(runner, "delete", "space", json!({"id": id}))?;
+            print_done(runner, format!("removed space {}", id), json!({"id": id}));
+        }
+        _ => return Err(Failure::new("bad_value", format!("unknown space command: {}", sub))),
+    }
+    Ok(())
+}
+
+fn board_cmd(runner: &mut Runner, params: &Params) -> Result<(), Failure> {
+    let sub = params.arg(0, "board list|show|add|edit|rm")?.clone();
+    match sub.as_str() {
+        "list" => {
+            params.allow(&["space"])?;
+            let boards = items(&profile(runner)?, "boards").into_iter()
+                .filter(|x| match params.flag("space") {
+                    Some(space_id) => jedi::get_opt::<String>(&["space_id"], x).as_ref() == Some(space_id),
+                    None => true,
+                })
+                .collect::<Vec<_>>();
+            print_list(runner, boards, BOARD_COLUMNS);
+        }
+        "show" => {
+            let id = params.arg(1, "board show <board_id>")?;
+            let board = find(&profile(runner)?, "board", id)?;
+            print_item(runner, board, BOARD_COLUMNS);
+        }
+        "add" => {
+            params.allow(&["space"])?;
+            let title = params.arg(1, "board add <title> [--space <space_id>]")?;
+            let profile = profile(runner)?;
+            let board = json!({
+                "user_id": user_id(&profile)?,
+                "space_id": space_id(&profile, params)?,
+                "title": title,
+            });
+            let saved = sync(runner, "add", "board", board)?;
+            print_done(runner, format!("added board {}", table::cell(&saved, &["id"])), saved);
+        }
+        "edit" => {
+            params.allow(&["title"])?;
+            let id = params.arg(1, "board edit <board_id> [--title <title>]")?;
+            let mut board = find(&profile(runner)?, "board", id)?;
+            if let Some(title) = params.flag("title") { set(&mut board, "title", title)?; }
+            let saved = sync(runner, "edit", "board", board)?;
+            print_done(runner, format!("saved board {}", id), saved);
+        }
+        "rm" => {
+            let id = params.arg(1, "board rm <board_id>")?;
+            sync(runner, "delete", "board", json!({"id": id}))?;
+            print_done(runner, format!("removed board {}", id), json!({"id": id}));
+        }
+        _ => return Err(Failure::new("bad_value", format!("unknown board command: {}", sub))),
+    }
+    Ok(())
+}
+
+/// Run a `profile:find-notes` query and print the notes we get back
+fn find_notes(runner: &mut Runner, params: &Params, text: Option<&String>) -> Result<(), Failure> {
+    params.allow(&["space", "board", "tags", "page"])?;
+    let profile = profile(runner)?;
+    let mut query = json!({
+        "space_id": space_id(&profile, params)?,
+        "text": text,
+    });
+    if let Some(board) = params.flag("board") {
+        let _ = jedi::set(&["boards"], &mut query, &vec![board]);
+    }
+    if let Some(tags) = params.flag("tags") {
+        let _ = jedi::set(&["tags"], &mut query, &split_tags(tags));
+    }
+    if let Some(page) = params.flag("page") {
+        let page: i32 = page.parse()
+            .map_err(|_| Failure::new("bad_value", format!("bad page: {}", page)))?;
+        let _ = jedi::set(&["page"], &mut query, &page);
+    }
+    let res = runner.call("profile:find-notes", vec![query])?;
+    let notes = jedi::get_opt::<Vec<Value>>(&["notes"], &res).unwrap_or(Vec::new());
+    print_list(runner, notes, NOTE_COLUMNS);
+    if !runner.json() {
+        println!("({} total)", jedi::get_opt::<i64>(&["total"], &res).unwrap_or(0));
+    }
+    Ok(())
+}
+
+fn note_cmd(runner: &mut Runner, params: &Params) -> Result<(), Failure> {
+    let sub = params.arg(0, "note list|show|add|edit|rm")?.clone();
+    let note_flags = ["space", "board", "type", "title", "text", "url", "tags", "file"];
+    match sub.as_str() {
+        "list" => find_notes(runner, params, None)?,
+        "show" => {
+            let id = params.arg(1, "note show <note_id>")?;
+            let note = note(runner, id)?;
+            print_item(runner, note, NOTE_FIELDS);
+        }
+        "add" => {
+            params.allow(&note_flags)?;
+            let profile = profile(runner)?;
+            let ty = if params.flag("file").is_some() { "file" } else { "text" };
+            let mut note = json!({
+                "user_id": user_id(&profile)?,
+                "space_id": space_id(&profile, params)?,
+                "type": ty,
+            });
+            apply_note_flags(&mut note, params)?;
+            let saved = sync(runner, "add", "note", note)?;
+            print_done(runner, format!("added note {}", table::cell(&saved, &["id"])), saved);
+        }
+        "edit" => {
+            params.allow(&note_flags)?;
+            let id = params.arg(1, "note edit <note_id> [options]")?;
+            let mut note = note(runner, id)?;
+            apply_note_flags(&mut note, params)?;
+            let saved = sync(runner, "edit", "note", note)?;
+            print_done(runner, format!("saved note {}", id), saved);
+        }
+        "rm" => {
+            let id = params.arg(1, "note rm <note_id>")?;
+            sync(runner, "delete", "note", json!({"id": id}))?;
+            print_done(runner, format!("removed note {}", id), json!({"id": id}));
+        }
+        _ => return Err(Failure::new("bad_value", format!("unknown note command: {}", sub))),
+    }
+    Ok(())
+}
+
+fn export_cmd(runner: &mut Runner, params: &Params) -> Result<(), Failure> {
+    let path = params.arg(0, "export <file>")?;
+    let export = runner.call("profile:export", vec![])?;
+    let contents = jedi::stringify(&export)
+        .map_err(|e| Failure::new("json_error", format!("{}", e)))?;
+    let io_err = |e| Failure::new("io_error", format!("problem writing {}: {}", path, e));
+    File::create(path).map_err(&io_err)?
+        .write_all(contents.as_bytes()).map_err(&io_err)?;
+    print_done(runner, format!("exported profile to {}", path), json!({"file": path}));
+    Ok(())
+}
+
+fn import_cmd(runner: &mut Runner, params: &Params) -> Result<(), Failure> {
+    params.allow(&["mode"])?;
+    let path = params.arg(0, "import <file> [--mode restore|replace|full]")?;
+    let mode = params.flag("mode").map(|x| x.as_str()).unwrap_or("restore");
+    let io_err = |e| Failure::new("io_error", format!("problem reading {}: {}", path, e));
+    let mut contents = String::new();
+    File::open(path).map_err(&io_err)?
+        .read_to_string(&mut contents).map_err(&io_err)?;
+    let export: Value = jedi::parse(&contents)
+        .map_err(|e| Failure::new("json_error", format!("{} isn't a valid export: {}", path, e)))?;
+    let res = runner.call("profile:import", vec![json!(mode), export])?;
+    let count = jedi::get_opt::<Vec<Value>>(&["actions"], &res).map(|x| x.len()).unwrap_or(0);
+    print_done(runner, format!("imported {} items from {}", count, path), res);
+    Ok(())
+}
+
+fn attach_cmd(runner: &mut Runner, params: &Params) -> Result<(), Failure> {
+    let usage = "attach-file <note_id> <file>";
+    let id = params.arg(0, usage)?;
+    let path = params.arg(1, usage)?;
+    let mut note = note(runner, id)?;
+    let file = file_val(Path::new(path))?;
+    jedi::set(&["file"], &mut note, &file)
+        .map_err(|e| Failure::new("json_error", format!("{}", e)))?;
+    let saved = sync(runner, "edit", "note", note)?;
+    print_done(runner, format!("attached {} to note {}", path, id), saved);
+    Ok(())
+}
+
+/// Run one of our commands, if `cmd` is one
+pub fn run(runner: &mut Runner, cmd: &str, args: &[String]) -> Option<Result<(), Failure>> {
+    let handler: fn(&mut Runner, &Params) -> Result<(), Failure> = match cmd {
+        "space" => space_cmd,
+        "board" => board_cmd,
+        "note" => note_cmd,
+        "search" => |runner, params| {
+            let text = params.arg(0, "search <text> [options]")?.clone();
+            find_notes(runner, params, Some(&text))
+        },
+        "export" => export_cmd,
+        "import" => import_cmd,
+        "attach-file" => attach_cmd,
+        _ => return None,
+    };
+    Some(Params::parse(args).and_then(|params| handler(runner, &params)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::std::env;
+    use ::std::fs;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|x| String::from(*x)).collect()
+    }
+
+    #[test]
+    fn parses_params() {
+        let params = Params::parse(&args(&["add", "--title", "get milk", "--tags", "a, b,,c"])).unwrap();
+        assert_eq!(params.arg(0, "").unwrap(), "add");
+        assert!(params.arg(1, "").is_err());
+        assert_eq!(params.flag("title").unwrap(), "get milk");
+        assert_eq!(split_tags(params.flag("tags").unwrap()), vec!["a", "b", "c"]);
+        assert!(params.allow(&["title", "tags"]).is_ok());
+        assert!(params.allow(&["title"]).is_err());
+        assert!(Params::parse(&args(&["add", "--title"])).is_err());
+    }
+
+    #[test]
+    fn builds_notes() {
+        let path = env::temp_dir().join("turtl-client-attach.json");
+        fs::File::create(&path).unwrap().write_all(b"{\"name\":\"slappy\"}").unwrap();
+        let params = Params::parse(&args(&["add", "--title", "slappy", "--board", "1234", "--tags", "dogs", "--file", path.to_str().unwrap()])).unwrap();
+        let mut note = json!({"type": "file"});
+        apply_note_flags(&mut note, &params).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(jedi::get::<String>(&["title"], &note).unwrap(), "slappy");
+        assert_eq!(jedi::get::<String>(&["board_id"], &note).unwrap(), "1234");
+        assert_eq!(jedi::get::<Vec<String>>(&["tags"], &note).unwrap(), vec!["dogs"]);
+        assert_eq!(jedi::get::<String>(&["file", "name"], &note).unwrap(), "turtl-client-attach.json");
+        assert_eq!(jedi::get::<String>(&["file", "type"], &note).unwrap(), "application/json");
+        assert_eq!(jedi::get::<u64>(&["file", "size"], &note).unwrap(), 17);
+        assert_eq!(jedi::get::<String>(&["file", "filedata", "data"], &note).unwrap(), "eyJuYW1lIjoic2xhcHB5In0=");
+
+        let params = Params::parse(&args(&["add", "--file", "/this/does/not/exist.png"])).unwrap();
+        assert_eq!(apply_note_flags(&mut note, &params).unwrap_err().code, 15);
+    }
+
+    #[test]
+    fn guesses_mime_types() {
+        assert_eq!(mime_type(Path::new("cat.JPG")), "image/jpeg");
+        assert_eq!(mime_type(Path::new("notes.txt")), "text/plain");
+        assert_eq!(mime_type(Path::new("mystery")), "application/octet-stream");
+    }
+}
@@ -1,109 +1,162 @@
+extern crate base64;
+extern crate config;
 extern crate jedi;
+#[macro_use]
+extern crate lazy_static;
 extern crate regex;
 extern crate rustyline;
+#[macro_use]
+extern crate serde_json;
 extern crate turtl_core;
 
+mod args;
+mod commands;
+mod runner;
+mod table;
+
 use ::std::env;
-use ::std::thread;
+use ::std::fs::File;
+use ::std::io::{self, BufRead, BufReader};
+use ::std::path::PathBuf;
+use ::std::process;
 use ::std::time::Duration;
-use jedi::Value;
-use regex::Regex;
-use rustyline::Editor;
-use rustyline::error::ReadlineError;
-use turtl_core::error::TResult;
+use ::rustyline::Editor;
+use ::rustyline::error::ReadlineError;
+use ::args::{Options, USAGE};
+use ::runner::{Runner, Outcome};
+
+/// Grab the exit code for an error type
+fn error_code_num(name: &str) -> i32 {
+    turtl_core::error::error_code(name).num as i32
+}
 
-pub fn sleep(millis: u64) {
-    thread::sleep(Duration::from_millis(millis));
+/// Log out and shut the core down so we can join its thread
+fn exit(runner: &mut Runner) {
+    let _ = runner.call("user:logout", vec![jedi::Value::Bool(false)]);
+    let _ = runner.call("app:shutdown", vec![]);
 }
 
-fn exit() {
-    turtl_core::send(String::from(r#"["0","sync:shutdown",false]"#)).unwrap();
-    turtl_core::send(String::from(r#"["0","user:logout",false]"#)).unwrap();
+/// Figure out where the prompt history goes. Returns None if we're running in
+/// memory and weren't told where to put it.
+fn history_path(opts: &Options) -> Option<PathBuf> {
+    if let Some(ref path) = opts.history {
+        return Some(PathBuf::from(path));
+    }
+    match config::get::<String>(&["data_folder"]) {
+        Ok(ref x) if x != ":memory:" => Some(PathBuf::from(x).join("client_history.txt")),
+        _ => None,
+    }
 }
 
-fn repl() -> TResult<()> {
-    let re = Regex::new(r#"'.+?'|".+?"|[^ ]+"#).unwrap();
-    let mut req_id = 1;
+/// Run commands from a file (or stdin) until we run out or one fails. Returns
+/// our exit code.
+fn run_script(runner: &mut Runner, opts: &Options, script: &str) -> i32 {
+    let reader: Box<BufRead> = if script == "-" {
+        Box::new(BufReader::new(io::stdin()))
+    } else {
+        match File::open(script) {
+            Ok(x) => Box::new(BufReader::new(x)),
+            Err(e) => {
+                eprintln!("could not open script {}: {}", script, e);
+                return error_code_num("io_error");
+            }
+        }
+    };
+    let mut code = 0;
+    for line in reader.lines() {
+        let line = match line {
+            Ok(x) => x,
+            Err(e) => {
+                eprintln!("problem reading script: {}", e);
+                return error_code_num("io_error");
+            }
+        };
+        match runner.run_line(line.as_str()) {
+            Ok(Outcome::Continue) => {}
+            Ok(Outcome::Quit) => break,
+            Err(f) => {
+                code = f.code;
+                if !opts.keep_going { break; }
+            }
+        }
+    }
+    code
+}
+
+/// Run our interactive prompt
+fn repl(runner: &mut Runner, opts: &Options) {
     let mut rl = Editor::<()>::new();
-    // TODO: Find a good path for history
-    //rl.load_history("history.txt")?;
+    let history = history_path(opts);
+    if let Some(ref path) = history {
+        // no history yet is fine
+        let _ = rl.load_history(path);
+    }
 
+    println!("");
+    println!("");
+    println!("Welcome to the Turtl Client.");
+    println!("");
     loop {
-        let req_str = format!("{}", req_id);
-        let readline = rl.readline(">> ");
-
-        match readline {
+        match rl.readline(">> ") {
             Ok(line) => {
-                if line.is_empty() {
-                    continue;
-                }
-
+                if line.trim().is_empty() { continue; }
                 rl.add_history_entry(&line);
-
-                let mut parts: Vec<String> = re.find_iter(&line)
-                    .map(|x| String::from(x.as_str().trim()
-                        .trim_matches('"').trim_matches('\'')))
-                    .collect::<Vec<_>>();
-
-                if parts.len() == 0 {
-                    continue;
-                }
-
-                let cmd = parts.remove(0);
-
-                // I GUESS I'll let you exit
-                if cmd == "quit" || cmd == "q" {
-                    exit();
-                    break;
+                match runner.run_line(line.as_str()) {
+                    Ok(Outcome::Quit) => break,
+                    // failures are printed by the runner, keep going
+                    _ => {}
                 }
-
-                let mut msg_parts: Vec<Value> = vec![Value::String(req_str.clone()), Value::String(cmd)];
-                let mut args: Vec<Value> = parts.into_iter()
-                    .map(|x| {
-                        match jedi::parse::<Value>(&x) {
-                            Ok(val) => val,
-                            Err(_) => Value::String(format!("{}", x)),
-                        }
-                    })
-                .collect::<Vec<_>>();
-                msg_parts.append(&mut args);
-
-                let msg = jedi::stringify(&msg_parts)?;
-                turtl_core::send(msg).unwrap();
-                let response = turtl_core::recv(None).unwrap();
-                println!("response: {}", response);
-                req_id += 1;
-            },
-            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => {
-                exit();
-                break;
-            },
+            }
+            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => break,
             Err(err) => {
                 eprintln!("Error: {:?}", err);
-                exit();
                 break;
             }
         }
     }
-    Ok(())
+
+    if let Some(ref path) = history {
+        if let Err(e) = rl.save_history(path) {
+            eprintln!("could not save history to {}: {}", path.display(), e);
+        }
+    }
 }
 
 fn main() {
+    let opts = match Options::from_env() {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            process::exit(error_code_num("bad_value"));
+        }
+    };
+    if opts.help {
+        println!("{}\nShortcuts for working with your profile:\n\n{}", USAGE, commands::USAGE);
+        return;
+    }
+
     if env::var("TURTL_CONFIG_FILE").is_err() {
         env::set_var("TURTL_CONFIG_FILE", "../config.yaml");
     }
-    let handle = turtl_core::start(String::from(r#"{"messaging":{"reqres_append_mid":false}}"#));
-
-    sleep(1000);
-    println!("");
-    println!("");
-    println!("Welcome to the Turtl Client.");
-    println!("");
-    match repl() {
-        Ok(_) => {},
-        Err(err) => println!("turtl-client::repl() -- {}", err),
+    // bound our events channel: a script that never waits on events shouldn't
+    // pile them up forever
+    let init_res = turtl_core::init(String::from(r#"{"messaging":{"reqres_append_mid":false,"events_capacity":1000}}"#));
+    if let Err(e) = init_res {
+        eprintln!("could not init core: {}", e);
+        process::exit(1);
     }
+    let handle = turtl_core::start();
+
+    let mut runner = Runner::new(opts.json, Duration::from_secs(opts.timeout));
+    let code = match opts.script {
+        Some(ref script) => run_script(&mut runner, &opts, script.as_str()),
+        None => {
+            repl(&mut runner, &opts);
+            0
+        }
+    };
 
+    exit(&mut runner);
     handle.join().unwrap();
+    process::exit(code);
 }
-
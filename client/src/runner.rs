@@ -0,0 +1,287 @@
+//! Runs client commands (from the prompt or a script) against the core and
+//! prints the results.
+
+use ::std::thread;
+use ::std::time::{Duration, Instant};
+
+use ::jedi::{self, Value};
+use ::regex::Regex;
+use ::turtl_core;
+use ::turtl_core::error::error_code;
+
+use ::commands;
+
+/// What to do after running a line
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Continue,
+    Quit,
+}
+
+/// A command that didn't work out
+#[derive(Debug)]
+pub struct Failure {
+    /// The exit code this failure maps to
+    pub code: i32,
+    /// What went wrong
+    pub message: String,
+}
+
+impl Failure {
+    /// Create a failure for the given error type (see `ERROR_CODES`)
+    pub fn new(errtype: &str, message: String) -> Failure {
+        Failure {
+            code: error_code(errtype).num as i32,
+            message: message,
+        }
+    }
+
+    /// Create a failure from an error the core sent us
+    fn from_response(err: &Value) -> Failure {
+        // errors might be wrapped if `wrap_errors` is on
+        let code = jedi::get_opt::<i32>(&["code"], err)
+            .or_else(|| jedi::get_opt::<i32>(&["err", "code"], err))
+            .unwrap_or(1);
+        Failure {
+            code: code,
+            message: jedi::stringify(err).unwrap_or(String::from("unknown error")),
+        }
+    }
+}
+
+/// Split a line into its parts. Quoted strings ('' or "") are kept together.
+pub fn tokenize(line: &str) -> Vec<String> {
+    lazy_static! {
+        static ref TOKENS: Regex = Regex::new(r#"'.+?'|".+?"|[^ ]+"#).unwrap();
+    }
+    TOKENS.find_iter(line)
+        .map(|x| String::from(x.as_str().trim().trim_matches('"').trim_matches('\'')))
+        .filter(|x| x.len() > 0)
+        .collect::<Vec<_>>()
+}
+
+/// Turn a command-line argument into a value. Anything that parses as JSON is
+/// sent as JSON, everything else is a string.
+pub fn arg_val(arg: &str) -> Value {
+    match jedi::parse::<Value>(&String::from(arg)) {
+        Ok(val) => val,
+        Err(_) => Value::String(String::from(arg)),
+    }
+}
+
+/// Convert a duration to ms for the core's recv functions. Never returns 0,
+/// which would mean "don't wait at all."
+fn duration_ms(dur: Duration) -> u64 {
+    let ms = (dur.as_secs() * 1000) + (dur.subsec_nanos() / 1_000_000) as u64;
+    if ms == 0 { 1 } else { ms }
+}
+
+/// Runs commands and keeps track of our request ids
+pub struct Runner {
+    req_id: u64,
+    json: bool,
+    timeout: Duration,
+}
+
+impl Runner {
+    /// Create a new runner. `json` prints results as JSON lines, `timeout` is
+    /// how long we wait on responses and events.
+    pub fn new(json: bool, timeout: Duration) -> Runner {
+        Runner {
+            req_id: 1,
+            json: json,
+            timeout: timeout,
+        }
+    }
+
+    /// Whether we're printing JSON
+    pub fn json(&self) -> bool {
+        self.json
+    }
+
+    /// Print a line of output
+    pub fn print(&self, friendly: String, json: Value) {
+        if self.json {
+            println!("{}", jedi::stringify(&json).unwrap_or(String::from("null")));
+        } else {
+            println!("{}", friendly);
+        }
+    }
+
+    /// Send a command to the core and wait for its response. Returns the
+    /// response's data, or a failure if the core sent back an error.
+    pub fn call(&mut self, cmd: &str, args: Vec<Value>) -> Result<Value, Failure> {
+        let mid = format!("{}", self.req_id);
+        self.req_id += 1;
+        let mut msg_parts = vec![Value::String(mid.clone()), Value::String(String::from(cmd))];
+        msg_parts.extend(args);
+        let msg = jedi::stringify(&msg_parts)
+            .map_err(|e| Failure::new("json_error", format!("{}", e)))?;
+        turtl_core::send(msg)
+            .map_err(|e| Failure::new("generic", format!("problem sending {}: {}", cmd, e)))?;
+        let start = Instant::now();
+        let res = loop {
+            let elapsed = start.elapsed();
+            if elapsed >= self.timeout {
+                return Err(Failure::new("timeout", format!("timed out waiting on {}", cmd)));
+            }
+            let res = turtl_core::recv_timeout(None, duration_ms(self.timeout - elapsed))
+                .map_err(|e| Failure::new("generic", format!("problem receiving response to {}: {}", cmd, e)))?;
+            let res = match res {
+                Some(x) => x,
+                None => continue,
+            };
+            let res: Value = jedi::parse(&res)
+                .map_err(|e| Failure::new("json_error", format!("bad response to {}: {}", cmd, e)))?;
+            // a command we gave up on earlier might answer late. skip it.
+            match jedi::get_opt::<String>(&["id"], &res) {
+                Some(ref id) if id != &mid => continue,
+                _ => break res,
+            }
+        };
+        let e = jedi::get_opt::<i64>(&["e"], &res).unwrap_or(1);
+        let d = jedi::get_opt::<Value>(&["d"], &res).unwrap_or(Value::Null);
+        if e == 0 {
+            Ok(d)
+        } else {
+            Err(Failure::from_response(&d))
+        }
+    }
+
+    /// Wait for the core to send the given event, returning its data
+    pub fn wait_event(&self, name: &str, timeout: Duration) -> Result<Value, Failure> {
+        let start = Instant::now();
+        loop {
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                return Err(Failure::new("timeout", format!("timed out waiting for event {}", name)));
+            }
+            let ev = turtl_core::recv_event_timeout(duration_ms(timeout - elapsed))
+                .map_err(|e| Failure::new("generic", format!("problem receiving event: {}", e)))?;
+            let ev = match ev {
+                Some(x) => x,
+                None => continue,
+            };
+            let ev: Value = match jedi::parse(&ev) {
+                Ok(x) => x,
+                Err(_) => continue,
+            };
+            if jedi::get_opt::<String>(&["e"], &ev).as_ref().map(|x| x.as_str()) == Some(name) {
+                return Ok(jedi::get_opt::<Value>(&["d"], &ev).unwrap_or(Value::Null));
+            }
+        }
+    }
+
+    /// Run a builtin command, if `cmd` is one
+    fn builtin(&mut self, cmd: &str, args: &Vec<String>) -> Option<Result<Outcome, Failure>> {
+        let res = match cmd {
+            "quit" | "q" => Ok(Outcome::Quit),
+            "help" => {
+                println!("{}", commands::USAGE);
+                Ok(Outcome::Continue)
+            }
+            "sleep" => {
+                match args.get(0).and_then(|x| x.parse::<u64>().ok()) {
+                    Some(ms) => {
+                        thread::sleep(Duration::from_millis(ms));
+                        Ok(Outcome::Continue)
+                    }
+                    None => Err(Failure::new("bad_value", String::from("usage: sleep <ms>"))),
+                }
+            }
+            "wait-event" => {
+                let name = match args.get(0) {
+                    Some(x) => x.clone(),
+                    None => return Some(Err(Failure::new("bad_value", String::from("usage: wait-event <event> [secs]")))),
+                };
+                let timeout = match args.get(1) {
+                    Some(x) => match x.parse::<u64>() {
+                        Ok(secs) => Duration::from_secs(secs),
+                        Err(_) => return Some(Err(Failure::new("bad_value", format!("bad timeout: {}", x)))),
+                    },
+                    None => self.timeout,
+                };
+                self.wait_event(name.as_str(), timeout).map(|data| {
+                    let mut out = jedi::Map::new();
+                    out.insert(String::from("event"), Value::String(name.clone()));
+                    out.insert(String::from("data"), data.clone());
+                    self.print(format!("event: {} {}", name, jedi::stringify(&data).unwrap_or(String::new())), Value::Object(out));
+                    Outcome::Continue
+                })
+            }
+            _ => return None,
+        };
+        Some(res)
+    }
+
+    /// Print the result of a command
+    fn report(&self, cmd: &str, res: Result<&Value, &Failure>) {
+        let (e, d) = match res {
+            Ok(d) => (0, d.clone()),
+            // errors from the core are JSON, our own are plain strings
+            Err(f) => (1, jedi::parse::<Value>(&f.message).unwrap_or(Value::String(f.message.clone()))),
+        };
+        let friendly = format!("{}: {}", if e == 0 { "response" } else { "error" }, jedi::stringify(&d).unwrap_or(String::new()));
+        let mut out = jedi::Map::new();
+        out.insert(String::from("cmd"), Value::String(String::from(cmd)));
+        out.insert(String::from("e"), Value::from(e));
+        out.insert(String::from("d"), d);
+        self.print(friendly, Value::Object(out));
+    }
+
+    /// Run a single line of input
+    pub fn run_line(&mut self, line: &str) -> Result<Outcome, Failure> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("#") {
+            return Ok(Outcome::Continue);
+        }
+        let mut parts = tokenize(line);
+        if parts.len() == 0 {
+            return Ok(Outcome::Continue);
+        }
+        let cmd = parts.remove(0);
+        if let Some(res) = self.builtin(cmd.as_str(), &parts) {
+            if let Err(ref f) = res { self.report(cmd.as_str(), Err(f)); }
+            return res;
+        }
+        // our friendlier commands print their own results
+        if let Some(res) = commands::run(self, cmd.as_str(), &parts[..]) {
+            if let Err(ref f) = res { self.report(cmd.as_str(), Err(f)); }
+            return res.map(|_| Outcome::Continue);
+        }
+        let args = parts.iter().map(|x| arg_val(x)).collect::<Vec<_>>();
+        let res = self.call(cmd.as_str(), args);
+        self.report(cmd.as_str(), res.as_ref());
+        res.map(|_| Outcome::Continue)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokenizes() {
+        assert_eq!(tokenize("user:login 'andrew' \"my pass\""), vec!["user:login", "andrew", "my pass"]);
+        assert_eq!(tokenize("  ping  "), vec!["ping"]);
+        assert_eq!(tokenize("wait-event sync:outgoing:complete 30"), vec!["wait-event", "sync:outgoing:complete", "30"]);
+    }
+
+    #[test]
+    fn parses_args() {
+        assert_eq!(arg_val("true"), Value::Bool(true));
+        assert_eq!(arg_val("12"), Value::from(12));
+        assert_eq!(arg_val("note"), Value::String(String::from("note")));
+        assert_eq!(arg_val(r#"{"title":"hi"}"#), jedi::parse::<Value>(&String::from(r#"{"title":"hi"}"#)).unwrap());
+    }
+
+    #[test]
+    fn failures_map_to_error_codes() {
+        let err = jedi::parse::<Value>(&String::from(r#"{"type":"not_found","code":6}"#)).unwrap();
+        assert_eq!(Failure::from_response(&err).code, 6);
+        let err = jedi::parse::<Value>(&String::from(r#"{"wrapped":true,"err":{"type":"validation","code":8}}"#)).unwrap();
+        assert_eq!(Failure::from_response(&err).code, 8);
+        assert_eq!(Failure::from_response(&Value::String(String::from("lol"))).code, 1);
+        assert_eq!(Failure::new("timeout", String::new()).code, error_code("timeout").num as i32);
+    }
+}
@@ -0,0 +1,118 @@
+//! Prints lists of things as plain-text tables
+
+use ::jedi::{self, Value};
+
+/// Cells longer than this get cut off
+const MAX_CELL: usize = 48;
+
+/// Turn a value into something that fits in a table cell
+pub fn cell(val: &Value, path: &[&str]) -> String {
+    let val = match jedi::get_opt::<Value>(path, val) {
+        Some(x) => x,
+        None => return String::new(),
+    };
+    let text = match val {
+        Value::Null => String::new(),
+        Value::String(x) => x,
+        Value::Array(items) => {
+            items.iter()
+                .map(|x| match x {
+                    &Value::String(ref s) => s.clone(),
+                    _ => jedi::stringify(x).unwrap_or(String::new()),
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+        x => jedi::stringify(&x).unwrap_or(String::new()),
+    };
+    // tables are one line per row
+    let text = text.replace("\r", "").replace("\n", " ");
+    if text.chars().count() > MAX_CELL {
+        let cut = text.chars().take(MAX_CELL - 3).collect::<String>();
+        format!("{}...", cut)
+    } else {
+        text
+    }
+}
+
+/// Render a table with the given headers and rows. Columns are padded to the
+/// width of their widest cell.
+pub fn render(headers: &[&str], rows: &Vec<Vec<String>>) -> String {
+    let mut widths = headers.iter().map(|x| x.chars().count()).collect::<Vec<_>>();
+    for row in rows {
+        for (i, col) in row.iter().enumerate() {
+            if i >= widths.len() { break; }
+            let len = col.chars().count();
+            if len > widths[i] { widths[i] = len; }
+        }
+    }
+    let line = |cols: Vec<String>| -> String {
+        let mut line = cols.iter()
+            .enumerate()
+            .map(|(i, col)| {
+                let pad = widths[i] - col.chars().count();
+                format!("{}{}", col, " ".repeat(pad))
+            })
+            .collect::<Vec<_>>()
+            .join("  ");
+        // no trailing whitespace
+        while line.ends_with(' ') { line.pop(); }
+        line
+    };
+    let mut out = Vec::with_capacity(rows.len() + 2);
+    out.push(line(headers.iter().map(|x| x.to_uppercase()).collect()));
+    out.push(line(widths.iter().map(|x| "-".repeat(*x)).collect()));
+    for row in rows {
+        let mut cols = row.clone();
+        cols.resize(headers.len(), String::new());
+        out.push(line(cols));
+    }
+    out.join("\n")
+}
+
+/// Render a single item as a list of `field: value` lines
+pub fn render_item(item: &Value, fields: &[(&str, &[&str])]) -> String {
+    let width = fields.iter().map(|&(name, _)| name.len()).max().unwrap_or(0);
+    fields.iter()
+        .map(|&(name, path)| {
+            let val = cell(item, path);
+            if val.is_empty() { return format!("{}:", name); }
+            format!("{}:{} {}", name, " ".repeat(width - name.len()), val)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_tables() {
+        let rows = vec![
+            vec![String::from("1234"), String::from("get milk")],
+            vec![String::from("56"), String::new()],
+        ];
+        let table = render(&["id", "title"], &rows);
+        assert_eq!(table, "ID    TITLE\n----  --------\n1234  get milk\n56");
+    }
+
+    #[test]
+    fn converts_cells() {
+        let note: Value = jedi::parse(&String::from(r#"{"title":"hi\nthere","tags":["a","b"],"color":3,"text":null}"#)).unwrap();
+        assert_eq!(cell(&note, &["title"]), "hi there");
+        assert_eq!(cell(&note, &["tags"]), "a, b");
+        assert_eq!(cell(&note, &["color"]), "3");
+        assert_eq!(cell(&note, &["text"]), "");
+        assert_eq!(cell(&note, &["url"]), "");
+        let long = Value::String("x".repeat(100));
+        assert_eq!(cell(&long, &[]).len(), MAX_CELL);
+    }
+
+    #[test]
+    fn renders_items() {
+        let note: Value = jedi::parse(&String::from(r#"{"id":"abc","title":"hi"}"#)).unwrap();
+        let out = render_item(&note, &[("id", &["id"]), ("title", &["title"]), ("url", &["url"])]);
+        assert_eq!(out, "id:    abc\ntitle: hi\nurl:");
+    }
+}
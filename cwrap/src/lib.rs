@@ -14,6 +14,12 @@ extern "C" {
     pub fn turtlc_send(message_bytes: *const u8, message_len: usize) -> i32;
     pub fn turtlc_recv(non_block: u8, msgid: *const ::std::os::raw::c_char, len: *mut usize) -> *const u8;
     pub fn turtlc_recv_event(non_block: u8, len: *mut usize) -> *const u8;
+    pub fn turtlc_recv_timeout(msgid: *const ::std::os::raw::c_char, timeout_ms: u64, len: *mut usize) -> *const u8;
+    pub fn turtlc_recv_event_timeout(timeout_ms: u64, len: *mut usize) -> *const u8;
+    pub fn turtlc_recv_event_sub(non_block: u8, subscriber: *const ::std::os::raw::c_char, len: *mut usize) -> *const u8;
+    pub fn turtlc_events_subscribe() -> u64;
+    pub fn turtlc_events_recv(sub_id: u64, timeout_ms: i64, len: *mut usize) -> *const u8;
+    pub fn turtlc_events_unsubscribe(sub_id: u64) -> i32;
     pub fn turtlc_free(msg: *const u8, len: usize) -> i32;
     pub fn turtlc_lasterr() -> *mut ::std::os::raw::c_char;
     pub fn turtlc_free_err(lasterr: *mut ::std::os::raw::c_char) -> i32;
@@ -48,42 +54,67 @@ pub fn init(app_config: &str) -> thread::JoinHandle<()> {
 
 /// Send a message to the core
 pub fn send(msg: &str) {
-    let msg_vec = Vec::from(String::from(msg).as_bytes());
+    send_bytes(msg.as_bytes());
+}
+
+/// Send a raw message to the core. Use this for sending msgpack-encoded
+/// messages.
+pub fn send_bytes(msg: &[u8]) {
     let ret = unsafe {
-        turtlc_send(msg_vec.as_ptr(), msg_vec.len())
+        turtlc_send(msg.as_ptr(), msg.len())
     };
     if ret != 0 {
         panic!("Error sending msg: err {}", ret);
     }
 }
 
-/// Receive a message from the core, blocking (note that if you are not using
-/// {"reqres_append_mid": true} in the app config, you should pass "" for the
-/// `mid` arg here).
-pub fn recv(mid: &str) -> String {
-    let mut len: usize = 0;
-    let raw_len = &mut len as *mut usize;
-    let mid_c = CString::new(mid).unwrap();
-    let msg_c = unsafe {
-        turtlc_recv(0, mid_c.as_ptr(), raw_len)
-    };
+/// Copy a message from the core into a Vec and free the original
+fn take_msg(fnname: &str, msg_c: *const u8, len: usize) -> Vec<u8> {
     if msg_c.is_null() && len > 0 {
         match lasterr() {
-            Some(x) => panic!("recv() -- error getting event: {}", x),
-            None => panic!("recv() -- got empty msg and couldn't grab lasterr"),
+            Some(x) => panic!("{}() -- error getting msg: {}", fnname, x),
+            None => panic!("{}() -- got empty msg and couldn't grab lasterr", fnname),
         }
     }
     let slice = unsafe { slice::from_raw_parts(msg_c, len) };
-    let res_str = str::from_utf8(slice).unwrap();
-    let ret = String::from(res_str);
+    let ret = Vec::from(slice);
     unsafe {
         turtlc_free(msg_c, len);
     }
     ret
 }
 
-/// Like recv, but non-blocking
-pub fn recv_nb(mid: &str) -> Option<String> {
+/// Convert a message from the core into a String
+fn to_string(bytes: Vec<u8>) -> String {
+    String::from(str::from_utf8(&bytes[..]).unwrap())
+}
+
+/// Convert a Duration to milliseconds for the C API
+fn duration_ms(duration: Duration) -> u64 {
+    (duration.as_secs() * 1000) + (duration.subsec_nanos() / 1_000_000) as u64
+}
+
+/// Receive a raw message from the core, blocking. Use this for receiving
+/// msgpack-encoded responses.
+pub fn recv_bytes(mid: &str) -> Vec<u8> {
+    let mut len: usize = 0;
+    let raw_len = &mut len as *mut usize;
+    let mid_c = CString::new(mid).unwrap();
+    let msg_c = unsafe {
+        turtlc_recv(0, mid_c.as_ptr(), raw_len)
+    };
+    take_msg("recv", msg_c, len)
+}
+
+/// Receive a message from the core, blocking (note that if you are not using
+/// {"reqres_append_mid": true} in the app config, you should pass "" for the
+/// `mid` arg here).
+pub fn recv(mid: &str) -> String {
+    to_string(recv_bytes(mid))
+}
+
+/// Like recv_bytes, but non-blocking
+pub fn recv_bytes_nb(mid: &str) -> Option<Vec<u8>> {
     let mut len: usize = 0;
     let raw_len = &mut len as *mut usize;
     let mid_c = CString::new(mid).unwrap();
@@ -93,54 +124,153 @@ pub fn recv_nb(mid: &str) -> Option<String> {
     if msg_c.is_null() {
         return None;
     }
-    let slice = unsafe { slice::from_raw_parts(msg_c, len) };
-    let res_str = str::from_utf8(slice).unwrap();
-    let ret = String::from(res_str);
-    unsafe {
-        turtlc_free(msg_c, len);
-    }
-    Some(ret)
+    Some(take_msg("recv_nb", msg_c, len))
+}
+
+/// Like recv, but non-blocking
+pub fn recv_nb(mid: &str) -> Option<String> {
+    recv_bytes_nb(mid).map(to_string)
+}
+
+/// Receive a raw core event (blocks)
+pub fn recv_event_bytes() -> Vec<u8> {
+    let mut len: usize = 0;
+    let raw_len = &mut len as *mut usize;
+    let msg_c = unsafe {
+        turtlc_recv_event(0, raw_len)
+    };
+    take_msg("recv_event", msg_c, len)
 }
 
 /// Receive a core event (blocks)
 pub fn recv_event() -> String {
+    to_string(recv_event_bytes())
+}
+
+/// Receive a raw core event (non blocking)
+pub fn recv_event_bytes_nb() -> Option<Vec<u8>> {
     let mut len: usize = 0;
     let raw_len = &mut len as *mut usize;
     let msg_c = unsafe {
-        turtlc_recv_event(0, raw_len)
+        turtlc_recv_event(1, raw_len)
     };
-    if msg_c.is_null() && len > 0 {
-        match lasterr() {
-            Some(x) => panic!("recv_event() -- error getting event: {}", x),
-            None => panic!("recv_event() -- got empty msg and couldn't grab lasterr"),
-        }
-    }
-    let slice = unsafe { slice::from_raw_parts(msg_c, len) };
-    let res_str = str::from_utf8(slice).unwrap();
-    let ret = String::from(res_str);
-    unsafe {
-        turtlc_free(msg_c, len);
+    if msg_c.is_null() {
+        return None;
     }
-    ret
+    Some(take_msg("recv_event_nb", msg_c, len))
 }
 
 /// Receive a core event (non blocking)
 pub fn recv_event_nb() -> Option<String> {
+    recv_event_bytes_nb().map(to_string)
+}
+
+/// Receive a raw message from the core, waiting at most `timeout` before
+/// giving up (in which case we return None)
+pub fn recv_bytes_timeout(mid: &str, timeout: Duration) -> Option<Vec<u8>> {
     let mut len: usize = 0;
     let raw_len = &mut len as *mut usize;
+    let mid_c = CString::new(mid).unwrap();
     let msg_c = unsafe {
-        turtlc_recv_event(1, raw_len)
+        turtlc_recv_timeout(mid_c.as_ptr(), duration_ms(timeout), raw_len)
+    };
+    if msg_c.is_null() && len == 0 {
+        return None;
+    }
+    Some(take_msg("recv_timeout", msg_c, len))
+}
+
+/// Like recv_bytes_timeout, but returns a String
+pub fn recv_timeout(mid: &str, timeout: Duration) -> Option<String> {
+    recv_bytes_timeout(mid, timeout).map(to_string)
+}
+
+/// Receive a raw core event, waiting at most `timeout` before giving up (in
+/// which case we return None)
+pub fn recv_event_bytes_timeout(timeout: Duration) -> Option<Vec<u8>> {
+    let mut len: usize = 0;
+    let raw_len = &mut len as *mut usize;
+    let msg_c = unsafe {
+        turtlc_recv_event_timeout(duration_ms(timeout), raw_len)
+    };
+    if msg_c.is_null() && len == 0 {
+        return None;
+    }
+    Some(take_msg("recv_event_timeout", msg_c, len))
+}
+
+/// Like recv_event_bytes_timeout, but returns a String
+pub fn recv_event_timeout(timeout: Duration) -> Option<String> {
+    recv_event_bytes_timeout(timeout).map(to_string)
+}
+
+/// Receive a raw core event for a subscriber (blocks). See the
+/// `app:events:subscribe` command.
+pub fn recv_event_sub_bytes(subscriber: &str) -> Vec<u8> {
+    let mut len: usize = 0;
+    let raw_len = &mut len as *mut usize;
+    let subscriber_c = CString::new(subscriber).unwrap();
+    let msg_c = unsafe {
+        turtlc_recv_event_sub(0, subscriber_c.as_ptr(), raw_len)
+    };
+    take_msg("recv_event_sub", msg_c, len)
+}
+
+/// Receive a core event for a subscriber (blocks)
+pub fn recv_event_sub(subscriber: &str) -> String {
+    to_string(recv_event_sub_bytes(subscriber))
+}
+
+/// Receive a raw core event for a subscriber (non blocking)
+pub fn recv_event_sub_bytes_nb(subscriber: &str) -> Option<Vec<u8>> {
+    let mut len: usize = 0;
+    let raw_len = &mut len as *mut usize;
+    let subscriber_c = CString::new(subscriber).unwrap();
+    let msg_c = unsafe {
+        turtlc_recv_event_sub(1, subscriber_c.as_ptr(), raw_len)
     };
     if msg_c.is_null() {
         return None;
     }
-    let slice = unsafe { slice::from_raw_parts(msg_c, len) };
-    let res_str = str::from_utf8(slice).unwrap();
-    let ret = String::from(res_str);
-    unsafe {
-        turtlc_free(msg_c, len);
+    Some(take_msg("recv_event_sub_nb", msg_c, len))
+}
+
+/// Receive a core event for a subscriber (non blocking)
+pub fn recv_event_sub_nb(subscriber: &str) -> Option<String> {
+    recv_event_sub_bytes_nb(subscriber).map(to_string)
+}
+
+/// Subscribe to the events broadcast channel, returning the subscription id
+pub fn events_subscribe() -> u64 {
+    let sub_id = unsafe { turtlc_events_subscribe() };
+    if sub_id == 0 {
+        panic!("events_subscribe() -- error subscribing: {:?}", lasterr());
+    }
+    sub_id
+}
+
+/// Receive a raw event from a subscription. A negative `timeout_ms` blocks, 0
+/// returns immediately, and anything else waits at most that long.
+pub fn events_recv_bytes(sub_id: u64, timeout_ms: i64) -> Option<Vec<u8>> {
+    let mut len: usize = 0;
+    let raw_len = &mut len as *mut usize;
+    let msg_c = unsafe {
+        turtlc_events_recv(sub_id, timeout_ms, raw_len)
+    };
+    if msg_c.is_null() && len == 0 {
+        return None;
     }
-    Some(ret)
+    Some(take_msg("events_recv", msg_c, len))
+}
+
+/// Receive an event from a subscription (see `events_recv_bytes`)
+pub fn events_recv(sub_id: u64, timeout_ms: i64) -> Option<String> {
+    events_recv_bytes(sub_id, timeout_ms).map(to_string)
+}
+
+/// End an events subscription
+pub fn events_unsubscribe(sub_id: u64) -> bool {
+    unsafe { turtlc_events_unsubscribe(sub_id) == 0 }
 }
 
 pub fn lasterr() -> Option<String> {
@@ -0,0 +1,140 @@
+//! Index values. Single-field indexes store their values typed (integer, real,
+//! text, bool or null) so they compare and sort the way you'd expect. Indexes
+//! over more than one field store a text key instead, with the fields' values
+//! joined by `|` (and any `|` or `\` in a value escaped with a `\`).
+
+use ::rusqlite;
+use ::rusqlite::types::{ToSql, ToSqlOutput, Null};
+use ::jedi::Value;
+
+/// A value stored in (or compared against) an index
+#[derive(Debug, Clone, PartialEq)]
+pub enum IndexVal {
+    Null,
+    /// Stored as an integer (1/0)
+    Bool(bool),
+    Int(i64),
+    Real(f64),
+    Text(String),
+}
+
+impl IndexVal {
+    /// Convert a JSON value into an index value. Objects and arrays can't be
+    /// indexed directly, so they become null.
+    pub fn from_json(val: &Value) -> IndexVal {
+        match *val {
+            Value::String(ref x) => IndexVal::Text(x.clone()),
+            Value::Bool(x) => IndexVal::Bool(x),
+            Value::Number(ref x) => {
+                if let Some(int) = x.as_i64() {
+                    IndexVal::Int(int)
+                } else {
+                    IndexVal::Real(x.as_f64().unwrap_or(0.0))
+                }
+            }
+            Value::Null | Value::Array(_) | Value::Object(_) => IndexVal::Null,
+        }
+    }
+
+    /// Turn this value into a piece of a compound key
+    fn key_part(&self) -> String {
+        match *self {
+            IndexVal::Null => String::new(),
+            IndexVal::Bool(x) => format!("{}", x),
+            IndexVal::Int(x) => format!("{}", x),
+            IndexVal::Real(x) => format!("{}", x),
+            IndexVal::Text(ref x) => x.replace("\\", "\\\\").replace("|", "\\|"),
+        }
+    }
+
+    /// Build the value we store for a set of field values. One value is stored
+    /// as-is, more than one becomes a compound (text) key.
+    pub fn key(vals: &[IndexVal]) -> IndexVal {
+        if vals.len() == 1 {
+            return vals[0].clone();
+        }
+        IndexVal::Text(IndexVal::compound_key(vals))
+    }
+
+    /// Join a set of field values into a compound (text) key
+    pub fn compound_key(vals: &[IndexVal]) -> String {
+        vals.iter()
+            .map(|x| x.key_part())
+            .collect::<Vec<_>>()
+            .join("|")
+    }
+}
+
+impl ToSql for IndexVal {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput> {
+        let res = match *self {
+            IndexVal::Null => ToSqlOutput::from(Null),
+            IndexVal::Bool(x) => ToSqlOutput::from(x),
+            IndexVal::Int(x) => ToSqlOutput::from(x),
+            IndexVal::Real(x) => ToSqlOutput::from(x),
+            IndexVal::Text(ref x) => ToSqlOutput::from(x.as_str()),
+        };
+        Ok(res)
+    }
+}
+
+impl From<String> for IndexVal {
+    fn from(val: String) -> IndexVal { IndexVal::Text(val) }
+}
+
+impl<'a> From<&'a str> for IndexVal {
+    fn from(val: &'a str) -> IndexVal { IndexVal::Text(String::from(val)) }
+}
+
+impl<'a> From<&'a String> for IndexVal {
+    fn from(val: &'a String) -> IndexVal { IndexVal::Text(val.clone()) }
+}
+
+impl From<bool> for IndexVal {
+    fn from(val: bool) -> IndexVal { IndexVal::Bool(val) }
+}
+
+impl From<i32> for IndexVal {
+    fn from(val: i32) -> IndexVal { IndexVal::Int(val as i64) }
+}
+
+impl From<i64> for IndexVal {
+    fn from(val: i64) -> IndexVal { IndexVal::Int(val) }
+}
+
+impl From<f64> for IndexVal {
+    fn from(val: f64) -> IndexVal { IndexVal::Real(val) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::jedi;
+
+    #[test]
+    fn converts_json() {
+        let val: Value = jedi::parse(&String::from(r#"[1, -4, 2.5, "hi", true, null, {"a": 1}]"#)).unwrap();
+        let vals = jedi::get::<Vec<Value>>(&[], &val).unwrap()
+            .iter()
+            .map(|x| IndexVal::from_json(x))
+            .collect::<Vec<_>>();
+        assert_eq!(vals, vec![
+            IndexVal::Int(1),
+            IndexVal::Int(-4),
+            IndexVal::Real(2.5),
+            IndexVal::from("hi"),
+            IndexVal::Bool(true),
+            IndexVal::Null,
+            IndexVal::Null,
+        ]);
+    }
+
+    #[test]
+    fn builds_keys() {
+        assert_eq!(IndexVal::key(&[IndexVal::Int(42)]), IndexVal::Int(42));
+        assert_eq!(IndexVal::key(&[IndexVal::from("note"), IndexVal::Bool(false)]), IndexVal::from("note|false"));
+        assert_eq!(IndexVal::compound_key(&[IndexVal::from("a|b"), IndexVal::from("c\\"), IndexVal::Null]), "a\\|b|c\\\\|");
+        // escaping keeps values with pipes in them from colliding
+        assert!(IndexVal::compound_key(&[IndexVal::from("a|b"), IndexVal::from("c")]) != IndexVal::compound_key(&[IndexVal::from("a"), IndexVal::from("b|c")]));
+    }
+}
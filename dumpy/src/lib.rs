@@ -14,6 +14,10 @@
 //!      in the index table for each value, and point each one to your target
 //!      object.
 //!
+//! Stores are versioned: give `Dumpy::versioned()` a schema version and a list
+//! of `Migration`s and the store is brought up to date when it's `init()`ed
+//! (see the `migration` module).
+//!
 //! All that said, unless this use-case fits yours perfectly, don't use this
 //! library. It's interface could be thought of as a crude IndexedDB. It was
 //! made specifically for the Turtl app and probably won't ever do the things
@@ -26,6 +30,8 @@ extern crate quick_error;
 extern crate rusqlite;
 extern crate serde_json;
 
+use ::std::collections::BTreeMap;
+
 use ::rusqlite::Connection;
 use ::rusqlite::types::Value as SqlValue;
 use ::rusqlite::types::{ToSql, ToSqlOutput};
@@ -33,10 +39,30 @@ use ::rusqlite::Error as SqlError;
 use ::jedi::{Value, JSONError};
 
 pub mod error;
+pub mod index;
+pub mod migration;
+pub mod query;
 
 pub use ::error::DError;
+pub use ::index::IndexVal;
+pub use ::migration::Migration;
+pub use ::query::{Query, Filter, Order};
 use ::error::DResult;
 
+/// The kv key we store the schema version under
+const KV_SCHEMA_VERSION: &'static str = "dumpy:schema_version";
+/// The kv key we store the schema's index definitions under (so we can tell
+/// when they change)
+const KV_SCHEMA_INDEXES: &'static str = "dumpy:schema_indexes";
+/// The kv key we store the format of the `dumpy_index` table under
+const KV_INDEX_FORMAT: &'static str = "dumpy:index_format";
+/// The current format of the `dumpy_index` table. Format 1 (no key in the kv
+/// store) held every index value as text.
+const INDEX_FORMAT: &'static str = "2";
+
+/// A table's index definitions, as (index name, fields) pairs
+type IndexDefs = Vec<(String, Vec<String>)>;
+
 /// Makes generating SQL statements somewhat painless by implementing rusqlite's
 /// ToSql for some primitive types (wrapped in one enum).
 #[derive(Debug)]
@@ -66,40 +92,181 @@ impl ToSql for SearchVal {
 /// functions.
 pub struct Dumpy {
     schema: Value,
+    /// The version of our schema
+    version: u32,
+    /// Migrations to run when opening a store with an older schema version,
+    /// sorted by version
+    migrations: Vec<Migration>,
 }
 
 impl Dumpy {
     /// Create a new dumpy
     pub fn new(schema: Value) -> Dumpy {
+        Dumpy::versioned(schema, 0, Vec::new())
+    }
+
+    /// Create a new dumpy with a schema version and the migrations that get a
+    /// store from an older version to this one.
+    pub fn versioned(schema: Value, version: u32, mut migrations: Vec<Migration>) -> Dumpy {
+        migrations.sort_by_key(|x| x.version);
         Dumpy {
             schema: schema,
+            version: version,
+            migrations: migrations,
         }
     }
 
-    /// Init our dumpy store on an existing connection.
+    /// Init our dumpy store on an existing connection. If the store was last
+    /// used with an older schema, it's migrated.
     pub fn init(&self, conn: &Connection) -> DResult<()> {
         conn.execute("CREATE TABLE IF NOT EXISTS dumpy_objects (id VARCHAR(64) PRIMARY KEY, table_name VARCHAR(32), data TEXT)", &[])?;
-        conn.execute("CREATE TABLE IF NOT EXISTS dumpy_index (id INTEGER PRIMARY KEY, table_name VARCHAR(32), index_name VARCHAR(32), vals VARCHAR(256), object_id VARCHAR(64))", &[])?;
         conn.execute("CREATE TABLE IF NOT EXISTS dumpy_kv (key VARCHAR(32) PRIMARY KEY, value TEXT)", &[])?;
+        conn.execute("CREATE UNIQUE INDEX IF NOT EXISTS dumpy_idx_kv ON dumpy_kv (key)", &[])?;
+        Dumpy::create_index_table(conn)?;
+        self.migrate(conn)
+    }
 
+    /// Create the table our indexes live in
+    fn create_index_table(conn: &Connection) -> DResult<()> {
+        // `vals` has no type (so no type affinity), which lets sqlite store
+        // each value with its own type rather than converting them all to text
+        conn.execute("CREATE TABLE IF NOT EXISTS dumpy_index (id INTEGER PRIMARY KEY, table_name VARCHAR(32), index_name VARCHAR(32), vals, object_id VARCHAR(64))", &[])?;
         conn.execute("CREATE INDEX IF NOT EXISTS dumpy_idx_index ON dumpy_index (table_name, index_name, vals)", &[])?;
         conn.execute("CREATE INDEX IF NOT EXISTS dumpy_idx_index_obj ON dumpy_index (table_name, object_id)", &[])?;
-        conn.execute("CREATE UNIQUE INDEX IF NOT EXISTS dumpy_idx_kv ON dumpy_kv (key)", &[])?;
         Ok(())
     }
 
-    /// Store an object!
-    pub fn store(&self, conn: &Connection, table: &String, obj: &Value) -> DResult<()> {
-        let id: String = match jedi::get_opt(&["id"], obj) {
-            Some(id) => id,
-            None => return Err(DError::Msg(format!("Dumpy.store() -- object being saved to table `{}` is missing `id` field", table))),
+    /// Bring the store up to our schema version (and indexes) if it isn't
+    /// already. Everything happens in a savepoint, so a failed migration
+    /// leaves the store the way it was.
+    fn migrate(&self, conn: &Connection) -> DResult<()> {
+        let indexes = self.schema_indexes()?;
+        let indexes_str = jedi::stringify(&indexes)?;
+        let stored_version = match self.kv_get(conn, KV_SCHEMA_VERSION)? {
+            Some(x) => match x.parse::<u32>() {
+                Ok(x) => Some(x),
+                Err(_) => return Err(DError::Msg(format!("dumpy: bad schema version in store: {}", x))),
+            },
+            None => None,
         };
-        let json = jedi::stringify(obj)?;
-        // "upsert" the object
-        conn.execute("INSERT OR REPLACE INTO dumpy_objects (id, table_name, data) VALUES ($1, $2, $3)", &[&id, table, &json])?;
-        // wipte out all indexes for this object
-        conn.execute("DELETE FROM dumpy_index WHERE table_name = $1 AND object_id = $2", &[table, &id])?;
+        let stored_indexes = self.kv_get(conn, KV_SCHEMA_INDEXES)?;
+        let rebuild_index = self.kv_get(conn, KV_INDEX_FORMAT)? != Some(String::from(INDEX_FORMAT));
+        if stored_version == Some(self.version) && stored_indexes.as_ref() == Some(&indexes_str) && !rebuild_index {
+            return Ok(());
+        }
+        if let Some(version) = stored_version {
+            if version > self.version {
+                return Err(DError::Msg(format!("dumpy: store has schema version {}, which is newer than ours ({})", version, self.version)));
+            }
+        }
+
+        conn.execute_batch("SAVEPOINT dumpy_migrate")?;
+        let res = self.run_migrations(conn, stored_version, stored_indexes, &indexes, rebuild_index)
+            .and_then(|_| {
+                self.kv_set(conn, KV_SCHEMA_VERSION, &format!("{}", self.version))?;
+                self.kv_set(conn, KV_SCHEMA_INDEXES, &indexes_str)?;
+                self.kv_set(conn, KV_INDEX_FORMAT, &String::from(INDEX_FORMAT))
+            });
+        match res {
+            Ok(_) => {
+                conn.execute_batch("RELEASE dumpy_migrate")?;
+                Ok(())
+            }
+            Err(e) => {
+                // we already have an error to return, don't clobber it
+                let _ = conn.execute_batch("ROLLBACK TO dumpy_migrate; RELEASE dumpy_migrate");
+                Err(e)
+            }
+        }
+    }
+
+    /// Does the actual work of migrating a store.
+    fn run_migrations(&self, conn: &Connection, stored_version: Option<u32>, stored_indexes: Option<String>, indexes: &BTreeMap<String, IndexDefs>, rebuild_index: bool) -> DResult<()> {
+        if rebuild_index {
+            // the index table is in an old format. start it over (every table
+            // gets reindexed below)
+            conn.execute("DROP TABLE IF EXISTS dumpy_index", &[])?;
+            Dumpy::create_index_table(conn)?;
+        }
+        let num_objects: i64 = conn.query_row("SELECT COUNT(*) FROM dumpy_objects", &[], |row| row.get(0))?;
+        if stored_version.is_none() && num_objects == 0 {
+            // a brand new store, nothing to migrate
+            return Ok(());
+        }
+        // stores from before we tracked versions are version 0
+        let from_version = stored_version.unwrap_or(0);
+        let old_indexes: BTreeMap<String, IndexDefs> = match stored_indexes {
+            Some(ref x) if !rebuild_index => jedi::parse(x)?,
+            _ => BTreeMap::new(),
+        };
+        let migrations = self.migrations.iter()
+            .filter(|x| x.version > from_version && x.version <= self.version)
+            .collect::<Vec<_>>();
+
+        // drop any indexes that aren't in the schema anymore
+        let existing = {
+            let mut query = conn.prepare("SELECT DISTINCT table_name, index_name FROM dumpy_index")?;
+            let rows = query.query_map(&[], |row| -> (String, String) {
+                (row.get("table_name"), row.get("index_name"))
+            })?;
+            let mut existing = Vec::new();
+            for row in rows {
+                existing.push(row?);
+            }
+            existing
+        };
+        for (table, index) in existing {
+            let keep = match indexes.get(&table) {
+                Some(defs) => defs.iter().any(|x| x.0 == index),
+                None => false,
+            };
+            if !keep {
+                conn.execute("DELETE FROM dumpy_index WHERE table_name = $1 AND index_name = $2", &[&table, &index])?;
+            }
+        }
 
+        // reindex any table whose indexes changed (or that a migration asked
+        // us to reindex)
+        let no_defs = Vec::new();
+        let mut reindex: Vec<String> = Vec::new();
+        for (table, defs) in indexes {
+            let old_defs = old_indexes.get(table).unwrap_or(&no_defs);
+            if old_defs != defs {
+                reindex.push(table.clone());
+            }
+        }
+        for migration in &migrations {
+            for table in &migration.reindex {
+                if !reindex.contains(table) {
+                    reindex.push(table.clone());
+                }
+            }
+        }
+        for table in &reindex {
+            self.reindex(conn, table)?;
+        }
+
+        for migration in &migrations {
+            if let Some(ref transform) = migration.transform {
+                transform(self, conn)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Grab the index definitions for every table in our schema
+    fn schema_indexes(&self) -> DResult<BTreeMap<String, IndexDefs>> {
+        let mut indexes = BTreeMap::new();
+        if let Value::Object(ref tables) = self.schema {
+            for table in tables.keys() {
+                indexes.insert(table.clone(), self.index_defs(table)?);
+            }
+        }
+        Ok(indexes)
+    }
+
+    /// Grab the index definitions for a table
+    fn index_defs(&self, table: &String) -> DResult<IndexDefs> {
         let indexes = match jedi::get::<Vec<Value>>(&[table, "indexes"], &self.schema) {
             Ok(x) => x,
             Err(e) => match e {
@@ -109,95 +276,102 @@ impl Dumpy {
                 _ => return Err(From::from(e)),
             }
         };
+        let mut defs = Vec::with_capacity(indexes.len());
         for index in &indexes {
             let fields = jedi::get::<Vec<String>>(&["fields"], index)?;
+            if fields.len() == 0 {
+                return Err(DError::Msg(format!("dumpy: {}: index has no fields", table)));
+            }
             let idx_name: String = match jedi::get::<String>(&["name"], index) {
                 Ok(x) => x,
                 Err(e) => match e {
                     JSONError::DeadEnd | JSONError::NotFound(_) => {
-                        let mut name = fields[0].clone();
-                        for field in &fields[1..] {
-                            name = format!("{}_{}", name, field);
-                        }
-                        name
+                        fields.join("_")
                     }
                     _ => return Err(From::from(e)),
                 }
             };
-            let mut val_vec: Vec<Vec<String>> = Vec::new();
-            let blankval = String::from("");
+            defs.push((idx_name, fields));
+        }
+        Ok(defs)
+    }
+
+    /// Rebuild the indexes for every object in a table
+    pub fn reindex(&self, conn: &Connection, table: &String) -> DResult<()> {
+        conn.execute("DELETE FROM dumpy_index WHERE table_name = $1", &[table])?;
+        let defs = self.index_defs(table)?;
+        if defs.len() == 0 { return Ok(()); }
+        for obj in self.all(conn, table)? {
+            let id: String = match jedi::get_opt(&["id"], &obj) {
+                Some(id) => id,
+                None => continue,
+            };
+            self.index_object(conn, table, &id, &obj, &defs)?;
+        }
+        Ok(())
+    }
+
+    /// Store an object!
+    pub fn store(&self, conn: &Connection, table: &String, obj: &Value) -> DResult<()> {
+        let id: String = match jedi::get_opt(&["id"], obj) {
+            Some(id) => id,
+            None => return Err(DError::Msg(format!("Dumpy.store() -- object being saved to table `{}` is missing `id` field", table))),
+        };
+        let json = jedi::stringify(obj)?;
+        // "upsert" the object
+        conn.execute("INSERT OR REPLACE INTO dumpy_objects (id, table_name, data) VALUES ($1, $2, $3)", &[&id, table, &json])?;
+        // wipte out all indexes for this object
+        conn.execute("DELETE FROM dumpy_index WHERE table_name = $1 AND object_id = $2", &[table, &id])?;
 
+        let defs = self.index_defs(table)?;
+        self.index_object(conn, table, &id, obj, &defs)
+    }
+
+    /// Write the index entries for an object
+    fn index_object(&self, conn: &Connection, table: &String, id: &String, obj: &Value, defs: &IndexDefs) -> DResult<()> {
+        for &(ref idx_name, ref fields) in defs {
             // build an array of an array of values (we want all combinations
             // of the various fields)
-            for field in &fields {
+            let mut val_vec: Vec<Vec<IndexVal>> = Vec::new();
+            for field in fields {
                 let val = jedi::walk(&[&field], &obj);
-                let mut subvals: Vec<String> = Vec::new();
+                let mut subvals: Vec<IndexVal> = Vec::new();
                 match val {
-                    Ok(x) => {
-                        match *x {
-                            Value::String(ref x) => {
-                                subvals.push(x.clone());
-                            },
-                            Value::Number(ref x) => {
-                                subvals.push(format!("{}", x));
-                            },
-                            Value::Bool(ref x) => {
-                                subvals.push(format!("{}", x));
-                            },
-                            Value::Array(ref x) => {
-                                for val in x {
-                                    match *val {
-                                        Value::String(ref s) => {
-                                            subvals.push(s.clone());
-                                        }
-                                        Value::Number(ref x) => {
-                                            subvals.push(format!("{}", x));
-                                        },
-                                        _ => {
-                                            subvals.push(blankval.clone());
-                                        },
-                                    }
-                                }
-                            },
-                            Value::Null | Value::Object(_) => {
-                                subvals.push(blankval.clone());
-                            },
+                    Ok(&Value::Array(ref x)) => {
+                        for val in x {
+                            subvals.push(IndexVal::from_json(val));
                         }
-                    },
+                    }
+                    Ok(x) => {
+                        subvals.push(IndexVal::from_json(x));
+                    }
                     Err(JSONError::NotFound(_)) => {
-                        subvals.push(blankval.clone());
-                    },
+                        subvals.push(IndexVal::Null);
+                    }
                     Err(e) => return Err(From::from(e)),
                 }
                 val_vec.push(subvals);
             }
 
-            fn combine(acc: String, next: &Vec<Vec<String>>, final_vals: &mut Vec<String>) {
+            fn combine(acc: Vec<IndexVal>, next: &[Vec<IndexVal>], final_vals: &mut Vec<IndexVal>) {
                 if next.len() == 0 {
-                    final_vals.push(acc);
+                    final_vals.push(IndexVal::key(&acc));
                     return;
                 }
-                let here = &next[0];
-                let next = Vec::from(&next[1..]);
-                for val in here {
-                    let acced;
-                    if acc == "" {
-                        acced = format!("{}", val);
-                    } else {
-                        acced = format!("{}|{}", acc, val);
-                    }
-                    combine(acced, &next, final_vals);
+                for val in &next[0] {
+                    let mut acced = acc.clone();
+                    acced.push(val.clone());
+                    combine(acced, &next[1..], final_vals);
                 }
-
             }
-            let mut vals: Vec<String> = Vec::new();
-            combine(String::from(""), &val_vec, &mut vals);
+            let mut vals: Vec<IndexVal> = Vec::new();
+            combine(Vec::new(), &val_vec, &mut vals);
             for val in &vals {
                 conn.execute("INSERT INTO dumpy_index (table_name, index_name, vals, object_id) VALUES ($1, $2, $3, $4)", &[
                     table,
-                    &idx_name,
+                    idx_name,
                     val,
-                    &id,
+                    id,
                 ])?;
             }
         }
@@ -235,39 +409,26 @@ impl Dumpy {
         }
     }
 
-    /// Find objects using a given index/values
+    /// Find objects using a given index/values. For multi-field indexes, you
+    /// can pass in just the first few values.
     pub fn find(&self, conn: &Connection, table: &String, index: &String, vals: &Vec<String>) -> DResult<Vec<Value>> {
-        let mut query = conn.prepare("SELECT object_id FROM dumpy_index WHERE table_name = $1 AND index_name = $2 AND vals LIKE $3")?;
-        let vals_str = vals
-            .into_iter()
-            .fold(String::new(), |acc, x| {
-                if acc == "" {
-                    format!("{}", x)
-                } else {
-                    format!("{}|{}", acc, x)
-                }
-            });
-        let vals_str = format!("{}%", vals_str);
-        let rows = query.query_map(&[table, index, &vals_str], |row| {
-            row.get("object_id")
-        })?;
-        let mut ids: Vec<String> = Vec::new();
-        for oid in rows {
-            ids.push(oid?);
-        }
+        let vals = vals.iter().map(|x| IndexVal::from(x)).collect::<Vec<_>>();
+        let query = Query::new(table).filter(Filter::Leading(index.clone(), vals));
+        self.query(conn, &query)
+    }
 
-        let oids = ids.into_iter().fold(String::new(), |acc, x| {
-            if acc == "" {
-                format!("'{}'", x)
-            } else {
-                format!("{}, '{}'", acc, x)
-            }
-        });
-        let query = format!("SELECT data FROM dumpy_objects WHERE id IN ({}) ORDER BY id ASC", oids);
-        let mut query = conn.prepare(&query[..])?;
-        let rows = query.query_map(&[], |row| {
-            row.get("data")
-        })?;
+    /// Grab the objects matching a query
+    pub fn query(&self, conn: &Connection, query: &Query) -> DResult<Vec<Value>> {
+        let mut qry_vals: Vec<IndexVal> = Vec::new();
+        let sql = query.to_sql(&mut qry_vals);
+        let mut prepared_qry = conn.prepare(sql.as_str())?;
+        let values: Vec<&ToSql> = qry_vals.iter()
+            .map(|x| {
+                let ts: &ToSql = x;
+                ts
+            })
+            .collect::<Vec<_>>();
+        let rows = prepared_qry.query_map(values.as_slice(), |row| row.get("data"))?;
         let mut objects: Vec<Value> = Vec::new();
         for data in rows {
             objects.push(jedi::parse(&data?)?);
@@ -275,32 +436,27 @@ impl Dumpy {
         Ok(objects)
     }
 
-    /// Get ALL objects in a table, ordered by id ASC, with a limit
-    pub fn all_limit(&self, conn: &Connection, table: &String, limit: Option<i32>) -> DResult<Vec<Value>> {
-        let mut qry_parts = Vec::with_capacity(2);
-        let mut qry_vals: Vec<SearchVal> = Vec::with_capacity(2);
-        qry_parts.push("SELECT data FROM dumpy_objects WHERE table_name = ? ORDER BY id ASC");
-        qry_vals.push(SearchVal::String(table.clone()));
-        if let Some(lim) = limit {
-            qry_parts.push(" LIMIT ?");
-            qry_vals.push(SearchVal::Int(lim));
-        }
-        let merged_qry = qry_parts.as_slice().join("");
-        let mut query = conn.prepare(merged_qry.as_str())?;
-
+    /// Count the objects matching a query (ignores its limit/offset)
+    pub fn count(&self, conn: &Connection, query: &Query) -> DResult<i64> {
+        let mut qry_vals: Vec<IndexVal> = Vec::new();
+        let sql = query.to_count_sql(&mut qry_vals);
         let values: Vec<&ToSql> = qry_vals.iter()
             .map(|x| {
                 let ts: &ToSql = x;
                 ts
             })
             .collect::<Vec<_>>();
+        let count: i64 = conn.query_row(sql.as_str(), values.as_slice(), |row| row.get(0))?;
+        Ok(count)
+    }
 
-        let rows = query.query_map(values.as_slice(), |row| row.get("data"))?;
-        let mut objects: Vec<Value> = Vec::new();
-        for data in rows {
-            objects.push(jedi::parse(&data?)?);
+    /// Get ALL objects in a table, ordered by id ASC, with a limit
+    pub fn all_limit(&self, conn: &Connection, table: &String, limit: Option<i32>) -> DResult<Vec<Value>> {
+        let mut query = Query::new(table);
+        if let Some(lim) = limit {
+            query = query.limit(lim);
         }
-        Ok(objects)
+        self.query(conn, &query)
     }
 
     /// Get ALL objects in a table, ordered by id ASC
@@ -383,6 +539,8 @@ mod tests {
     use ::rusqlite::Connection;
     use ::rusqlite::types::Value as SqlValue;
     use ::error::DResult;
+    use ::std::sync::Arc;
+    use ::std::sync::atomic::{AtomicUsize, Ordering};
 
     fn pre_test() -> (Connection, Dumpy) {
         let conn = Connection::open_in_memory().unwrap();
@@ -522,4 +680,181 @@ mod tests {
         let val = dumpy.kv_get(&conn, "some_setting").unwrap();
         assert_eq!(val, None);
     }
+
+    #[test]
+    fn migrates_indexes_and_data() {
+        let conn = Connection::open_in_memory().unwrap();
+        let schema_v1: Value = jedi::parse(&String::from(r#"{"notes":{"indexes":[{"fields":["boards"]},{"name":"user_boards","fields":["user_id","boards"]}]}}"#)).unwrap();
+        let dumpy = Dumpy::versioned(schema_v1.clone(), 1, Vec::new());
+        dumpy.init(&conn).unwrap();
+        let note = jedi::parse(&String::from(r#"{"id":"n0mnm","user_id":"3443","boards":["1234","5678"],"body":"this is my note lol"}"#)).unwrap();
+        dumpy.store(&conn, &String::from("notes"), &note).unwrap();
+        assert_eq!(index_count(&conn), 4);
+        assert_eq!(dumpy.kv_get(&conn, "dumpy:schema_version").unwrap(), Some(String::from("1")));
+
+        // v2 drops the user_boards index, adds a user_id index, and gives every
+        // note a title
+        let runs = Arc::new(AtomicUsize::new(0));
+        let runs2 = runs.clone();
+        let migrations = vec![
+            Migration::new(2).transform(move |dumpy, conn| {
+                runs2.fetch_add(1, Ordering::SeqCst);
+                for mut note in dumpy.all(conn, &String::from("notes"))? {
+                    jedi::set(&["title"], &mut note, &String::from("untitled"))?;
+                    dumpy.store(conn, &String::from("notes"), &note)?;
+                }
+                Ok(())
+            }),
+        ];
+        let schema_v2: Value = jedi::parse(&String::from(r#"{"notes":{"indexes":[{"fields":["boards"]},{"fields":["user_id"]}]}}"#)).unwrap();
+        let dumpy = Dumpy::versioned(schema_v2, 2, migrations);
+        dumpy.init(&conn).unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(index_count(&conn), 3);
+        let notes = dumpy.find(&conn, &String::from("notes"), &String::from("user_id"), &vec![String::from("3443")]).unwrap();
+        assert_eq!(notes.len(), 1);
+        assert_eq!(jedi::get::<String>(&["title"], &notes[0]).unwrap(), "untitled");
+        let notes = dumpy.find(&conn, &String::from("notes"), &String::from("user_boards"), &vec![String::from("3443")]).unwrap();
+        assert_eq!(notes.len(), 0);
+        assert_eq!(dumpy.kv_get(&conn, "dumpy:schema_version").unwrap(), Some(String::from("2")));
+
+        // already migrated, so nothing runs
+        dumpy.init(&conn).unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        // brand new stores don't need migrating
+        let conn2 = Connection::open_in_memory().unwrap();
+        dumpy.init(&conn2).unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(dumpy.kv_get(&conn2, "dumpy:schema_version").unwrap(), Some(String::from("2")));
+
+        // an older schema can't open a newer store
+        let dumpy = Dumpy::versioned(schema_v1, 1, Vec::new());
+        assert!(dumpy.init(&conn).is_err());
+    }
+
+    #[test]
+    fn failed_migrations_roll_back() {
+        let (conn, dumpy) = pre_test();
+        dumpy.init(&conn).unwrap();
+        let note = jedi::parse(&String::from(r#"{"id":"n0mnm","user_id":"3443","boards":["1234","5678"],"body":"this is my note lol"}"#)).unwrap();
+        dumpy.store(&conn, &String::from("notes"), &note).unwrap();
+        assert_eq!(index_count(&conn), 4);
+
+        let schema = jedi::parse(&String::from(r#"{"boards":null,"notes":{"indexes":[{"fields":["user_id"]}]}}"#)).unwrap();
+        let migrations = vec![
+            Migration::new(1).transform(|dumpy, conn| {
+                let board = jedi::parse(&String::from(r#"{"id":"s4nd1","title":"get a job"}"#))?;
+                dumpy.store(conn, &String::from("boards"), &board)?;
+                Err(DError::Msg(String::from("get a job")))
+            }),
+        ];
+        let dumpy = Dumpy::versioned(schema, 1, migrations);
+        assert!(dumpy.init(&conn).is_err());
+        assert_eq!(index_count(&conn), 4);
+        assert_eq!(dumpy.get(&conn, &String::from("boards"), &String::from("s4nd1")).unwrap(), None);
+        assert_eq!(dumpy.kv_get(&conn, "dumpy:schema_version").unwrap(), Some(String::from("0")));
+    }
+
+    #[test]
+    fn reindexes_tables() {
+        let (conn, dumpy) = pre_test();
+        dumpy.init(&conn).unwrap();
+        let note = jedi::parse(&String::from(r#"{"id":"n0mnm","user_id":"3443","boards":["1234","5678"],"body":"this is my note lol"}"#)).unwrap();
+        dumpy.store(&conn, &String::from("notes"), &note).unwrap();
+        conn.execute("DELETE FROM dumpy_index", &[]).unwrap();
+        assert_eq!(index_count(&conn), 0);
+        dumpy.reindex(&conn, &String::from("notes")).unwrap();
+        assert_eq!(index_count(&conn), 4);
+    }
+
+    #[test]
+    fn runs_queries() {
+        let conn = Connection::open_in_memory().unwrap();
+        let schema = jedi::parse(&String::from(r#"{"notes":{"indexes":[{"fields":["space_id"]},{"fields":["mod"]},{"fields":["tags"]}]}}"#)).unwrap();
+        let dumpy = Dumpy::new(schema);
+        dumpy.init(&conn).unwrap();
+        let notes = vec![
+            r#"{"id":"n1","space_id":"s1","mod":103,"tags":["breakfast","pancakes"]}"#,
+            r#"{"id":"n2","space_id":"s1","mod":99,"tags":["lunch"]}"#,
+            r#"{"id":"n3","space_id":"s2","mod":105,"tags":["breakfast"]}"#,
+            r#"{"id":"n4","space_id":"s1","mod":1000,"tags":["bread_crumbs"]}"#,
+            r#"{"id":"n5","space_id":"s1","mod":102,"tags":[]}"#,
+            r#"{"id":"n6","space_id":"s3","mod":"soon","tags":[]}"#,
+        ];
+        for note in notes {
+            dumpy.store(&conn, &String::from("notes"), &jedi::parse(&String::from(note)).unwrap()).unwrap();
+        }
+        fn ids(notes: Vec<Value>) -> Vec<String> {
+            notes.iter().map(|x| jedi::get::<String>(&["id"], x).unwrap()).collect::<Vec<_>>()
+        }
+        let in_s1 = Filter::Eq(String::from("space_id"), vec![IndexVal::from("s1")]);
+
+        // mod is indexed as an integer, so 1000 sorts after 103
+        let query = Query::new("notes")
+            .filter(Filter::And(vec![in_s1.clone(), Filter::Gte(String::from("mod"), IndexVal::Int(102))]))
+            .order_by("mod", Order::Desc);
+        assert_eq!(ids(dumpy.query(&conn, &query).unwrap()), vec!["n4", "n1", "n5"]);
+        assert_eq!(dumpy.count(&conn, &query).unwrap(), 3);
+        let query = query.limit(2).offset(1);
+        assert_eq!(ids(dumpy.query(&conn, &query).unwrap()), vec!["n1", "n5"]);
+        assert_eq!(dumpy.count(&conn, &query).unwrap(), 3);
+
+        let query = Query::new("notes")
+            .filter(Filter::Or(vec![
+                Filter::Between(String::from("mod"), IndexVal::Int(99), IndexVal::Int(102)),
+                Filter::Eq(String::from("space_id"), vec![IndexVal::from("s2")]),
+            ]))
+            .order_by("mod", Order::Asc);
+        assert_eq!(ids(dumpy.query(&conn, &query).unwrap()), vec!["n2", "n5", "n3"]);
+
+        // ranges only look at values of the same type as their bounds (sqlite
+        // sorts all text after all numbers)
+        let query = Query::new("notes").filter(Filter::Gt(String::from("mod"), IndexVal::Int(999)));
+        assert_eq!(ids(dumpy.query(&conn, &query).unwrap()), vec!["n4"]);
+        let query = Query::new("notes").filter(Filter::Lt(String::from("mod"), IndexVal::from("zzz")));
+        assert_eq!(ids(dumpy.query(&conn, &query).unwrap()), vec!["n6"]);
+
+        // prefixes are case sensitive and don't have wildcards
+        let query = Query::new("notes").filter(Filter::Prefix(String::from("tags"), vec![IndexVal::from("brea_")]));
+        assert_eq!(ids(dumpy.query(&conn, &query).unwrap()), Vec::<String>::new());
+        let query = Query::new("notes").filter(Filter::Prefix(String::from("tags"), vec![IndexVal::from("brea*")]));
+        assert_eq!(ids(dumpy.query(&conn, &query).unwrap()), Vec::<String>::new());
+        let query = Query::new("notes").filter(Filter::Prefix(String::from("tags"), vec![IndexVal::from("BREA")]));
+        assert_eq!(ids(dumpy.query(&conn, &query).unwrap()), Vec::<String>::new());
+        let query = Query::new("notes").filter(Filter::Prefix(String::from("tags"), vec![IndexVal::from("brea")]));
+        assert_eq!(ids(dumpy.query(&conn, &query).unwrap()), vec!["n1", "n3", "n4"]);
+
+        let query = Query::new("notes")
+            .filter(Filter::And(vec![in_s1, Filter::Not(Box::new(Filter::Lt(String::from("mod"), IndexVal::Int(103))))]));
+        assert_eq!(ids(dumpy.query(&conn, &query).unwrap()), vec!["n1", "n4"]);
+        assert_eq!(dumpy.count(&conn, &Query::new("notes")).unwrap(), 6);
+    }
+
+    #[test]
+    fn rebuilds_old_indexes() {
+        let conn = Connection::open_in_memory().unwrap();
+        let schema = jedi::parse(&String::from(r#"{"boards":{"indexes":[{"fields":["mod"]},{"fields":["user_id","title"]}]}}"#)).unwrap();
+        let dumpy = Dumpy::new(schema);
+        dumpy.init(&conn).unwrap();
+        let board = jedi::parse(&String::from(r#"{"id":"b1","user_id":51,"mod":1000,"title":"a|b"}"#)).unwrap();
+        dumpy.store(&conn, &String::from("boards"), &board).unwrap();
+
+        // fake a store from before index values were typed
+        conn.execute("DROP TABLE dumpy_index", &[]).unwrap();
+        conn.execute("CREATE TABLE dumpy_index (id INTEGER PRIMARY KEY, table_name VARCHAR(32), index_name VARCHAR(32), vals VARCHAR(256), object_id VARCHAR(64))", &[]).unwrap();
+        conn.execute("INSERT INTO dumpy_index (table_name, index_name, vals, object_id) VALUES ('boards', 'mod', '1000', 'b1')", &[]).unwrap();
+        conn.execute("DELETE FROM dumpy_kv WHERE key = $1", &[&KV_INDEX_FORMAT]).unwrap();
+
+        dumpy.init(&conn).unwrap();
+        assert_eq!(dumpy.kv_get(&conn, KV_INDEX_FORMAT).unwrap(), Some(String::from(INDEX_FORMAT)));
+        let query = Query::new("boards").filter(Filter::Gt(String::from("mod"), IndexVal::Int(999)));
+        assert_eq!(dumpy.count(&conn, &query).unwrap(), 1);
+        let query = Query::new("boards").filter(Filter::Eq(String::from("user_id_title"), vec![IndexVal::Int(51), IndexVal::from("a|b")]));
+        assert_eq!(dumpy.count(&conn, &query).unwrap(), 1);
+        let found = dumpy.find(&conn, &String::from("boards"), &String::from("user_id_title"), &vec![String::from("51")]).unwrap();
+        assert_eq!(found.len(), 1);
+        let num_rows: i64 = conn.query_row("SELECT COUNT(*) FROM dumpy_index", &[], |row| row.get(0)).unwrap();
+        assert_eq!(num_rows, 2);
+    }
 }
@@ -0,0 +1,53 @@
+//! Versioned schema migrations. Dumpy stores the schema version it last ran
+//! with (and the indexes it had at that point) in `dumpy_kv`. When the app
+//! opens a store with a newer version, we reindex any tables whose indexes
+//! changed, drop indexes that aren't in the schema anymore, and run whatever
+//! migrations sit between the old version and the new one.
+
+use ::rusqlite::Connection;
+
+use ::Dumpy;
+use ::error::DResult;
+
+/// Runs against the store during a migration. Gets the (new) dumpy and the
+/// connection, so it can use `all()`/`store()`/etc to rewrite objects.
+pub type Transform = Box<Fn(&Dumpy, &Connection) -> DResult<()> + Send + Sync>;
+
+/// Describes what needs to happen to bring a store up to a given version.
+///
+/// Tables with changed indexes are reindexed automatically, so a migration is
+/// only needed when the data itself changes (or the way it's indexed does
+/// without the index definitions changing).
+pub struct Migration {
+    /// The schema version this migration brings us to
+    pub version: u32,
+    /// Tables that should be reindexed, even if their indexes didn't change
+    pub reindex: Vec<String>,
+    /// Rewrites the data in the store
+    pub transform: Option<Transform>,
+}
+
+impl Migration {
+    /// Create a migration that brings us to `version`
+    pub fn new(version: u32) -> Migration {
+        Migration {
+            version: version,
+            reindex: Vec::new(),
+            transform: None,
+        }
+    }
+
+    /// Force a table to be reindexed when running this migration
+    pub fn reindex(mut self, table: &str) -> Migration {
+        self.reindex.push(String::from(table));
+        self
+    }
+
+    /// Run a data transform when running this migration
+    pub fn transform<F>(mut self, transform: F) -> Migration
+        where F: Fn(&Dumpy, &Connection) -> DResult<()> + Send + Sync + 'static
+    {
+        self.transform = Some(Box::new(transform));
+        self
+    }
+}
@@ -0,0 +1,297 @@
+//! A small query builder for pulling objects out of dumpy using its indexes.
+//!
+//! ```ignore
+//! let query = Query::new("notes")
+//!     .filter(Filter::And(vec![
+//!         Filter::Eq(String::from("space_id"), vec![IndexVal::from(space_id)]),
+//!         Filter::Gt(String::from("mod"), IndexVal::Int(1500000000)),
+//!     ]))
+//!     .order_by("mod", Order::Desc)
+//!     .limit(50)
+//!     .offset(100);
+//! let notes = dumpy.query(&conn, &query)?;
+//! ```
+//!
+//! Single-field indexes compare (and sort) by their typed values, so make sure
+//! to query them with the right type (`IndexVal::Int(51)` will not match a
+//! `"51"` string). Range filters (`Gt`, `Between`, etc) only match values of
+//! the same kind as their bounds: numbers (and bools) or text. Multi-field
+//! indexes compare by their compound (text) key, so a `user_boards` index
+//! might look like `"andrew123|1234"` (see the `index` module).
+
+use ::index::IndexVal;
+
+/// A condition on an object's indexes. For indexes with more than one value
+/// per object (ie, an array field), a filter matches if *any* of the values
+/// match.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    /// The index's values are exactly these
+    Eq(String, Vec<IndexVal>),
+    /// The first fields of a multi-field index are exactly these (ie, match
+    /// on just the `user_id` of a `["user_id", "boards"]` index). With no
+    /// values, matches any object with an entry in the index.
+    Leading(String, Vec<IndexVal>),
+    /// The index's (text) key starts with these values (case sensitive)
+    Prefix(String, Vec<IndexVal>),
+    /// The index's value is greater than this
+    Gt(String, IndexVal),
+    /// The index's value is greater than or equal to this
+    Gte(String, IndexVal),
+    /// The index's value is less than this
+    Lt(String, IndexVal),
+    /// The index's value is less than or equal to this
+    Lte(String, IndexVal),
+    /// The index's value is between these two (inclusive). Both should be the
+    /// same type.
+    Between(String, IndexVal, IndexVal),
+    /// All of these filters match
+    And(Vec<Filter>),
+    /// At least one of these filters matches
+    Or(Vec<Filter>),
+    /// This filter doesn't match
+    Not(Box<Filter>),
+}
+
+/// Build a clause that matches objects with an index value passing `cmp`
+fn index_clause(table: &String, index: &String, cmp: &str, cmp_vals: Vec<IndexVal>, vals: &mut Vec<IndexVal>) -> String {
+    vals.push(IndexVal::Text(table.clone()));
+    vals.push(IndexVal::Text(index.clone()));
+    for val in cmp_vals {
+        vals.push(val);
+    }
+    format!("id IN (SELECT object_id FROM dumpy_index WHERE table_name = ? AND index_name = ?{})", cmp)
+}
+
+/// Build a range comparison that only looks at index values in the same type
+/// class as `val`. sqlite happily compares across types (every number is less
+/// than every string), so otherwise `Gt(mod, Int(5))` would match all the text
+/// values in the index too.
+fn range_cmp(cmp: &str, val: &IndexVal) -> String {
+    let class = match *val {
+        IndexVal::Null => "",
+        IndexVal::Text(..) => " AND typeof(vals) = 'text'",
+        _ => " AND typeof(vals) IN ('integer', 'real')",
+    };
+    format!(" AND vals {}{}", cmp, class)
+}
+
+/// Escape a value we're passing to GLOB (which, unlike LIKE, is case
+/// sensitive) by wrapping its wildcards in brackets
+fn escape_glob(val: &str) -> String {
+    let mut escaped = String::with_capacity(val.len());
+    for c in val.chars() {
+        match c {
+            '*' | '?' | '[' => {
+                escaped.push('[');
+                escaped.push(c);
+                escaped.push(']');
+            }
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+impl Filter {
+    /// Turn this filter into a WHERE clause on `dumpy_objects`, pushing any
+    /// values it needs into `vals`.
+    pub(crate) fn to_sql(&self, table: &String, vals: &mut Vec<IndexVal>) -> String {
+        match *self {
+            Filter::Eq(ref index, ref eq) => index_clause(table, index, " AND vals = ?", vec![IndexVal::key(eq)], vals),
+            Filter::Leading(ref index, ref leading) => {
+                if leading.len() == 0 {
+                    return index_clause(table, index, "", vec![], vals);
+                }
+                let glob = format!("{}|*", escape_glob(&IndexVal::compound_key(leading)));
+                index_clause(table, index, " AND (vals = ? OR vals GLOB ?)", vec![IndexVal::key(leading), IndexVal::Text(glob)], vals)
+            }
+            Filter::Prefix(ref index, ref prefix) => {
+                let glob = format!("{}*", escape_glob(&IndexVal::compound_key(prefix)));
+                index_clause(table, index, " AND vals GLOB ?", vec![IndexVal::Text(glob)], vals)
+            }
+            Filter::Gt(ref index, ref val) => index_clause(table, index, &range_cmp("> ?", val), vec![val.clone()], vals),
+            Filter::Gte(ref index, ref val) => index_clause(table, index, &range_cmp(">= ?", val), vec![val.clone()], vals),
+            Filter::Lt(ref index, ref val) => index_clause(table, index, &range_cmp("< ?", val), vec![val.clone()], vals),
+            Filter::Lte(ref index, ref val) => index_clause(table, index, &range_cmp("<= ?", val), vec![val.clone()], vals),
+            Filter::Between(ref index, ref min, ref max) => index_clause(table, index, &range_cmp("BETWEEN ? AND ?", min), vec![min.clone(), max.clone()], vals),
+            Filter::And(ref filters) | Filter::Or(ref filters) => {
+                let (joiner, empty) = match *self {
+                    Filter::And(..) => (" AND ", "1"),
+                    _ => (" OR ", "0"),
+                };
+                if filters.len() == 0 { return String::from(empty); }
+                let clauses = filters.iter()
+                    .map(|x| x.to_sql(table, vals))
+                    .collect::<Vec<_>>();
+                format!("({})", clauses.join(joiner))
+            }
+            Filter::Not(ref filter) => format!("NOT ({})", filter.to_sql(table, vals)),
+        }
+    }
+}
+
+/// Which direction to sort in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
+/// Describes which objects to pull out of a table, and in what order.
+#[derive(Debug, Clone)]
+pub struct Query {
+    table: String,
+    filter: Option<Filter>,
+    order: Option<(String, Order)>,
+    limit: Option<i32>,
+    offset: Option<i32>,
+}
+
+impl Query {
+    /// Start a query on the given table. With no filters, this grabs every
+    /// object in the table, ordered by id ASC.
+    pub fn new(table: &str) -> Query {
+        Query {
+            table: String::from(table),
+            filter: None,
+            order: None,
+            limit: None,
+            offset: None,
+        }
+    }
+
+    /// Only grab objects matching this filter (use `Filter::And`/`Filter::Or`
+    /// to combine filters)
+    pub fn filter(mut self, filter: Filter) -> Query {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Sort by an index. Objects with the same value are sorted by id ASC.
+    pub fn order_by(mut self, index: &str, order: Order) -> Query {
+        self.order = Some((String::from(index), order));
+        self
+    }
+
+    /// Grab at most this many objects
+    pub fn limit(mut self, limit: i32) -> Query {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Skip this many objects
+    pub fn offset(mut self, offset: i32) -> Query {
+        self.offset = Some(offset);
+        self
+    }
+
+    /// The table we're querying
+    pub fn table(&self) -> &String {
+        &self.table
+    }
+
+    /// Build the WHERE clause for this query
+    fn where_sql(&self, vals: &mut Vec<IndexVal>) -> String {
+        vals.push(IndexVal::Text(self.table.clone()));
+        let mut sql = String::from(" WHERE table_name = ?");
+        if let Some(ref filter) = self.filter {
+            sql.push_str(" AND ");
+            sql.push_str(filter.to_sql(&self.table, vals).as_str());
+        }
+        sql
+    }
+
+    /// Turn this query into SQL that grabs the matching objects' data
+    pub(crate) fn to_sql(&self, vals: &mut Vec<IndexVal>) -> String {
+        let mut sql = String::from("SELECT data FROM dumpy_objects");
+        sql.push_str(self.where_sql(vals).as_str());
+        sql.push_str(" ORDER BY ");
+        if let Some((ref index, order)) = self.order {
+            // objects can have more than one value for an index, so sort on
+            // the lowest (or highest) one
+            let (agg, dir) = match order {
+                Order::Asc => ("MIN", "ASC"),
+                Order::Desc => ("MAX", "DESC"),
+            };
+            sql.push_str(format!("(SELECT {}(vals) FROM dumpy_index WHERE dumpy_index.table_name = ? AND dumpy_index.index_name = ? AND dumpy_index.object_id = dumpy_objects.id) {}, ", agg, dir).as_str());
+            vals.push(IndexVal::Text(self.table.clone()));
+            vals.push(IndexVal::Text(index.clone()));
+        }
+        sql.push_str("id ASC");
+        if self.limit.is_some() || self.offset.is_some() {
+            // sqlite won't take an OFFSET without a LIMIT (-1 being no limit)
+            sql.push_str(" LIMIT ? OFFSET ?");
+            vals.push(IndexVal::Int(self.limit.unwrap_or(-1) as i64));
+            vals.push(IndexVal::Int(self.offset.unwrap_or(0) as i64));
+        }
+        sql
+    }
+
+    /// Turn this query into SQL that counts the matching objects (ignoring
+    /// limit/offset)
+    pub(crate) fn to_count_sql(&self, vals: &mut Vec<IndexVal>) -> String {
+        let mut sql = String::from("SELECT COUNT(*) FROM dumpy_objects");
+        sql.push_str(self.where_sql(vals).as_str());
+        sql
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(vals: &Vec<IndexVal>) -> Vec<String> {
+        vals.iter()
+            .map(|x| match *x {
+                IndexVal::Text(ref x) => x.clone(),
+                IndexVal::Int(ref x) => format!("{}", x),
+                _ => panic!("unexpected value {:?}", x),
+            })
+            .collect::<Vec<_>>()
+    }
+
+    #[test]
+    fn builds_sql() {
+        let query = Query::new("notes")
+            .filter(Filter::And(vec![
+                Filter::Eq(String::from("space_id"), vec![IndexVal::from("s1")]),
+                Filter::Or(vec![
+                    Filter::Prefix(String::from("tags"), vec![IndexVal::from("100*real?[x]")]),
+                    Filter::Not(Box::new(Filter::Gt(String::from("mod"), IndexVal::Int(15)))),
+                ]),
+            ]))
+            .order_by("mod", Order::Desc)
+            .offset(20);
+        let mut vals = Vec::new();
+        let sql = query.to_sql(&mut vals);
+        assert_eq!(sql, "SELECT data FROM dumpy_objects WHERE table_name = ? AND (id IN (SELECT object_id FROM dumpy_index WHERE table_name = ? AND index_name = ? AND vals = ?) AND (id IN (SELECT object_id FROM dumpy_index WHERE table_name = ? AND index_name = ? AND vals GLOB ?) OR NOT (id IN (SELECT object_id FROM dumpy_index WHERE table_name = ? AND index_name = ? AND vals > ? AND typeof(vals) IN ('integer', 'real'))))) ORDER BY (SELECT MAX(vals) FROM dumpy_index WHERE dumpy_index.table_name = ? AND dumpy_index.index_name = ? AND dumpy_index.object_id = dumpy_objects.id) DESC, id ASC LIMIT ? OFFSET ?");
+        assert_eq!(strings(&vals), vec![
+            "notes",
+            "notes", "space_id", "s1",
+            "notes", "tags", "100[*]real[?][[]x]*",
+            "notes", "mod", "15",
+            "notes", "mod",
+            "-1", "20",
+        ]);
+
+        let mut vals = Vec::new();
+        let sql = Query::new("sync")
+            .filter(Filter::Or(vec![
+                Filter::Leading(String::from("sync"), vec![IndexVal::from("file|incoming")]),
+                Filter::Leading(String::from("sync"), vec![]),
+            ]))
+            .to_count_sql(&mut vals);
+        assert_eq!(sql, "SELECT COUNT(*) FROM dumpy_objects WHERE table_name = ? AND (id IN (SELECT object_id FROM dumpy_index WHERE table_name = ? AND index_name = ? AND (vals = ? OR vals GLOB ?)) OR id IN (SELECT object_id FROM dumpy_index WHERE table_name = ? AND index_name = ?))");
+        assert_eq!(strings(&vals), vec![
+            "sync",
+            "sync", "sync", "file|incoming", "file\\|incoming|*",
+            "sync", "sync",
+        ]);
+
+        let mut vals = Vec::new();
+        let sql = Query::new("boards").filter(Filter::Or(vec![])).limit(5).to_count_sql(&mut vals);
+        assert_eq!(sql, "SELECT COUNT(*) FROM dumpy_objects WHERE table_name = ? AND 0");
+        assert_eq!(strings(&vals), vec!["boards"]);
+    }
+}
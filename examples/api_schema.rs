@@ -0,0 +1,35 @@
+//! Writes core's API schema (see `dispatch::schema`) to a file. Run via
+//! `make api-schema`, or:
+//!
+//!   cargo run --example api_schema -- [path]
+//!
+//! `path` defaults to `target/api-schema.json`.
+
+extern crate serde_json;
+extern crate turtl_core;
+
+use ::std::env;
+use ::std::fs;
+use ::std::io::Write;
+use ::std::process;
+
+fn main() {
+    let path = env::args().nth(1).unwrap_or(String::from("target/api-schema.json"));
+    let schema = match turtl_core::api_schema() {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("api_schema -- problem building schema: {}", e);
+            process::exit(1);
+        }
+    };
+    let json = serde_json::to_string_pretty(&schema).expect("api_schema -- problem serializing schema");
+    let res = fs::File::create(&path)
+        .and_then(|mut file| file.write_all(json.as_bytes()));
+    match res {
+        Ok(_) => println!("wrote API schema to {}", path),
+        Err(e) => {
+            eprintln!("api_schema -- problem writing {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+}
@@ -40,13 +40,50 @@ TURTL_EXPORT int32_t TURTL_CONV turtlc_start(const char*, uint8_t);
 // Send a message to the Turtl core. Messages are JSON arrays in the format:
 //   ["<msg id>", "command", [args, ...]]
 //
+// or JSON objects with named arguments:
+//   {"id": "<msg id>", "cmd": "command", "args": {"<arg name>": ..., ...}}
+//
+// The argument names for each command match the names of its positional
+// arguments (ie "user:login" takes "username" and "password"). `args` can be
+// left out for commands that don't take any arguments.
+//
+// The object format can also have a `deadline` field holding the number of
+// milliseconds the request is allowed to run. A request that runs past its
+// deadline returns a `timeout` error. Running requests can be cancelled with:
+//   ["<msg id>", "app:cancel-request", "<msg id to cancel>"]
+// which makes the cancelled request stop and respond with a `cancelled` error.
+// Cancellation (and timing out) is cooperative: a request that finishes before
+// it notices responds with its result as usual.
+//
 // The core will respond using the following format (see `turtlc_recv()`)
 //   {"id": "<msg id>", "e": 1|0, "d": ...}
 //
+// Messages can also be encoded using MessagePack instead of JSON. The core
+// detects the encoding of each message and responds using the same encoding.
+// Events use the `messaging.encoding` config value ("json" or "msgpack"). When
+// using MessagePack, binary data (like file contents) is sent as raw `bin`
+// values instead of base64 strings, and `bin` values can be used in requests
+// anywhere the core expects base64.
+//
 // The <msg id> of the response will match the id that was sent in, this way you
 // know which response is for which message. The `e` param will be 0 on success,
 // 1 if there was an error, and `d` will hold the data for the response (if
 // any).
+//
+// Errors:
+//   When `e` is 1, `d` holds an error object:
+//     {
+//       "type": "validation",     // stable error name
+//       "code": 8,                // stable error number
+//       "retryable": false,       // whether trying again might work
+//       "message": ...,           // a description of what happened (optional)
+//       "details": {...} | null   // structured info (which field, which mid)
+//     }
+//   If the `wrap_errors` config is true, the error is wrapped in an object that
+//   also has the file/line the error came from:
+//     {"file": "...", "line": 12, "err": {<error object>}, "wrapped": true}
+//   The full list of error types is available from the `app:api-schema`
+//   command.
 TURTL_EXPORT int32_t TURTL_CONV turtlc_send(const uint8_t*, size_t);
 
 // -----------------------------------------------------------------------------
@@ -90,6 +127,133 @@ TURTL_EXPORT const uint8_t* TURTL_CONV turtlc_recv(uint8_t, const char*, size_t*
 // error occurred.
 TURTL_EXPORT const uint8_t* TURTL_CONV turtlc_recv_event(uint8_t, size_t*);
 
+// -----------------------------------------------------------------------------
+// turtlc_recv_timeout(msgid, timeout_ms, &msg_len) -> *uint8_t
+//   msgid:
+//     same as `turtlc_recv()`
+//   timeout_ms:
+//     how long (in milliseconds) to wait for a message before giving up
+//   msg_len:
+//     a pointer to a size_t that is filled in with the length (in bytes) of the
+//     message we receive
+//   -> returns a pointer to our message data, or null if no message came in
+//     before the timeout
+// -----------------------------------------------------------------------------
+// Like `turtlc_recv()` but blocks for at most `timeout_ms` milliseconds. A
+// timeout returns null with msg_len = 0, so it can be told apart from an error
+// (null with msg_len > 0). Handy for wrappers that want to notice the core has
+// gone away instead of hanging forever.
+TURTL_EXPORT const uint8_t* TURTL_CONV turtlc_recv_timeout(const char*, uint64_t, size_t*);
+
+// -----------------------------------------------------------------------------
+// turtlc_recv_event_timeout(timeout_ms, &msg_len) -> *uint8_t
+//   timeout_ms:
+//     how long (in milliseconds) to wait for an event before giving up
+//   msg_len:
+//     a pointer to a size_t that is filled in with the length (in bytes) of the
+//     event we receive
+//   -> returns a pointer to our event data, or null if no event came in before
+//     the timeout
+// -----------------------------------------------------------------------------
+// Like `turtlc_recv_event()` but blocks for at most `timeout_ms` milliseconds.
+// Timeouts and errors are told apart the same way as `turtlc_recv_timeout()`.
+TURTL_EXPORT const uint8_t* TURTL_CONV turtlc_recv_event_timeout(uint64_t, size_t*);
+
+// -----------------------------------------------------------------------------
+// turtlc_recv_event_sub(non_block, subscriber, &msg_len) -> *uint8_t
+//   non_block:
+//     if 1, returns immediately if there are no messages to retrieve. if 0,
+//     block until a message becomes available
+//   subscriber:
+//     a null-terminated string holding the subscriber name passed to the
+//     `app:events:subscribe` command
+//   msg_len:
+//     a pointer to a size_t that is filled in with the length (in bytes) of the
+//     event we receive
+//   -> returns a pointer to our event data, or null if no event is available
+//     and we set non_block = 1
+// -----------------------------------------------------------------------------
+// Like `turtlc_recv_event()`, but receives events for a subscriber. Send the
+// core an `app:events:subscribe` command with a subscriber name and a list of
+// filters (["sync:*", "user:login"]) and only events that match the filters
+// will be sent to that subscriber. This lets multiple consumers (UI windows, a
+// websocket bridge) each get the events they care about without stealing
+// events from each other.
+//
+// Note that if a null message is returned but msg_len > 0, this indicates an
+// error occurred.
+TURTL_EXPORT const uint8_t* TURTL_CONV turtlc_recv_event_sub(uint8_t, const char*, size_t*);
+
+// -----------------------------------------------------------------------------
+// turtlc_events_subscribe() -> uint64_t
+//   -> returns a subscription id, or 0 if something went wrong (check
+//      `turtlc_lasterr()`)
+// -----------------------------------------------------------------------------
+// Subscribe to the events broadcast channel. Unlike `turtlc_recv_event()`,
+// where each event goes to whoever grabs it first, every subscription gets its
+// own copy of every event sent after it subscribed. Good for when more than one
+// consumer needs to see all events.
+TURTL_EXPORT uint64_t TURTL_CONV turtlc_events_subscribe();
+
+// -----------------------------------------------------------------------------
+// turtlc_events_recv(sub_id, timeout_ms, &msg_len) -> *uint8_t
+//   sub_id:
+//     the id returned from `turtlc_events_subscribe()`
+//   timeout_ms:
+//     if < 0, block until an event comes in. if 0, return right away. if > 0,
+//     wait at most that many milliseconds
+//   msg_len:
+//     a pointer to a size_t that is filled in with the length (in bytes) of the
+//     event we receive
+//   -> returns a pointer to our event data, or null if no event is available.
+//      free with `turtlc_free()`
+// -----------------------------------------------------------------------------
+// Receive an event from a subscription. A null with msg_len > 0 means an error
+// occurred (such as an unknown sub_id).
+TURTL_EXPORT const uint8_t* TURTL_CONV turtlc_events_recv(uint64_t, int64_t, size_t*);
+
+// -----------------------------------------------------------------------------
+// turtlc_events_unsubscribe(sub_id) -> i32
+//   sub_id:
+//     the id returned from `turtlc_events_subscribe()`
+//   -> returns 0 on success, -1 if there was no such subscription
+// -----------------------------------------------------------------------------
+// End a subscription. Don't unsubscribe while another thread is blocked in
+// `turtlc_events_recv()` (with timeout_ms < 0) on the same subscription: it
+// will stay blocked.
+TURTL_EXPORT int32_t TURTL_CONV turtlc_events_unsubscribe(uint64_t);
+
+// -----------------------------------------------------------------------------
+// turtlc_channels(&msg_len) -> *uint8_t
+//   msg_len:
+//     a pointer to a size_t that is filled in with the length (in bytes) of the
+//     returned data
+//   -> returns a pointer to a JSON array describing the active channels. free
+//      with `turtlc_free()`
+// -----------------------------------------------------------------------------
+// List the channels the core and UI talk over, for diagnosing a stuck UI (for
+// instance, events piling up because nobody is receiving them). Each item looks
+// like:
+//
+//   {
+//     "name": "inproc://turtl-events",
+//     "type": "queue",        // or "broadcast"
+//     "messages": 12,         // waiting to be received
+//     "listeners": 0,         // blocked in a receive call
+//     "sent": 40,
+//     "received": 28,
+//     "dropped": 0,           // thrown out because the channel was full
+//     "capacity": null,       // null if unbounded
+//     "overflow": null,       // "block", "drop_oldest", "drop_newest", "error"
+//     "last_activity": 1508457600000  // ms since the unix epoch
+//   }
+//
+// Broadcast channels add up their subscribers' stats, and their listeners count
+// is the number of subscribers. Channels that are empty and have nobody
+// listening are cleaned up, so they won't show up here. This works even if the
+// core is too stuck to answer the `app:debug:channels` command.
+TURTL_EXPORT const uint8_t* TURTL_CONV turtlc_channels(size_t*);
+
 // -----------------------------------------------------------------------------
 // turtlc_free(msg_ptr, len) -> i32
 //   msg_ptr:
@@ -109,9 +273,11 @@ TURTL_EXPORT int32_t TURTL_CONV turtlc_free(const uint8_t*, size_t);
 //   -> returns a pointer to a null-terminated string of the last error that
 //      occurred (null if no error). Must be freed via `turtlc_free_err`
 // -----------------------------------------------------------------------------
-// Grab the last error that occurred. This is usually used during initialization
-// of the core, and is especially handy when working with platforms that gobble
-// STDOUT (since initialization errors will also be logged). Mainly, you'd call
+// Grab the last error that occurred. The error is a JSON object in the same
+// format as errors returned from commands (see "Errors" under `turtlc_send()`
+// above). This is usually used during initialization of the core, and is
+// especially handy when working with platforms that gobble STDOUT (since
+// initialization errors will also be logged). Mainly, you'd call
 // this after getting a non-zero from `turtlc_start()`/`turtlc_send()`, or if
 // `turtlc_recv()`/`turtlc_recv_event()` return a null with an msg_len > 0.
 TURTL_EXPORT char* TURTL_CONV turtlc_lasterr();
@@ -18,7 +18,7 @@ use ::std::error::Error;
 use ::std::convert::From;
 use ::jedi::{Value, JSONError};
 
-pub use ::cwrap::{send, recv, recv_event, recv_event_nb};
+pub use ::cwrap::{send, recv, recv_event, recv_event_nb, recv_event_sub, recv_event_sub_nb, recv_timeout, recv_event_timeout, events_subscribe, events_recv, events_unsubscribe};
 
 // -----------------------------------------------------------------------------
 // Error object
@@ -79,6 +79,10 @@ pub struct Response {
     d: Value,
 }
 
+/// How long we wait on a response or event before deciding the core is stuck.
+/// Generous, since some of our tests sync with a real server.
+const RECV_TIMEOUT_MS: u64 = 300000;
+
 lazy_static! {
     /// create a static/global CONFIG var, and load it with our config data
     static ref MID: RwLock<u64> = RwLock::new(0);
@@ -129,7 +133,11 @@ pub fn dispatch(args: Value) -> Response {
     msg_args.append(&mut vals);
     let msg = jedi::stringify(&msg_args).unwrap();
     send(msg.as_str());
-    let recv = recv(msg_id.as_str());
+    // if the core dies on us, fail the test instead of hanging forever
+    let recv = match recv_timeout(msg_id.as_str(), Duration::from_millis(RECV_TIMEOUT_MS)) {
+        Some(x) => x,
+        None => panic!("dispatch() -- timed out waiting for response to {}", msg),
+    };
     jedi::parse(&recv).unwrap()
 }
 
@@ -144,7 +152,10 @@ pub fn dispatch_ass(args: Value) -> Value {
 
 pub fn wait_on(evname: &str) -> Value {
     loop {
-        let ev = recv_event();
+        let ev = match recv_event_timeout(Duration::from_millis(RECV_TIMEOUT_MS)) {
+            Some(x) => x,
+            None => panic!("wait_on() -- timed out waiting for event {}", evname),
+        };
         let parsed: Value = jedi::parse(&ev).unwrap();
         let parsed_evname: String = jedi::get(&["e"], &parsed).unwrap();
         if parsed_evname == evname {
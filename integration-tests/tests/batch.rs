@@ -0,0 +1,36 @@
+include!("../src/util.rs");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batch() {
+        let handle = init();
+        let res = dispatch_ass(json!([
+            "batch",
+            [
+                ["ping"],
+                ["no:such:command"],
+                {"cmd": "ping"},
+            ]
+        ]));
+        let results: Vec<Response> = jedi::from_val(res).unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].e, 0);
+        assert_eq!(results[0].d, json!("pong"));
+        assert_eq!(results[1].e, 1);
+        assert_eq!(results[2].e, 0);
+
+        let res = dispatch(json!([
+            "batch",
+            [["ping"], ["no:such:command"], ["ping"]],
+            true
+        ]));
+        assert_eq!(res.e, 1);
+        assert_eq!(jedi::get::<String>(&["err", "type"], &res.d).unwrap(), "batch_aborted");
+        assert_eq!(jedi::get::<usize>(&["err", "index"], &res.d).unwrap(), 1);
+        end(handle);
+    }
+}
+
@@ -0,0 +1,24 @@
+include!("../src/util.rs");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_channels() {
+        let handle = init();
+        dispatch_ass(json!(["ping"]));
+        let events: String = config::get(&["messaging", "events"]).unwrap();
+        let channels = dispatch_ass(json!(["app:debug:channels"]));
+        let channels: Vec<Value> = jedi::from_val(channels).unwrap();
+        let chan = channels.iter()
+            .find(|x| jedi::get::<String>(&["name"], x).unwrap() == events)
+            .expect("events channel missing");
+        assert_eq!(jedi::get::<String>(&["type"], chan).unwrap(), "queue");
+        assert!(jedi::get::<u64>(&["messages"], chan).unwrap() >= 1);
+        assert!(jedi::get::<u64>(&["sent"], chan).unwrap() >= 1);
+        wait_on("pong");
+        end(handle);
+    }
+}
+
@@ -0,0 +1,49 @@
+include!("../src/util.rs");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_broadcasts() {
+        let handle = init();
+        let sub1 = events_subscribe();
+        let sub2 = events_subscribe();
+        dispatch_ass(json!(["ping"]));
+        for sub in &[sub1, sub2] {
+            let ev: Value = jedi::parse(&events_recv(*sub, 10000).unwrap()).unwrap();
+            assert_eq!(jedi::get::<String>(&["e"], &ev).unwrap(), "pong");
+        }
+        // the main channel still gets its copy
+        wait_on("pong");
+        assert!(events_unsubscribe(sub1));
+        assert!(!events_unsubscribe(sub1));
+        dispatch_ass(json!(["ping"]));
+        assert!(events_recv(sub2, 10000).is_some());
+        assert!(events_unsubscribe(sub2));
+        end(handle);
+    }
+
+    #[test]
+    fn event_subscriptions() {
+        let handle = init();
+        let channel: String = jedi::from_val(dispatch_ass(json!(["app:events:subscribe", "test:pong", ["pong"]]))).unwrap();
+        assert!(channel.ends_with(":test:pong"));
+        dispatch_ass(json!(["app:events:subscribe", "test:sync", ["sync:*"]]));
+        dispatch_ass(json!(["ping"]));
+        let ev: Value = jedi::parse(&recv_event_sub("test:pong")).unwrap();
+        assert_eq!(jedi::get::<String>(&["e"], &ev).unwrap(), "pong");
+        assert!(recv_event_sub_nb("test:sync").is_none());
+        // the main channel still gets everything
+        wait_on("pong");
+
+        let removed: bool = jedi::from_val(dispatch_ass(json!(["app:events:unsubscribe", "test:pong"]))).unwrap();
+        assert!(removed);
+        dispatch_ass(json!(["app:events:unsubscribe", "test:sync"]));
+        dispatch_ass(json!(["ping"]));
+        wait_on("pong");
+        assert!(recv_event_sub_nb("test:pong").is_none());
+        end(handle);
+    }
+}
+
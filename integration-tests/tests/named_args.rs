@@ -0,0 +1,27 @@
+include!("../src/util.rs");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn named_args() {
+        let handle = init();
+        let msg = jedi::stringify(&json!({
+            "id": "named-1",
+            "cmd": "app:api:set-endpoint",
+            "args": {"endpoint": "http://api.turtl.dev:8181"},
+        })).unwrap();
+        send(msg.as_str());
+        let res: Response = jedi::parse(&recv("named-1")).unwrap();
+        assert_eq!(res.e, 0);
+
+        let msg = jedi::stringify(&json!({"id": "named-2", "cmd": "ping"})).unwrap();
+        send(msg.as_str());
+        let res: Response = jedi::parse(&recv("named-2")).unwrap();
+        assert_eq!(res.e, 0);
+        assert_eq!(res.d, json!("pong"));
+        end(handle);
+    }
+}
+
@@ -0,0 +1,19 @@
+include!("../src/util.rs");
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recv_with_timeout() {
+        let handle = init();
+        // nobody is going to answer this one
+        assert!(recv_timeout("no-such-mid", Duration::from_millis(50)).is_none());
+        let msg = jedi::stringify(&json!({"id": "timeout-1", "cmd": "ping"})).unwrap();
+        send(msg.as_str());
+        let res: Response = jedi::parse(&recv_timeout("timeout-1", Duration::from_secs(10)).unwrap()).unwrap();
+        assert_eq!(res.d, json!("pong"));
+        end(handle);
+    }
+}
+
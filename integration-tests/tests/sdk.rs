@@ -0,0 +1,31 @@
+include!("../src/util.rs");
+
+extern crate turtl_sdk;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::turtl_sdk::{Client, Future, Request, SdkError};
+
+    #[test]
+    fn sdk_requests_and_events() {
+        let client = Client::start(json!({
+            "data_folder": ":memory:",
+            "logging": {"file": null},
+        })).unwrap();
+        let mut events = client.events();
+
+        // several requests in flight at once, each gets its own response
+        let pings = (0..5).map(|_| client.ping()).collect::<Vec<_>>();
+        for ping in pings {
+            assert_eq!(ping.wait().unwrap(), "pong");
+        }
+        assert_eq!(events.wait_for("pong", Duration::from_secs(10)).unwrap(), Value::Null);
+
+        match client.request(Request::new("lol:no-such-command")).wait() {
+            Err(SdkError::Core(err)) => assert_eq!(err.ty, "missing_command"),
+            x => panic!("expected a missing_command error, got {:?}", x),
+        }
+        client.shutdown().unwrap();
+    }
+}
@@ -0,0 +1,357 @@
+//! The SDK's main object. Starts the core, sends requests into it, and hands
+//! each response back to whoever asked for it.
+
+use ::std::collections::HashMap;
+use ::std::sync::{Arc, Mutex};
+use ::std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use ::std::thread;
+use ::std::time::{Duration, Instant};
+
+use ::cwrap;
+use ::futures::{Async, Future, Poll};
+use ::futures::sync::oneshot;
+use ::jedi::{self, DeserializeOwned, Value};
+
+use ::error::{CoreError, SdkError, SdkResult};
+use ::events::EventStream;
+use ::models::{Board, Note, Profile, SearchResults, Space, User};
+use ::request::{Query, Request};
+
+/// How long a request waits on the core by default
+const DEFAULT_TIMEOUT_MS: usize = 300000;
+
+/// How long the core gets to answer our first ping when starting
+const START_TIMEOUT_MS: u64 = 30000;
+
+/// How often the response pump checks for expired requests
+const PUMP_POLL_MS: u64 = 100;
+
+/// A boxed future that resolves to a typed response
+pub type SdkFuture<T> = Box<Future<Item = T, Error = SdkError> + Send>;
+
+/// A request waiting on its response
+struct Pending {
+    tx: oneshot::Sender<SdkResult<Value>>,
+    deadline: Instant,
+    cmd: String,
+}
+
+type PendingMap = Arc<Mutex<HashMap<String, Pending>>>;
+
+/// The response to a request. Resolves to the response's data, or an error if
+/// the core failed the request (or never answered).
+pub struct Response {
+    rx: oneshot::Receiver<SdkResult<Value>>,
+}
+
+impl Future for Response {
+    type Item = Value;
+    type Error = SdkError;
+
+    fn poll(&mut self) -> Poll<Value, SdkError> {
+        match self.rx.poll() {
+            Ok(Async::Ready(Ok(val))) => Ok(Async::Ready(val)),
+            Ok(Async::Ready(Err(e))) => Err(e),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            // the pump went away without answering
+            Err(_) => Err(SdkError::Disconnected),
+        }
+    }
+}
+
+/// Turn a response from the core into a result
+fn response_result(res: &Value) -> SdkResult<Value> {
+    let e = jedi::get_opt::<i64>(&["e"], res).unwrap_or(1);
+    let d = jedi::get_opt::<Value>(&["d"], res).unwrap_or(Value::Null);
+    if e == 0 {
+        Ok(d)
+    } else {
+        Err(SdkError::Core(CoreError::from_value(d)))
+    }
+}
+
+/// Grab responses from the core and resolve the matching requests, failing
+/// any that have waited too long
+fn pump(pending: PendingMap, running: Arc<AtomicBool>) {
+    while running.load(Ordering::SeqCst) {
+        if let Some(msg) = cwrap::recv_timeout("", Duration::from_millis(PUMP_POLL_MS)) {
+            let res: Value = match jedi::parse(&msg) {
+                Ok(x) => x,
+                Err(_) => continue,
+            };
+            let id = match jedi::get_opt::<String>(&["id"], &res) {
+                Some(x) => x,
+                None => continue,
+            };
+            let waiting = pending.lock().unwrap().remove(&id);
+            if let Some(waiting) = waiting {
+                // if nobody's listening anymore, that's fine
+                let _ = waiting.tx.send(response_result(&res));
+            }
+        }
+
+        let now = Instant::now();
+        let mut guard = pending.lock().unwrap();
+        let expired = guard.iter()
+            .filter(|&(_, x)| x.deadline <= now)
+            .map(|(id, _)| id.clone())
+            .collect::<Vec<_>>();
+        for id in expired {
+            if let Some(waiting) = guard.remove(&id) {
+                let _ = waiting.tx.send(Err(SdkError::Timeout(waiting.cmd)));
+            }
+        }
+    }
+    // dropping the senders fails everyone still waiting with Disconnected
+    pending.lock().unwrap().clear();
+}
+
+/// Runs the core and talks to it
+pub struct Client {
+    pending: PendingMap,
+    next_id: AtomicUsize,
+    timeout_ms: AtomicUsize,
+    running: Arc<AtomicBool>,
+    core_handle: Option<thread::JoinHandle<()>>,
+    pump_handle: Option<thread::JoinHandle<()>>,
+}
+
+impl Client {
+    /// Start the core with the given config (same as what you'd pass to
+    /// `turtlc_start`) and wait for it to be ready.
+    ///
+    /// Responses are matched to requests by id, so `reqres_append_mid` is
+    /// always turned off. Events come in over broadcast subscriptions (see
+    /// `events()`), so `events_unfiltered` defaults to off as well.
+    pub fn start(config: Value) -> SdkResult<Client> {
+        let mut config = match config {
+            Value::Null => json!({}),
+            x => x,
+        };
+        jedi::set(&["messaging", "reqres_append_mid"], &mut config, &false)?;
+        if jedi::get_opt::<bool>(&["messaging", "events_unfiltered"], &config).is_none() {
+            jedi::set(&["messaging", "events_unfiltered"], &mut config, &false)?;
+        }
+        let config_str = jedi::stringify(&config)?;
+
+        let pending: PendingMap = Arc::new(Mutex::new(HashMap::new()));
+        let running = Arc::new(AtomicBool::new(true));
+        let core_handle = cwrap::init(config_str.as_str());
+        let pending2 = pending.clone();
+        let running2 = running.clone();
+        let pump_handle = thread::spawn(move || pump(pending2, running2));
+        let client = Client {
+            pending: pending,
+            next_id: AtomicUsize::new(1),
+            timeout_ms: AtomicUsize::new(DEFAULT_TIMEOUT_MS),
+            running: running,
+            core_handle: Some(core_handle),
+            pump_handle: Some(pump_handle),
+        };
+        client.request_timeout(Request::new("ping"), Duration::from_millis(START_TIMEOUT_MS)).wait()?;
+        Ok(client)
+    }
+
+    /// Set how long requests wait on the core before failing with a timeout
+    pub fn set_timeout(&self, timeout: Duration) {
+        let ms = (timeout.as_secs() * 1000) + (timeout.subsec_nanos() / 1_000_000) as u64;
+        self.timeout_ms.store(ms as usize, Ordering::SeqCst);
+    }
+
+    /// Send a request into the core, waiting at most `timeout` for the
+    /// response
+    pub fn request_timeout(&self, req: Request, timeout: Duration) -> Response {
+        let (tx, rx) = oneshot::channel();
+        let id = format!("{}", self.next_id.fetch_add(1, Ordering::SeqCst));
+        let cmd = String::from(req.cmd());
+        match req.into_message(id.as_str()) {
+            Ok(msg) => {
+                let waiting = Pending {
+                    tx: tx,
+                    deadline: Instant::now() + timeout,
+                    cmd: cmd,
+                };
+                self.pending.lock().unwrap().insert(id, waiting);
+                cwrap::send(msg.as_str());
+            }
+            Err(e) => { let _ = tx.send(Err(e)); }
+        }
+        Response { rx: rx }
+    }
+
+    /// Send a request into the core
+    pub fn request(&self, req: Request) -> Response {
+        let timeout = Duration::from_millis(self.timeout_ms.load(Ordering::SeqCst) as u64);
+        self.request_timeout(req, timeout)
+    }
+
+    /// Send a request and deserialize its response
+    pub fn call<T>(&self, req: Request) -> SdkFuture<T>
+        where T: DeserializeOwned + Send + 'static
+    {
+        Box::new(self.request(req).and_then(|val| Ok(jedi::from_val(val)?)))
+    }
+
+    /// Send a request we only care about the success of
+    fn call_unit(&self, req: Request) -> SdkFuture<()> {
+        Box::new(self.request(req).map(|_| ()))
+    }
+
+    /// Start listening for events
+    pub fn events(&self) -> EventStream {
+        EventStream::new()
+    }
+
+    /// Check that the core is alive
+    pub fn ping(&self) -> SdkFuture<String> {
+        self.call(Request::new("ping"))
+    }
+
+    /// Log a user in
+    pub fn login(&self, username: &str, password: &str) -> SdkFuture<User> {
+        self.call(Request::new("user:login").arg(&username).arg(&password))
+    }
+
+    /// Create a new account (and log into it)
+    pub fn join(&self, username: &str, password: &str) -> SdkFuture<User> {
+        self.call(Request::new("user:join").arg(&username).arg(&password))
+    }
+
+    /// Log the current user out
+    pub fn logout(&self) -> SdkFuture<()> {
+        self.call_unit(Request::new("user:logout"))
+    }
+
+    /// Delete the current user's account, locally and on the server
+    pub fn delete_account(&self) -> SdkFuture<()> {
+        self.call_unit(Request::new("user:delete-account"))
+    }
+
+    /// Start syncing with the server. Call after logging in.
+    pub fn sync_start(&self) -> SdkFuture<()> {
+        self.call_unit(Request::new("sync:start"))
+    }
+
+    /// Grab the current user, their spaces, boards, and invites
+    pub fn profile(&self) -> SdkFuture<Profile> {
+        self.call(Request::new("profile:load"))
+    }
+
+    /// Save a model via `profile:sync:model`
+    fn sync_model<T>(&self, action: &str, ty: &str, data: &T) -> SdkFuture<T>
+        where T: ::jedi::Serialize + DeserializeOwned + Send + 'static
+    {
+        self.call(Request::new("profile:sync:model").arg(&action).arg(&ty).arg(data))
+    }
+
+    fn sync_delete(&self, ty: &str, id: &str) -> SdkFuture<()> {
+        let req = Request::new("profile:sync:model").arg(&"delete").arg(&ty).arg(&json!({"id": id}));
+        self.call_unit(req)
+    }
+
+    /// Create a space
+    pub fn add_space(&self, space: &Space) -> SdkFuture<Space> {
+        self.sync_model("add", "space", space)
+    }
+
+    /// Save changes to a space
+    pub fn edit_space(&self, space: &Space) -> SdkFuture<Space> {
+        self.sync_model("edit", "space", space)
+    }
+
+    /// Delete a space (and everything in it)
+    pub fn delete_space(&self, space_id: &str) -> SdkFuture<()> {
+        self.sync_delete("space", space_id)
+    }
+
+    /// Create a board
+    pub fn add_board(&self, board: &Board) -> SdkFuture<Board> {
+        self.sync_model("add", "board", board)
+    }
+
+    /// Save changes to a board
+    pub fn edit_board(&self, board: &Board) -> SdkFuture<Board> {
+        self.sync_model("edit", "board", board)
+    }
+
+    /// Delete a board
+    pub fn delete_board(&self, board_id: &str) -> SdkFuture<()> {
+        self.sync_delete("board", board_id)
+    }
+
+    /// Create a note. To attach a file, set `file` and put the base64
+    /// contents in `file.extra["filedata"] = {"data": ...}`.
+    pub fn add_note(&self, note: &Note) -> SdkFuture<Note> {
+        self.sync_model("add", "note", note)
+    }
+
+    /// Save changes to a note
+    pub fn edit_note(&self, note: &Note) -> SdkFuture<Note> {
+        self.sync_model("edit", "note", note)
+    }
+
+    /// Delete a note
+    pub fn delete_note(&self, note_id: &str) -> SdkFuture<()> {
+        self.sync_delete("note", note_id)
+    }
+
+    /// Load notes by id
+    pub fn get_notes(&self, note_ids: &[&str]) -> SdkFuture<Vec<Note>> {
+        self.call(Request::new("profile:get-notes").arg(&note_ids))
+    }
+
+    /// Search notes
+    pub fn find_notes(&self, query: &Query) -> SdkFuture<SearchResults> {
+        self.call(Request::new("profile:find-notes").arg(query))
+    }
+
+    /// Export the profile. The export is passed to `import()` as-is.
+    pub fn export(&self) -> SdkFuture<Value> {
+        self.call(Request::new("profile:export"))
+    }
+
+    /// Import an export. `mode` is one of `restore`, `replace`, or `full`.
+    pub fn import(&self, mode: &str, export: &Value) -> SdkFuture<Value> {
+        self.call(Request::new("profile:import").arg(&mode).arg(export))
+    }
+
+    /// Shut the core down and wait for it to stop
+    pub fn shutdown(mut self) -> SdkResult<()> {
+        self.stop()
+    }
+
+    fn stop(&mut self) -> SdkResult<()> {
+        let core_handle = match self.core_handle.take() {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+        let res = self.request_timeout(Request::new("app:shutdown"), Duration::from_millis(START_TIMEOUT_MS)).wait();
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(pump_handle) = self.pump_handle.take() {
+            let _ = pump_handle.join();
+        }
+        res?;
+        core_handle.join().map_err(|_| SdkError::Msg(String::from("the core panicked")))
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_responses() {
+        let res = response_result(&json!({"id": "1", "e": 0, "d": "pong"})).unwrap();
+        assert_eq!(res, json!("pong"));
+        let err = response_result(&json!({"id": "2", "e": 1, "d": {"type": "missing_command", "code": 5}})).unwrap_err();
+        assert_eq!(err.core_type(), Some("missing_command"));
+        let err = response_result(&json!({"id": "3"})).unwrap_err();
+        assert_eq!(err.core_type(), Some("generic"));
+    }
+}
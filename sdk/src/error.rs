@@ -0,0 +1,125 @@
+use ::std::fmt;
+
+use ::jedi::{self, JSONError, Value};
+
+/// An error the core sent back in response to a request. See `ERROR_CODES`
+/// in the core's `error.rs` for the full list of types.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoreError {
+    /// The error's type (`not_found`, `validation`, etc)
+    pub ty: String,
+    /// The error's numeric code
+    pub code: u32,
+    /// Whether trying again later might work
+    pub retryable: bool,
+    /// A human-readable message, if the error has one
+    pub message: Option<String>,
+    /// The error exactly as the core sent it
+    pub raw: Value,
+}
+
+impl CoreError {
+    /// Build an error from the `d` field of a failed response. Handles errors
+    /// wrapped with file/line info (the `wrap_errors` config).
+    pub fn from_value(val: Value) -> CoreError {
+        let inner = {
+            let mut cur = &val;
+            while jedi::get_opt::<bool>(&["wrapped"], cur).unwrap_or(false) {
+                match jedi::walk(&["err"], cur) {
+                    Ok(x) => cur = x,
+                    Err(_) => break,
+                }
+            }
+            cur.clone()
+        };
+        CoreError {
+            ty: jedi::get_opt(&["type"], &inner).unwrap_or(String::from("generic")),
+            code: jedi::get_opt(&["code"], &inner).unwrap_or(1),
+            retryable: jedi::get_opt(&["retryable"], &inner).unwrap_or(false),
+            message: jedi::get_opt(&["message"], &inner),
+            raw: val,
+        }
+    }
+}
+
+impl fmt::Display for CoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.message {
+            Some(ref msg) => write!(f, "{} ({}): {}", self.ty, self.code, msg),
+            None => write!(f, "{} ({})", self.ty, self.code),
+        }
+    }
+}
+
+quick_error! {
+    #[derive(Debug)]
+    /// The SDK's error object
+    pub enum SdkError {
+        Core(err: CoreError) {
+            description("the core returned an error")
+            display("core error: {}", err)
+        }
+        JSON(err: JSONError) {
+            cause(err)
+            description("JSON error")
+            display("JSON error: {}", err)
+        }
+        Timeout(what: String) {
+            description("timed out")
+            display("timed out waiting on {}", what)
+        }
+        Disconnected {
+            description("the client was shut down before the core responded")
+            display("the client was shut down before the core responded")
+        }
+        Msg(msg: String) {
+            description(msg)
+            display("{}", msg)
+        }
+    }
+}
+
+impl From<JSONError> for SdkError {
+    fn from(err: JSONError) -> SdkError {
+        SdkError::JSON(err)
+    }
+}
+
+impl SdkError {
+    /// If this error came from the core, grab its type
+    pub fn core_type(&self) -> Option<&str> {
+        match *self {
+            SdkError::Core(ref err) => Some(err.ty.as_str()),
+            _ => None,
+        }
+    }
+}
+
+pub type SdkResult<T> = Result<T, SdkError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unwraps_core_errors() {
+        let val: Value = jedi::parse(&String::from(r#"{"type":"not_found","code":6,"retryable":false,"message":"no such note","details":null}"#)).unwrap();
+        let err = CoreError::from_value(val.clone());
+        assert_eq!(err.ty, "not_found");
+        assert_eq!(err.code, 6);
+        assert_eq!(err.message, Some(String::from("no such note")));
+        assert_eq!(format!("{}", err), "not_found (6): no such note");
+
+        let wrapped = json!({"file": "src/lib.rs", "line": 12, "wrapped": true, "err": {
+            "file": "src/models/note.rs", "line": 44, "wrapped": true, "err": val,
+        }});
+        let err2 = CoreError::from_value(wrapped.clone());
+        assert_eq!(err2.ty, "not_found");
+        assert_eq!(err2.raw, wrapped);
+
+        let err3 = CoreError::from_value(json!("lol"));
+        assert_eq!(err3.ty, "generic");
+        assert_eq!(err3.code, 1);
+        assert_eq!(SdkError::Core(err3).core_type(), Some("generic"));
+    }
+}
@@ -0,0 +1,69 @@
+//! Streams events from the core. Each `EventStream` is its own broadcast
+//! subscription, so any number of them can run side by side without stealing
+//! events from each other.
+
+use ::std::time::{Duration, Instant};
+
+use ::cwrap;
+use ::jedi;
+
+use ::error::{SdkError, SdkResult};
+use ::models::Event;
+
+/// A stream of events from the core. Use it as an iterator (which blocks
+/// between events) or call `next_timeout()`.
+pub struct EventStream {
+    sub_id: u64,
+}
+
+impl EventStream {
+    /// Start getting events. Only events sent after this is called show up.
+    pub fn new() -> EventStream {
+        EventStream {
+            sub_id: cwrap::events_subscribe(),
+        }
+    }
+
+    fn parse(msg: String) -> SdkResult<Event> {
+        Ok(jedi::parse(&msg)?)
+    }
+
+    /// Wait at most `timeout` for the next event
+    pub fn next_timeout(&mut self, timeout: Duration) -> SdkResult<Option<Event>> {
+        let ms = (timeout.as_secs() * 1000) + (timeout.subsec_nanos() / 1_000_000) as u64;
+        match cwrap::events_recv(self.sub_id, ms as i64) {
+            Some(x) => Ok(Some(EventStream::parse(x)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Wait for an event with the given name, skipping any others. Returns
+    /// the event's data.
+    pub fn wait_for(&mut self, name: &str, timeout: Duration) -> SdkResult<jedi::Value> {
+        let start = Instant::now();
+        loop {
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                return Err(SdkError::Timeout(format!("event {}", name)));
+            }
+            match self.next_timeout(timeout - elapsed)? {
+                Some(ev) => if ev.e == name { return Ok(ev.d); },
+                None => {}
+            }
+        }
+    }
+}
+
+impl Iterator for EventStream {
+    type Item = SdkResult<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        cwrap::events_recv(self.sub_id, -1).map(EventStream::parse)
+    }
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        cwrap::events_unsubscribe(self.sub_id);
+    }
+}
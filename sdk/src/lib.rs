@@ -0,0 +1,42 @@
+//! A typed Rust client for the Turtl core, built on `cwrap`. Instead of
+//! assembling message arrays and picking apart JSON responses by hand:
+//!
+//!     let client = Client::start(json!({"data_folder": ":memory:"}))?;
+//!     let user = client.login("slippy@turtlapp.com", "password").wait()?;
+//!     client.sync_start().wait()?;
+//!     let profile = client.profile().wait()?;
+//!     let note = Note {
+//!         space_id: profile.spaces[0].id.clone().unwrap(),
+//!         title: Some(String::from("Pancakes")),
+//!         ..Note::default()
+//!     };
+//!     let saved = client.add_note(&note).wait()?;
+//!
+//! Every request returns a future that resolves once the core answers, so many
+//! requests can be in flight at once. Events come in via `Client::events()`.
+//!
+//! Since the core is a global, only run one `Client` per process.
+
+extern crate cwrap;
+extern crate futures;
+extern crate jedi;
+#[macro_use]
+extern crate quick_error;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
+
+mod client;
+mod error;
+mod events;
+pub mod models;
+mod request;
+
+pub use ::client::{Client, Response, SdkFuture};
+pub use ::error::{CoreError, SdkError, SdkResult};
+pub use ::events::EventStream;
+pub use ::models::{Board, Event, File, Note, Profile, SearchResults, Space, User};
+pub use ::request::{Query, Request};
+pub use ::futures::Future;
@@ -0,0 +1,209 @@
+//! Typed versions of the objects the core hands back. These only pull out the
+//! fields a client usually cares about: anything else the core sends is kept
+//! in `extra` so editing a model and sending it back doesn't lose data.
+
+use ::jedi::{Map, Value};
+
+/// The core sends some ids (`user_id`, mainly) as numbers and others as
+/// strings. We always want strings.
+mod id {
+    use ::serde::de::{self, Deserializer, Visitor};
+    use ::std::fmt;
+
+    struct StringOrInt;
+    impl<'de> Visitor<'de> for StringOrInt {
+        type Value = String;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("string or integer")
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<String, E> {
+            Ok(String::from(value))
+        }
+
+        fn visit_i64<E: de::Error>(self, value: i64) -> Result<String, E> {
+            Ok(format!("{}", value))
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<String, E> {
+            Ok(format!("{}", value))
+        }
+
+        fn visit_unit<E: de::Error>(self) -> Result<String, E> {
+            Ok(String::new())
+        }
+    }
+
+    pub fn deserialize<'de, D>(des: D) -> Result<String, D::Error>
+        where D: Deserializer<'de>
+    {
+        des.deserialize_any(StringOrInt)
+    }
+}
+
+/// The logged-in user
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct User {
+    #[serde(deserialize_with = "id::deserialize")]
+    pub id: String,
+    #[serde(default)]
+    pub username: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub settings: Option<Value>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// A space, which holds boards and notes and can be shared
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Space {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default, deserialize_with = "id::deserialize")]
+    pub user_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    #[serde(default)]
+    pub members: Vec<Value>,
+    #[serde(default)]
+    pub invites: Vec<Value>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// A board, which groups notes in a space
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Board {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub space_id: String,
+    #[serde(default, deserialize_with = "id::deserialize")]
+    pub user_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Info about a file attached to a note
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct File {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub ty: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// A note
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Note {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub space_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub board_id: Option<String>,
+    #[serde(default, deserialize_with = "id::deserialize")]
+    pub user_id: String,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub ty: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+    #[serde(default)]
+    pub has_file: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<File>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Everything `profile:load` gives us
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Profile {
+    pub user: User,
+    #[serde(default)]
+    pub spaces: Vec<Space>,
+    #[serde(default)]
+    pub boards: Vec<Board>,
+    #[serde(default)]
+    pub invites: Vec<Value>,
+}
+
+/// The results of a `profile:find-notes` search
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct SearchResults {
+    #[serde(default)]
+    pub notes: Vec<Note>,
+    /// Tags on the matched notes, and how many notes have each
+    #[serde(default)]
+    pub tags: Vec<(String, i32)>,
+    #[serde(default)]
+    pub total: i32,
+}
+
+/// An event sent by the core
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Event {
+    /// The event's name
+    pub e: String,
+    /// The event's data
+    #[serde(default)]
+    pub d: Value,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::jedi;
+
+    #[test]
+    fn parses_profiles() {
+        let profile: Profile = jedi::from_val(json!({
+            "user": {"id": 51, "username": "slippy@turtlapp.com", "storage": 104857600},
+            "spaces": [{"id": "s1", "user_id": 51, "title": "Personal", "members": [{"user_id": 51}]}],
+            "boards": [{"id": "b1", "space_id": "s1", "user_id": "51", "title": "Recipes"}],
+            "invites": [],
+        })).unwrap();
+        assert_eq!(profile.user.id, "51");
+        assert_eq!(jedi::get::<i64>(&["storage"], &Value::Object(profile.user.extra.clone())).unwrap(), 104857600);
+        assert_eq!(profile.spaces[0].user_id, "51");
+        assert_eq!(profile.spaces[0].members.len(), 1);
+        assert_eq!(profile.boards[0].title, Some(String::from("Recipes")));
+    }
+
+    #[test]
+    fn keeps_unknown_fields() {
+        let val = json!({
+            "id": "n1",
+            "space_id": "s1",
+            "user_id": 51,
+            "type": "text",
+            "title": "Pancakes",
+            "tags": ["breakfast"],
+            "keys": [{"s": "s1", "k": "abc"}],
+            "mod": 1234,
+        });
+        let mut note: Note = jedi::from_val(val).unwrap();
+        assert_eq!(note.ty, Some(String::from("text")));
+        note.title = Some(String::from("Better pancakes"));
+        let back = jedi::to_val(&note).unwrap();
+        assert_eq!(jedi::get::<String>(&["title"], &back).unwrap(), "Better pancakes");
+        assert_eq!(jedi::get::<String>(&["keys", "0", "k"], &back).unwrap(), "abc");
+        assert_eq!(jedi::get::<i64>(&["mod"], &back).unwrap(), 1234);
+        assert_eq!(jedi::get::<String>(&["user_id"], &back).unwrap(), "51");
+    }
+}
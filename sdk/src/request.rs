@@ -0,0 +1,174 @@
+//! Builders for the messages we send into the core
+
+use ::jedi::{self, JSONError, Serialize, Value};
+
+use ::error::SdkResult;
+
+/// A command to send to the core, along with its (positional) arguments:
+///
+///     Request::new("profile:sync:model").arg(&"add").arg(&"note").arg(&note)
+pub struct Request {
+    cmd: String,
+    args: Vec<Value>,
+    /// Holds the first argument we failed to serialize, so building a request
+    /// can be chained and the error comes out when it's sent
+    err: Option<JSONError>,
+}
+
+impl Request {
+    /// Start a request for the given command
+    pub fn new(cmd: &str) -> Request {
+        Request {
+            cmd: String::from(cmd),
+            args: Vec::new(),
+            err: None,
+        }
+    }
+
+    /// Add an argument
+    pub fn arg<T: Serialize>(mut self, arg: &T) -> Request {
+        match jedi::to_val(arg) {
+            Ok(x) => self.args.push(x),
+            Err(e) => if self.err.is_none() { self.err = Some(e); },
+        }
+        self
+    }
+
+    /// The command this request runs
+    pub fn cmd(&self) -> &str {
+        self.cmd.as_str()
+    }
+
+    /// Turn this request into a message the core understands
+    pub fn into_message(self, id: &str) -> SdkResult<String> {
+        let Request { cmd, args, err } = self;
+        if let Some(e) = err {
+            return Err(From::from(e));
+        }
+        let mut msg = vec![Value::String(String::from(id)), Value::String(cmd)];
+        msg.extend(args);
+        Ok(jedi::stringify(&msg)?)
+    }
+}
+
+/// A note search, for `profile:find-notes`
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Query {
+    space_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    boards: Vec<String>,
+    tags: Vec<String>,
+    exclude_tags: Vec<String>,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    ty: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    has_file: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sort: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sort_direction: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    page: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    per_page: Option<i32>,
+}
+
+impl Query {
+    /// Search the notes in a space
+    pub fn new(space_id: &str) -> Query {
+        Query {
+            space_id: String::from(space_id),
+            ..Query::default()
+        }
+    }
+
+    /// Full-text search
+    pub fn text(mut self, text: &str) -> Query {
+        self.text = Some(String::from(text));
+        self
+    }
+
+    /// Only notes in this board (can be called more than once)
+    pub fn board(mut self, board_id: &str) -> Query {
+        self.boards.push(String::from(board_id));
+        self
+    }
+
+    /// Only notes with this tag (can be called more than once)
+    pub fn tag(mut self, tag: &str) -> Query {
+        self.tags.push(String::from(tag));
+        self
+    }
+
+    /// Skip notes with this tag
+    pub fn exclude_tag(mut self, tag: &str) -> Query {
+        self.exclude_tags.push(String::from(tag));
+        self
+    }
+
+    /// Only notes of this type (text, link, image, file, password)
+    pub fn note_type(mut self, ty: &str) -> Query {
+        self.ty = Some(String::from(ty));
+        self
+    }
+
+    /// Only notes with (or without) a file
+    pub fn has_file(mut self, has_file: bool) -> Query {
+        self.has_file = Some(has_file);
+        self
+    }
+
+    /// Sort by a field (`created`, `mod`, etc) in a direction (`asc`/`desc`)
+    pub fn sort(mut self, field: &str, direction: &str) -> Query {
+        self.sort = Some(String::from(field));
+        self.sort_direction = Some(String::from(direction));
+        self
+    }
+
+    /// Grab a page of results
+    pub fn page(mut self, page: i32, per_page: i32) -> Query {
+        self.page = Some(page);
+        self.per_page = Some(per_page);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::std::collections::HashMap;
+
+    #[test]
+    fn builds_messages() {
+        let msg = Request::new("profile:sync:model")
+            .arg(&"add")
+            .arg(&"board")
+            .arg(&json!({"title": "Recipes"}))
+            .into_message("12")
+            .unwrap();
+        assert_eq!(msg, r#"["12","profile:sync:model","add","board",{"title":"Recipes"}]"#);
+
+        // maps with non-string keys can't be JSON
+        let mut bad = HashMap::new();
+        bad.insert(vec![1], 2);
+        let req = Request::new("ping").arg(&bad).arg(&"fine");
+        assert_eq!(req.cmd(), "ping");
+        assert!(req.into_message("13").is_err());
+    }
+
+    #[test]
+    fn builds_queries() {
+        let qry = Query::new("s1").text("pancakes").board("b1").tag("breakfast").page(2, 10);
+        let val = jedi::to_val(&qry).unwrap();
+        assert_eq!(val, json!({
+            "space_id": "s1",
+            "text": "pancakes",
+            "boards": ["b1"],
+            "tags": ["breakfast"],
+            "exclude_tags": [],
+            "page": 2,
+            "per_page": 10,
+        }));
+    }
+}
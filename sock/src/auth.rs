@@ -0,0 +1,132 @@
+//! Keeps random local processes (or web pages) from talking to our core. A
+//! token is generated each time sock starts and written to `sock.token` in the
+//! data folder, readable only by the user running sock. The first message a
+//! client sends must be
+//!
+//!     {"auth": "<token>"}
+//!
+//! and nothing gets forwarded to the core until it is.
+
+use ::std::env;
+use ::std::fs::{self, OpenOptions};
+use ::std::io::{self, Write};
+use ::std::path::{Path, PathBuf};
+
+use ::config;
+use ::jedi::{self, Value};
+use ::rand::{Rng, OsRng};
+
+/// The name of the file we write our token to
+const TOKEN_FILE: &'static str = "sock.token";
+
+/// Generate a new random token
+pub fn generate_token() -> io::Result<String> {
+    let mut rng = OsRng::new()?;
+    let mut bytes = [0u8; 32];
+    rng.fill_bytes(&mut bytes);
+    Ok(bytes.iter().map(|x| format!("{:02x}", x)).collect::<Vec<_>>().join(""))
+}
+
+/// Figure out where our token lives. This is `data_folder`, unless the core is
+/// running in memory, in which case we use the temp dir.
+pub fn token_path() -> PathBuf {
+    let data_folder: String = match config::get(&["data_folder"]) {
+        Ok(x) => x,
+        Err(_) => String::from(":memory:"),
+    };
+    let folder = if data_folder == ":memory:" {
+        env::temp_dir()
+    } else {
+        PathBuf::from(data_folder)
+    };
+    folder.join(TOKEN_FILE)
+}
+
+/// Write our token out to a file only we can read
+pub fn write_token(path: &Path, token: &str) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    // don't inherit the permissions of an old token file
+    match fs::remove_file(path) {
+        Ok(_) => {}
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    let mut opts = OpenOptions::new();
+    opts.write(true).create_new(true);
+    restrict(&mut opts);
+    let mut file = opts.open(path)?;
+    file.write_all(token.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn restrict(opts: &mut OpenOptions) {
+    use ::std::os::unix::fs::OpenOptionsExt;
+    opts.mode(0o600);
+}
+
+#[cfg(not(unix))]
+fn restrict(_opts: &mut OpenOptions) {}
+
+/// Compare two strings without bailing out at the first difference, so the
+/// time it takes doesn't give away how much of the token was right
+fn constant_eq(a: &str, b: &str) -> bool {
+    let a = a.as_bytes();
+    let b = b.as_bytes();
+    if a.len() != b.len() { return false; }
+    a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Check if a client's message is a valid auth message
+pub fn check(token: &str, msg: &str) -> bool {
+    let parsed: Value = match jedi::parse(&String::from(msg)) {
+        Ok(x) => x,
+        Err(_) => return false,
+    };
+    match jedi::get_opt::<String>(&["auth"], &parsed) {
+        Some(x) => constant_eq(token, x.as_str()),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::std::io::Read;
+
+    #[test]
+    fn checks_tokens() {
+        let token = generate_token().unwrap();
+        assert_eq!(token.len(), 64);
+        assert!(token != generate_token().unwrap());
+        assert!(check(&token, &format!(r#"{{"auth":"{}"}}"#, token)));
+        assert!(!check(&token, r#"{"auth":"nope"}"#));
+        assert!(!check(&token, r#"["0","ping"]"#));
+        assert!(!check(&token, "lol"));
+        assert!(!check(&token, &format!(r#"{{"auth":"{}0"}}"#, token)));
+    }
+
+    #[test]
+    fn writes_tokens() {
+        let path = env::temp_dir().join("sock-test").join(TOKEN_FILE);
+        write_token(&path, "abc").unwrap();
+        write_token(&path, "def").unwrap();
+        let mut contents = String::new();
+        fs::File::open(&path).unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "def");
+        assert_private(&path);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(unix)]
+    fn assert_private(path: &Path) {
+        use ::std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[cfg(not(unix))]
+    fn assert_private(_path: &Path) {}
+}
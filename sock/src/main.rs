@@ -1,125 +1,238 @@
+extern crate config;
 extern crate cwrap;
 extern crate fern;
+extern crate jedi;
 #[macro_use]
 extern crate log;
+extern crate rand;
 extern crate time;
 extern crate websocket;
 
+mod auth;
 mod logger;
+mod routing;
 
+use ::std::collections::HashMap;
+use ::std::env;
+use ::std::net::TcpStream;
+use ::std::process;
+use ::std::sync::{Arc, RwLock, mpsc};
+use ::std::sync::atomic::{AtomicUsize, Ordering};
 use ::std::thread;
-use ::websocket::{Message, OwnedMessage};
-use ::websocket::sync::Server;
 use ::std::time::Duration;
-use ::std::env;
-use ::std::sync::{Arc, RwLock};
+use ::websocket::OwnedMessage;
+use ::websocket::sync::Server;
+use ::websocket::sync::server::upgrade::Upgrade;
+
+/// Where we listen if `TURTL_SOCK_BIND` isn't set
+const DEFAULT_BIND: &'static str = "127.0.0.1:7472";
+
+/// How long a new connection has to authenticate before we hang up on it
+const AUTH_TIMEOUT_SECS: u64 = 10;
+
+/// Holds a way to send messages to each connected client, by connection id
+type Clients = Arc<RwLock<HashMap<u32, mpsc::Sender<OwnedMessage>>>>;
 
-/// Go to sleeeeep
-fn sleep(millis: u64) {
-    thread::sleep(Duration::from_millis(millis));
+/// Send a message to a client. Returns false if the client has gone away.
+fn send_to(clients: &Clients, conn_id: u32, msg: OwnedMessage) -> bool {
+    let guard = clients.read().unwrap();
+    match guard.get(&conn_id) {
+        Some(tx) => tx.send(msg).is_ok(),
+        None => false,
+    }
 }
 
-fn drain_channels() {
+/// Grab responses from the core and hand them to the client that asked for
+/// them
+fn pump_responses(clients: Clients) {
     loop {
-        if cwrap::recv_event_nb().is_none() { break; }
+        let msg = cwrap::recv("");
+        match routing::route_response(msg.as_str()) {
+            Ok((conn_id, res)) => {
+                info!("* core -> ui {} (res: {})", conn_id, res.len());
+                if !send_to(&clients, conn_id, OwnedMessage::Text(res)) {
+                    debug!("* connection {} is gone, dropping response", conn_id);
+                }
+            }
+            Err(e) => warn!("* could not route response from core: {}", e),
+        }
     }
+}
+
+/// Grab events from the core and send them to every client
+fn pump_events(clients: Clients) {
+    let sub_id = cwrap::events_subscribe();
     loop {
-        if cwrap::recv_nb("").is_none() { break; }
+        let ev = match cwrap::events_recv(sub_id, -1) {
+            Some(x) => x,
+            None => continue,
+        };
+        let guard = clients.read().unwrap();
+        info!("* core -> ui x{} (ev: {})", guard.len(), ev.len());
+        for tx in guard.values() {
+            // if this fails, the client is on its way out anyway
+            let _ = tx.send(OwnedMessage::Text(ev.clone()));
+        }
     }
 }
 
+/// Send a message from a client into the core
+fn forward(conn_id: u32, msg: &str, tx: &mpsc::Sender<OwnedMessage>) {
+    info!("* ui {} -> core ({})", conn_id, msg.len());
+    match routing::namespace_request(conn_id, msg) {
+        Ok(x) => cwrap::send(x.as_str()),
+        Err(e) => {
+            warn!("* bad message from connection {}: {}", conn_id, e);
+            let _ = tx.send(OwnedMessage::Text(routing::error_response(e.as_str())));
+        }
+    }
+}
+
+/// Run a client connection until it closes
+fn handle_connection(conn_id: u32, upgrade: Upgrade<TcpStream>, clients: Clients, token: Arc<String>) {
+    let mut client = match upgrade.accept() {
+        Ok(x) => x,
+        Err((_, e)) => {
+            warn!("* connection {}: handshake failed: {}", conn_id, e);
+            return;
+        }
+    };
+
+    // nothing goes to the core until the client proves it can read our token
+    let timeout = Some(Duration::from_secs(AUTH_TIMEOUT_SECS));
+    if let Err(e) = client.stream_ref().set_read_timeout(timeout) {
+        warn!("* connection {}: could not set auth timeout: {}", conn_id, e);
+        return;
+    }
+    let authed = match client.recv_message() {
+        Ok(OwnedMessage::Text(x)) => auth::check(token.as_str(), x.as_str()),
+        Ok(OwnedMessage::Binary(x)) => auth::check(token.as_str(), String::from_utf8_lossy(&x[..]).as_ref()),
+        _ => false,
+    };
+    if !authed {
+        warn!("* connection {}: failed to authenticate, closing", conn_id);
+        let _ = client.send_message(&OwnedMessage::Text(routing::error_response("authentication failed")));
+        let _ = client.send_message(&OwnedMessage::Close(None));
+        return;
+    }
+    if let Err(e) = client.stream_ref().set_read_timeout(None) {
+        warn!("* connection {}: could not clear auth timeout: {}", conn_id, e);
+        return;
+    }
+
+    let (mut reader, mut writer) = match client.split() {
+        Ok(x) => x,
+        Err(e) => {
+            warn!("* connection {}: could not split socket: {}", conn_id, e);
+            return;
+        }
+    };
+    info!("* new connection! {}", conn_id);
+
+    // everything going out to the client goes through this channel, so
+    // responses, events, and pongs don't trip over each other
+    let (tx, rx) = mpsc::channel::<OwnedMessage>();
+    let write_handle = thread::spawn(move || {
+        for msg in rx {
+            let closing = match msg { OwnedMessage::Close(_) => true, _ => false };
+            match writer.send_message(&msg) {
+                Ok(_) => {}
+                Err(e) => {
+                    debug!("* connection {}: write failed: {}", conn_id, e);
+                    break;
+                }
+            }
+            if closing { break; }
+        }
+    });
+    let _ = tx.send(OwnedMessage::Text(String::from(r#"{"e":"messaging:ready","d":true}"#)));
+    clients.write().unwrap().insert(conn_id, tx.clone());
+
+    for msg in reader.incoming_messages() {
+        match msg {
+            Ok(OwnedMessage::Text(x)) => forward(conn_id, x.as_str(), &tx),
+            Ok(OwnedMessage::Binary(x)) => {
+                match String::from_utf8(x) {
+                    Ok(x) => forward(conn_id, x.as_str(), &tx),
+                    Err(_) => {
+                        let _ = tx.send(OwnedMessage::Text(routing::error_response("binary messages must be UTF8 JSON")));
+                    }
+                }
+            }
+            Ok(OwnedMessage::Ping(x)) => { let _ = tx.send(OwnedMessage::Pong(x)); }
+            Ok(OwnedMessage::Close(_)) => {
+                let _ = tx.send(OwnedMessage::Close(None));
+                break;
+            }
+            Ok(OwnedMessage::Pong(_)) => {}
+            Err(e) => {
+                debug!("* connection {}: read failed: {}", conn_id, e);
+                break;
+            }
+        }
+    }
+
+    // stop sending this client anything. any responses still on their way for
+    // it get dropped when they come in.
+    clients.write().unwrap().remove(&conn_id);
+    drop(tx);
+    let _ = write_handle.join();
+    info!("* connection ended! {}", conn_id);
+}
+
 pub fn main() {
     logger::setup_logger();
 
     if env::var("TURTL_CONFIG_FILE").is_err() {
         env::set_var("TURTL_CONFIG_FILE", "../config.yaml");
     }
-    let handle = cwrap::init(r#"{"messaging":{"reqres_append_mid":false}}"#);
-    let server = Server::bind("127.0.0.1:7472").unwrap();
-    info!("* sock server bound, listening");
-    let conn_id: Arc<RwLock<u32>> = Arc::new(RwLock::new(0));
-    macro_rules! inc_conn_id {
-        ($conn:expr) => {
-            {
-                let mut guard = $conn.write().unwrap();
-                *guard += 1;
-                *guard
-            }
-        }
+    // we only need the config to know where to put our token
+    if let Err(e) = config::load_config(None) {
+        error!("* could not load config: {}", e);
+        process::exit(1);
     }
-    macro_rules! get_conn_id {
-        ($conn:expr) => {
-            {
-                let guard = $conn.read().unwrap();
-                *guard
-            }
+    let token = match auth::generate_token() {
+        Ok(x) => Arc::new(x),
+        Err(e) => {
+            error!("* could not generate auth token: {}", e);
+            process::exit(1);
         }
+    };
+    let token_path = auth::token_path();
+    if let Err(e) = auth::write_token(&token_path, token.as_str()) {
+        error!("* could not write auth token to {}: {}", token_path.display(), e);
+        process::exit(1);
     }
-    for connection in server.filter_map(Result::ok) {
-        let cid = conn_id.clone();
-        let this_conn_id = inc_conn_id!(cid);
-        thread::spawn(move || {
-            info!("* new connection! {}", get_conn_id!(cid));
-            let mut client = connection.accept().unwrap();
-            client.set_nonblocking(true).unwrap();
-            drain_channels();
-            cwrap::send(r#"["0","sync:shutdown",false]"#);
-            cwrap::send(r#"["0","user:logout",false]"#);
-            cwrap::recv("");
-            cwrap::recv("");
-            drain_channels();
-            client.send_message(&Message::text(r#"{"e":"messaging:ready","d":true}"#)).unwrap();
-            loop {
-                // make sure that if our stupid lazy connection has been left
-                // behind that it is forgotten forever and ever and ever and
-                // ever and ever.
-                if this_conn_id != get_conn_id!(cid) { break; }
-
-                let msg_res = client.recv_message();
-                match msg_res {
-                    Ok(msg) => {
-                        match msg {
-                            OwnedMessage::Close(_) => { break; }
-                            OwnedMessage::Binary(x) => {
-                                info!("* ui -> core ({})", x.len());
-                                let msg_str = String::from_utf8(x).unwrap();
-                                cwrap::send(msg_str.as_str());
-                            }
-                            OwnedMessage::Text(x) => {
-                                info!("* ui -> core ({})", x.len());
-                                cwrap::send(x.as_str());
-                            }
-                            _ => {}
-                        }
-                    }
-                    Err(_) => {
-                    }
-                }
+    info!("* auth token written to {}", token_path.display());
+    let bind = match env::var("TURTL_SOCK_BIND") {
+        Ok(x) => x,
+        Err(_) => String::from(DEFAULT_BIND),
+    };
+    // responses carry their ids so we can route them, and events come to us
+    // over a broadcast subscription so there's no need to also send them out
+    // on the main events channel
+    let handle = cwrap::init(r#"{"messaging":{"reqres_append_mid":false,"events_unfiltered":false}}"#);
+    let server = match Server::bind(bind.as_str()) {
+        Ok(x) => x,
+        Err(e) => {
+            error!("* could not bind to {}: {}", bind, e);
+            process::exit(1);
+        }
+    };
+    info!("* sock server bound to {}, listening", bind);
 
-                let msg_turtl = cwrap::recv_nb("");
-                match msg_turtl {
-                    Some(x) => {
-                        info!("* core -> ui (ev: {})", x.len());
-                        //println!("---\n{}", x);
-                        client.send_message(&Message::text(x)).unwrap();
-                    }
-                    None => {}
-                }
+    let clients: Clients = Arc::new(RwLock::new(HashMap::new()));
+    let clients2 = clients.clone();
+    thread::spawn(move || pump_responses(clients2));
+    let clients2 = clients.clone();
+    thread::spawn(move || pump_events(clients2));
 
-                let msg_turtl = cwrap::recv_event_nb();
-                match msg_turtl {
-                    Some(x) => {
-                        info!("* core -> ui (res: {})", x.len());
-                        //println!("---\n{}", x);
-                        client.send_message(&Message::text(x)).unwrap();
-                    }
-                    None => {}
-                }
-                sleep(10);
-            }
-            info!("* connection ended! {}", this_conn_id);
-        });
+    let conn_counter = AtomicUsize::new(0);
+    for upgrade in server.filter_map(Result::ok) {
+        let conn_id = (conn_counter.fetch_add(1, Ordering::SeqCst) + 1) as u32;
+        let clients = clients.clone();
+        let token = token.clone();
+        thread::spawn(move || handle_connection(conn_id, upgrade, clients, token));
     }
     handle.join().unwrap();
 }
-
@@ -0,0 +1,110 @@
+//! Lets any number of websocket clients share one core. Each client's request
+//! ids are prefixed with its connection id on the way in (`"12"` from
+//! connection 3 becomes `"3:12"`), and responses coming out of the core are
+//! sent to whichever connection the prefix points to, with the prefix stripped
+//! back off. Clients never see each other's responses, even if they happen to
+//! use the same ids.
+//!
+//! Only JSON messages can be routed (we have no use for msgpack here).
+
+use ::jedi::{self, Value};
+
+/// Turn a client's message id into a namespaced one
+fn namespace_mid(conn_id: u32, mid: &Value) -> Result<Value, String> {
+    let mid = match *mid {
+        Value::String(ref x) => x.clone(),
+        Value::Number(ref x) => format!("{}", x),
+        _ => return Err(String::from("message id must be a string or a number")),
+    };
+    Ok(Value::String(format!("{}:{}", conn_id, mid)))
+}
+
+/// Prefix a request's message id with the connection it came in on. Requests
+/// are either `[mid, cmd, args...]` or `{"id": mid, "cmd": cmd, ...}`.
+pub fn namespace_request(conn_id: u32, msg: &str) -> Result<String, String> {
+    let mut req: Value = jedi::parse(&String::from(msg))
+        .map_err(|e| format!("message is not valid JSON: {}", e))?;
+    match req {
+        Value::Array(ref mut parts) if parts.len() > 0 => {
+            parts[0] = namespace_mid(conn_id, &parts[0])?;
+        }
+        Value::Object(ref mut obj) => {
+            let mid = match obj.get("id") {
+                Some(x) => namespace_mid(conn_id, x)?,
+                None => return Err(String::from("message is missing its `id`")),
+            };
+            obj.insert(String::from("id"), mid);
+        }
+        _ => return Err(String::from("message must be an array or an object")),
+    }
+    jedi::stringify(&req).map_err(|e| format!("problem encoding message: {}", e))
+}
+
+/// Figure out which connection a response from the core belongs to, and strip
+/// our prefix off of its id. Returns the connection id and the response to
+/// send it.
+pub fn route_response(msg: &str) -> Result<(u32, String), String> {
+    let mut res: Value = jedi::parse(&String::from(msg))
+        .map_err(|e| format!("response is not valid JSON: {}", e))?;
+    let conn_id = {
+        let obj = match res {
+            Value::Object(ref mut x) => x,
+            _ => return Err(String::from("response is not an object")),
+        };
+        let mid = match obj.get("id") {
+            Some(&Value::String(ref x)) => x.clone(),
+            _ => return Err(String::from("response has no id")),
+        };
+        let split = match mid.find(':') {
+            Some(x) => x,
+            None => return Err(format!("response id {} has no connection prefix", mid)),
+        };
+        let conn_id: u32 = mid[0..split].parse()
+            .map_err(|_| format!("response id {} has a bad connection prefix", mid))?;
+        obj.insert(String::from("id"), Value::String(String::from(&mid[(split + 1)..])));
+        conn_id
+    };
+    let res = jedi::stringify(&res).map_err(|e| format!("problem encoding response: {}", e))?;
+    Ok((conn_id, res))
+}
+
+/// Build an error response we can send back to a client whose message we
+/// couldn't make sense of
+pub fn error_response(err: &str) -> String {
+    let mut errobj = jedi::Map::new();
+    errobj.insert(String::from("type"), Value::String(String::from("bad_value")));
+    errobj.insert(String::from("message"), Value::String(format!("sock: {}", err)));
+    let mut res = jedi::Map::new();
+    res.insert(String::from("e"), Value::from(1));
+    res.insert(String::from("d"), Value::Object(errobj));
+    match jedi::stringify(&Value::Object(res)) {
+        Ok(x) => x,
+        Err(_) => String::from(r#"{"e":1,"d":{"type":"bad_value"}}"#),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn namespaces_requests() {
+        assert_eq!(namespace_request(3, r#"["12","ping"]"#).unwrap(), r#"["3:12","ping"]"#);
+        assert_eq!(namespace_request(3, r#"[12,"ping"]"#).unwrap(), r#"["3:12","ping"]"#);
+        assert_eq!(namespace_request(4, r#"{"id":"a:b","cmd":"ping"}"#).unwrap(), r#"{"cmd":"ping","id":"4:a:b"}"#);
+        assert!(namespace_request(3, r#"{"cmd":"ping"}"#).is_err());
+        assert!(namespace_request(3, r#"[]"#).is_err());
+        assert!(namespace_request(3, r#"[null,"ping"]"#).is_err());
+        assert!(namespace_request(3, "ping").is_err());
+    }
+
+    #[test]
+    fn routes_responses() {
+        let (conn_id, res) = route_response(r#"{"id":"4:a:b","e":0,"d":"pong"}"#).unwrap();
+        assert_eq!(conn_id, 4);
+        assert_eq!(res, r#"{"d":"pong","e":0,"id":"a:b"}"#);
+        assert!(route_response(r#"{"id":"12","e":0,"d":"pong"}"#).is_err());
+        assert!(route_response(r#"{"id":"x:12","e":0,"d":"pong"}"#).is_err());
+        assert!(route_response(r#"{"e":0,"d":"pong"}"#).is_err());
+    }
+}
@@ -16,6 +16,7 @@ pub use ::crypto::low::{
     from_hex,
     to_base64,
     from_base64,
+    hmac,
     HMAC_KEYLEN,
     KEYGEN_SALT_LEN,
     KEYGEN_OPS_DEFAULT,
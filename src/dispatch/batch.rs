@@ -0,0 +1,143 @@
+//! Runs a list of dispatch requests in order, under one storage transaction.
+//! This lets the UI do things like bulk-edit a bunch of notes' tags and have
+//! the whole thing either happen or not happen.
+//!
+//! Each sub-request is either positional:
+//!
+//!     ["<command>", arg1, arg2, ...]
+//!
+//! or named:
+//!
+//!     {"cmd": "<command>", "args": {...}}
+//!
+//! The result is an array of `{"e": 1|0, "d": ...}` objects, one for each
+//! sub-request, in the same order they were sent in.
+//!
+//! Note that only the local db is transactional. If a batch is rolled back,
+//! anything a command did to in-memory state or sent to the UI stays done.
+//!
+//! While a batch runs it has the db to itself: other commands and the sync
+//! threads wait until it's done (see `Turtl::db_transaction()`). This is also
+//! why commands that wait on the sync threads, or that open/close the user db,
+//! can't be batched.
+
+use ::std::sync::Arc;
+
+use ::jedi::{self, Value};
+
+use ::error::{TResult, TError};
+use ::turtl::Turtl;
+use ::messaging::{self, Response};
+use ::dispatch::{registry, inflight};
+
+/// Commands that can't run inside of a batch
+const UNBATCHABLE: &'static [&'static str] = &[
+    "user:login",
+    "user:login-from-token",
+    "user:login-from-saved",
+    "user:join",
+    "user:join-migrate",
+    "user:logout",
+    "user:change-password",
+    "user:delete-account",
+    "app:wipe-user-data",
+    "app:wipe-app-data",
+    "app:shutdown",
+    "sync:start",
+    "sync:shutdown",
+];
+
+/// Split a batch item into its (cmd, args) parts.
+fn parse_item(item: Value) -> TResult<(String, Value)> {
+    match item {
+        Value::Object(mut obj) => {
+            let cmd: String = match obj.remove("cmd").map(|x| jedi::from_val(x)) {
+                Some(Ok(x)) => x,
+                _ => return TErr!(TError::MissingField(String::from("batch: missing cmd (cmd)"))),
+            };
+            let args = match obj.remove("args") {
+                Some(Value::Null) | None => json!({}),
+                Some(x) => x,
+            };
+            Ok((cmd, args))
+        }
+        Value::Array(mut parts) => {
+            let cmd: String = match parts.get(0).map(|x| jedi::from_val(x.clone())) {
+                Some(Ok(x)) => x,
+                _ => return TErr!(TError::MissingField(String::from("batch: missing cmd (0)"))),
+            };
+            Ok((cmd, Value::Array(parts.split_off(1))))
+        }
+        _ => TErr!(TError::BadValue(String::from("batch: items must be an array or object"))),
+    }
+}
+
+/// Run a single batch item
+fn run_item(turtl: &Turtl, item: Value) -> TResult<Value> {
+    let (cmd, args) = parse_item(item)?;
+    if cmd == "batch" {
+        return TErr!(TError::BadValue(String::from("batch: batches cannot be nested")));
+    }
+    if UNBATCHABLE.contains(&cmd.as_str()) {
+        return TErr!(TError::BadValue(format!("batch: {} can't run in a batch", cmd)));
+    }
+    info!("dispatch::batch -- {}", cmd);
+    registry::call(&cmd, turtl, args)
+}
+
+/// Run a batch of requests. If `abort_on_error` is true, the first failed
+/// request rolls back the whole batch and we return an error pointing at the
+/// request that failed. Otherwise, every request is run and the batch is
+/// committed regardless of failures.
+pub fn run(turtl: &Turtl, requests: Vec<Value>, abort_on_error: bool) -> TResult<Value> {
+    let has_db = lock!(turtl.db).is_some();
+    if has_db {
+        turtl.db_transaction(|| run_items(turtl, requests, abort_on_error))
+    } else {
+        // nothing to roll back
+        run_items(turtl, requests, abort_on_error)
+    }
+}
+
+/// Run each item in a batch, in order
+fn run_items(turtl: &Turtl, requests: Vec<Value>, abort_on_error: bool) -> TResult<Value> {
+    let mut results: Vec<Response> = Vec::with_capacity(requests.len());
+    for (i, item) in requests.into_iter().enumerate() {
+        // a cancelled batch gets rolled back
+        inflight::checkpoint()?;
+        match run_item(turtl, item) {
+            Ok(val) => results.push(Response::new(0, val)),
+            Err(e) => {
+                if abort_on_error {
+                    return TErr!(TError::BatchAborted(i, Arc::new(e.shed())));
+                }
+                results.push(Response::new(1, messaging::error_val(&e)));
+            }
+        }
+    }
+    Ok(jedi::to_val(&results)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_items() {
+        let (cmd, args) = parse_item(json!(["profile:sync:model", "add", "note", {"title": "hi"}])).unwrap();
+        assert_eq!(cmd, "profile:sync:model");
+        assert_eq!(args, json!(["add", "note", {"title": "hi"}]));
+
+        let (cmd, args) = parse_item(json!({"cmd": "profile:sync:model", "args": {"action": "delete", "type": "note", "data": {"id": "1234"}}})).unwrap();
+        assert_eq!(cmd, "profile:sync:model");
+        assert_eq!(args, json!({"action": "delete", "type": "note", "data": {"id": "1234"}}));
+
+        let (cmd, args) = parse_item(json!(["ping"])).unwrap();
+        assert_eq!(cmd, "ping");
+        assert_eq!(args, json!([]));
+
+        assert!(parse_item(json!([])).is_err());
+        assert!(parse_item(json!({"args": {}})).is_err());
+        assert!(parse_item(json!("ping")).is_err());
+    }
+}
@@ -0,0 +1,718 @@
+//! Defines all of core's built-in dispatch commands, along with the argument
+//! structs each one takes. Arguments are listed in the order they are expected
+//! in the positional message format.
+
+use ::carrier;
+use ::jedi::{self, Value};
+use ::error::{TResult, TError};
+use ::config;
+use ::util::{self, logger};
+use ::search::Query;
+use ::profile::{Profile, Export, ImportMode};
+use ::models::model::Model;
+use ::models::protected::Protected;
+use ::models::user::User;
+use ::models::space::Space;
+use ::models::space_member::SpaceMember;
+use ::models::note::Note;
+use ::models::invite::{Invite, InviteRequest};
+use ::models::file::FileData;
+use ::models::sync_record::{SyncAction, SyncType, SyncRecord};
+use ::models::feedback::Feedback;
+use ::clippo::{self, CustomParser};
+use ::sync::sync_model;
+use ::messaging;
+use ::wire;
+use ::migrate;
+use ::crypto::Key;
+use ::dispatch::registry::{self, Registry, NoArgs};
+use ::dispatch::{batch, inflight, schema};
+
+/// Used for `#[serde(default = ...)]` on args that default to true
+fn default_true() -> bool { true }
+
+/// Turn a carrier channel listing into something we can send to the UI.
+/// `last_activity` is in milliseconds since the unix epoch.
+fn channels_val(channels: Vec<carrier::Channel>) -> Value {
+    let list = channels.into_iter().map(|chan| {
+        let last_activity = chan.stats.last_activity
+            .and_then(|x| x.duration_since(::std::time::UNIX_EPOCH).ok())
+            .map(|x| (x.as_secs() * 1000) + (x.subsec_nanos() / 1_000_000) as u64);
+        json!({
+            "name": chan.name,
+            "type": if chan.broadcast { "broadcast" } else { "queue" },
+            "messages": chan.stats.messages,
+            "listeners": chan.stats.listeners,
+            "sent": chan.stats.sent,
+            "received": chan.stats.received,
+            "dropped": chan.stats.dropped,
+            "capacity": chan.stats.capacity,
+            "overflow": chan.stats.overflow.map(|x| x.to_string()),
+            "last_activity": last_activity,
+        })
+    }).collect::<Vec<_>>();
+    Value::Array(list)
+}
+
+/// Event subscriptions get everything unless told otherwise
+fn default_filters() -> Vec<String> { vec![String::from("*")] }
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LoginArgs {
+    username: String,
+    password: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TokenArgs {
+    token: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SavedLoginArgs {
+    user_id: String,
+    key: Key,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MigrateArgs {
+    old_username: String,
+    old_password: String,
+    new_username: String,
+    new_password: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LogoutArgs {
+    #[serde(default = "default_true")]
+    clear_cookie: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ChangePasswordArgs {
+    current_username: String,
+    current_password: String,
+    new_username: String,
+    new_password: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LoginTokenArgs {
+    #[serde(default)]
+    confirmation: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EmailArgs {
+    email: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct EndpointArgs {
+    endpoint: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LogArgs {
+    lines: i32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BatchArgs {
+    requests: Vec<Value>,
+    #[serde(default)]
+    abort_on_error: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CancelArgs {
+    mid: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SubscribeArgs {
+    subscriber: String,
+    #[serde(default = "default_filters")]
+    filters: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UnsubscribeArgs {
+    subscriber: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SyncShutdownArgs {
+    #[serde(default = "default_true")]
+    wait: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SyncItemArgs {
+    sync_id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SyncModelArgs {
+    action: SyncAction,
+    #[serde(rename = "type")]
+    ty: SyncType,
+    data: Value,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SpaceUserArgs {
+    space_id: String,
+    user_id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SpaceArgs {
+    space_id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MemberArgs {
+    member: SpaceMember,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InviteRequestArgs {
+    invite: InviteRequest,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InviteArgs {
+    invite: Invite,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SpaceInviteArgs {
+    space_id: String,
+    invite_id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AcceptInviteArgs {
+    invite: Invite,
+    #[serde(default)]
+    passphrase: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct InviteIdArgs {
+    invite_id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NoteIdsArgs {
+    note_ids: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NoteIdArgs {
+    note_id: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct QueryArgs {
+    query: Query,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ImportArgs {
+    mode: ImportMode,
+    export: Export,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FeedbackArgs {
+    feedback: Feedback,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ClipArgs {
+    url: String,
+    custom_parsers: Vec<CustomParser>,
+}
+
+/// Send the "clear your cookies" event to the UI, logging on failure
+fn clear_cookie_event() {
+    messaging::ui_event("user:logout:clear-cookie", &Value::Null)
+        .unwrap_or_else(|e| error!("dispatch::commands -- error sending ui event: {}", e));
+}
+
+/// Register all of core's commands into the given registry
+pub fn register(reg: &mut Registry) {
+    reg.add("user:login", |turtl, args: LoginArgs| {
+        turtl.login(args.username, args.password)?;
+        let user_guard = lockr!(turtl.user);
+        user_guard.data()
+    });
+    reg.add("user:login-from-token", |turtl, args: TokenArgs| {
+        turtl.login_token(args.token)?;
+        let user_guard = lockr!(turtl.user);
+        user_guard.data()
+    });
+    reg.add("user:login-from-saved", |turtl, args: SavedLoginArgs| {
+        let token = User::restore_login(args.user_id, args.key)?;
+        turtl.login_token(token)?;
+        let user_guard = lockr!(turtl.user);
+        user_guard.data()
+    });
+    reg.add("user:join", |turtl, args: LoginArgs| {
+        turtl.join(args.username, args.password)?;
+        let user_guard = lockr!(turtl.user);
+        user_guard.data()
+    });
+    reg.add("user:can-migrate", |_turtl, args: LoginArgs| {
+        match migrate::check_login(&args.username, &args.password) {
+            Ok(x) => {
+                match x {
+                    Some(_) => Ok(json!(true)),
+                    None => Ok(json!(false)),
+                }
+            }
+            Err(_) => Ok(json!(false)),
+        }
+    });
+    reg.add("user:join-migrate", |turtl, args: MigrateArgs| {
+        turtl.join_migrate(args.old_username, args.old_password, args.new_username, args.new_password)?;
+        let user_guard = lockr!(turtl.user);
+        user_guard.data()
+    });
+    reg.add("user:logout", |turtl, args: LogoutArgs| {
+        if args.clear_cookie { clear_cookie_event(); }
+        turtl.logout()?;
+        util::sleep(1000);
+        Ok(json!({}))
+    });
+    reg.add("user:change-password", |turtl, args: ChangePasswordArgs| {
+        turtl.change_user_password(args.current_username, args.current_password, args.new_username, args.new_password)?;
+        Ok(json!({}))
+    });
+    reg.add("user:delete-account", |turtl, _args: NoArgs| {
+        clear_cookie_event();
+        turtl.delete_account()?;
+        Ok(json!({}))
+    });
+    reg.add("user:resend-confirmation", |turtl, _args: NoArgs| {
+        User::resend_confirmation(turtl)?;
+        Ok(json!({}))
+    });
+    reg.add("user:get-login-token", |turtl, args: LoginTokenArgs| {
+        if args.confirmation != "I understand this token contains the user's master key and their account may be compromised if the token is misplaced." {
+            return TErr!(TError::PermissionDenied(String::from("Please send the confirmation string to get the token")));
+        }
+        let token = User::get_login_token(turtl)?;
+        Ok(Value::String(token))
+    });
+    reg.add("user:save-login", |turtl, _args: NoArgs| {
+        let key = User::save_login(turtl)?;
+        Ok(json!({"user_id": turtl.user_id()?, "key": key}))
+    });
+    reg.add("user:find-by-email", |turtl, args: EmailArgs| {
+        let user = User::find_by_email(turtl, &args.email)?;
+        Ok(jedi::to_val(&user)?)
+    });
+    reg.add("app:connected", |turtl, _args: NoArgs| {
+        let connguard = lockr!(turtl.connected);
+        let connected: bool = *connguard;
+        drop(connguard);
+        Ok(Value::Bool(connected))
+    });
+    reg.add("app:wipe-user-data", |turtl, _args: NoArgs| {
+        clear_cookie_event();
+        turtl.wipe_user_data()?;
+        Ok(json!({}))
+    });
+    reg.add("app:wipe-app-data", |turtl, _args: NoArgs| {
+        clear_cookie_event();
+        turtl.wipe_app_data()?;
+        Ok(json!({}))
+    });
+    reg.add("app:api:set-endpoint", |_turtl, args: EndpointArgs| {
+        config::set(&["api", "endpoint"], &args.endpoint)?;
+        Ok(json!({}))
+    });
+    reg.add("app:api:set-old-endpoint", |_turtl, args: EndpointArgs| {
+        config::set(&["api", "v6", "endpoint"], &args.endpoint)?;
+        Ok(json!({}))
+    });
+    reg.add("app:api:get-endpoint", |_turtl, _args: NoArgs| {
+        let endpoint: String = config::get(&["api", "endpoint"])?;
+        Ok(Value::String(endpoint))
+    });
+    reg.add("app:api:get-old-endpoint", |_turtl, _args: NoArgs| {
+        let endpoint: String = config::get(&["api", "v6", "endpoint"])?;
+        Ok(Value::String(endpoint))
+    });
+    reg.add("app:get-config", |_turtl, _args: NoArgs| {
+        Ok(config::dump()?)
+    });
+    reg.add("app:get-log", |_turtl, args: LogArgs| {
+        let contents = logger::read_log(args.lines)?;
+        Ok(Value::String(contents))
+    });
+    reg.add("app:list-commands", |_turtl, _args: NoArgs| {
+        Ok(jedi::to_val(&registry::list())?)
+    });
+    reg.add("app:api-schema", |_turtl, _args: NoArgs| {
+        schema::export()
+    });
+    reg.add("app:cancel-request", |_turtl, args: CancelArgs| {
+        // the request itself responds with a `cancelled` error once it stops
+        // at its next checkpoint
+        Ok(Value::Bool(inflight::cancel(&args.mid).is_some()))
+    });
+    reg.add("app:events:subscribe", |_turtl, args: SubscribeArgs| {
+        messaging::subscribe(&args.subscriber, args.filters)?;
+        Ok(Value::String(messaging::subscriber_channel(&args.subscriber)?))
+    });
+    reg.add("app:events:unsubscribe", |_turtl, args: UnsubscribeArgs| {
+        Ok(Value::Bool(messaging::unsubscribe(&args.subscriber)))
+    });
+    reg.add("app:debug:channels", |_turtl, _args: NoArgs| {
+        Ok(channels_val(carrier::channels()))
+    });
+    reg.add("batch", |turtl, args: BatchArgs| {
+        batch::run(turtl, args.requests, args.abort_on_error)
+    });
+    reg.add("app:shutdown", |turtl, _args: NoArgs| {
+        turtl.sync_shutdown(false)?;
+        messaging::stop();
+        Ok(json!({}))
+    });
+    reg.add("sync:start", |turtl, _args: NoArgs| {
+        turtl.sync_start()?;
+        Ok(json!({}))
+    });
+    reg.add("sync:pause", |turtl, _args: NoArgs| {
+        turtl.sync_pause();
+        Ok(json!({}))
+    });
+    reg.add("sync:resume", |turtl, _args: NoArgs| {
+        turtl.sync_resume();
+        Ok(json!({}))
+    });
+    reg.add("sync:status", |turtl, _args: NoArgs| {
+        Ok(Value::Bool(turtl.sync_running()))
+    });
+    reg.add("sync:shutdown", |turtl, args: SyncShutdownArgs| {
+        turtl.sync_shutdown(args.wait)?;
+        Ok(json!({}))
+    });
+    reg.add("sync:get-pending", |turtl, _args: NoArgs| {
+        let frozen = SyncRecord::get_all_pending(turtl)?;
+        Ok(jedi::to_val(&frozen)?)
+    });
+    reg.add("sync:unfreeze-item", |turtl, args: SyncItemArgs| {
+        SyncRecord::kick_frozen_sync(turtl, &args.sync_id)?;
+        Ok(json!({}))
+    });
+    reg.add("sync:delete-item", |turtl, args: SyncItemArgs| {
+        SyncRecord::delete_sync_item(turtl, &args.sync_id)?;
+        Ok(json!({}))
+    });
+    reg.add("profile:load", |turtl, _args: NoArgs| {
+        let user_guard = lockr!(turtl.user);
+        let profile_guard = lockr!(turtl.profile);
+        let profile_data = json!({
+            "user": &user_guard.as_ref(),
+            "spaces": &profile_guard.spaces,
+            "boards": &profile_guard.boards,
+            "invites": &profile_guard.invites,
+        });
+        Ok(profile_data)
+    });
+    reg.add("profile:sync:model", |turtl, args: SyncModelArgs| {
+        // construct a sync record and hand to our sync dispatcher
+        let mut sync_record = SyncRecord::default();
+        sync_record.action = args.action;
+        sync_record.ty = args.ty;
+        sync_record.data = Some(args.data);
+        sync_model::dispatch(turtl, sync_record)
+    });
+    reg.add("profile:space:set-owner", |turtl, args: SpaceUserArgs| {
+        let mut profile_guard = lockw!(turtl.profile);
+        let space = match Profile::finder(&mut profile_guard.spaces, &args.space_id) {
+            Some(s) => s,
+            None => return TErr!(TError::MissingData(format!("couldn't find space {}", args.space_id))),
+        };
+        space.set_owner(turtl, &args.user_id)?;
+        Ok(space.data()?)
+    });
+    reg.add("profile:space:edit-member", |turtl, args: MemberArgs| {
+        let mut member = args.member;
+        let mut profile_guard = lockw!(turtl.profile);
+        let space = match Profile::finder(&mut profile_guard.spaces, &member.space_id) {
+            Some(s) => s,
+            None => return TErr!(TError::MissingData(format!("couldn't find space {}", member.space_id))),
+        };
+        space.edit_member(turtl, &mut member)?;
+        Ok(space.data()?)
+    });
+    reg.add("profile:space:delete-member", |turtl, args: SpaceUserArgs| {
+        let mut profile_guard = lockw!(turtl.profile);
+        let space = match Profile::finder(&mut profile_guard.spaces, &args.space_id) {
+            Some(s) => s,
+            None => return TErr!(TError::MissingData(format!("couldn't find space {}", args.space_id))),
+        };
+        space.delete_member(turtl, &args.user_id)?;
+        Ok(space.data()?)
+    });
+    reg.add("profile:space:leave", |turtl, args: SpaceArgs| {
+        let mut profile_guard = lockw!(turtl.profile);
+        let space = match Profile::finder(&mut profile_guard.spaces, &args.space_id) {
+            Some(s) => s,
+            None => return TErr!(TError::MissingData(format!("couldn't find space {}", args.space_id))),
+        };
+        space.leave(turtl)?;
+        Ok(space.data()?)
+    });
+    reg.add("profile:space:send-invite", |turtl, args: InviteRequestArgs| {
+        let req = args.invite;
+        let mut profile_guard = lockw!(turtl.profile);
+        let space = match Profile::finder(&mut profile_guard.spaces, &req.space_id) {
+            Some(s) => s,
+            None => return TErr!(TError::MissingData(format!("couldn't find space {}", req.space_id))),
+        };
+        space.send_invite(turtl, req)?;
+        Ok(space.data()?)
+    });
+    reg.add("profile:space:edit-invite", |turtl, args: InviteArgs| {
+        let mut invite = args.invite;
+        let mut profile_guard = lockw!(turtl.profile);
+        let space = match Profile::finder(&mut profile_guard.spaces, &invite.space_id) {
+            Some(s) => s,
+            None => return TErr!(TError::MissingData(format!("couldn't find space {}", invite.space_id))),
+        };
+        space.edit_invite(turtl, &mut invite)?;
+        Ok(space.data()?)
+    });
+    reg.add("profile:space:delete-invite", |turtl, args: SpaceInviteArgs| {
+        let mut profile_guard = lockw!(turtl.profile);
+        let space = match Profile::finder(&mut profile_guard.spaces, &args.space_id) {
+            Some(s) => s,
+            None => return TErr!(TError::MissingData(format!("couldn't find space {}", args.space_id))),
+        };
+        space.delete_invite(turtl, &args.invite_id)?;
+        Ok(space.data()?)
+    });
+    reg.add("profile:accept-invite", |turtl, args: AcceptInviteArgs| {
+        let mut invite = args.invite;
+        let space = Space::accept_invite(turtl, &mut invite, args.passphrase)?;
+        Ok(space.data()?)
+    });
+    reg.add("profile:delete-invite", |turtl, args: InviteIdArgs| {
+        Invite::delete_user_invite(turtl, &args.invite_id)?;
+        Ok(json!({}))
+    });
+    reg.add("profile:get-notes", |turtl, args: NoteIdsArgs| {
+        let notes: Vec<Note> = turtl.load_notes(&args.note_ids)?;
+        Ok(jedi::to_val(&notes)?)
+    });
+    reg.add("profile:find-notes", |turtl, args: QueryArgs| {
+        let qry = args.query;
+        let search_guard = lock!(turtl.search);
+        if search_guard.is_none() {
+            return TErr!(TError::MissingField(format!("turtl is missing `search` object")));
+        }
+        let search = search_guard.as_ref().unwrap();
+        let (note_ids, total) = search.find(&qry)?;
+        let notes: Vec<Note> = turtl.load_notes(&note_ids)?;
+        let tags: Vec<(String, i32)> = search.find_tags(&qry)?;
+        Ok(json!({
+            "notes": notes,
+            "tags": tags,
+            "total": total,
+        }))
+    });
+    reg.add("profile:find-tags", |turtl, args: QueryArgs| {
+        let qry = args.query;
+        let search_guard = lock!(turtl.search);
+        if search_guard.is_none() {
+            return TErr!(TError::MissingField(format!("turtl is missing `search` object")));
+        }
+        let search = search_guard.as_ref().unwrap();
+        let tags: Vec<(String, i32)> = search.find_tags(&qry)?;
+        Ok(json!({
+            "tags": tags,
+        }))
+    });
+    reg.add("profile:note:get-file", |turtl, args: NoteIdArgs| {
+        let notes: Vec<Note> = turtl.load_notes(&vec![args.note_id])?;
+        let bin = FileData::load_file(turtl, &notes[0])?;
+        // sent as a base64 string for JSON, raw binary for msgpack
+        Ok(wire::binary(&bin)?)
+    });
+    reg.add("profile:export", |turtl, _args: NoArgs| {
+        let export = Profile::export(turtl)?;
+        Ok(jedi::to_val(&export)?)
+    });
+    reg.add("profile:import", |turtl, args: ImportArgs| {
+        let result = Profile::import(turtl, args.mode, args.export)?;
+        Ok(jedi::to_val(&result)?)
+    });
+    reg.add("feedback:send", |turtl, args: FeedbackArgs| {
+        args.feedback.send(turtl)?;
+        Ok(json!({}))
+    });
+    reg.add("clip", |_turtl, args: ClipArgs| {
+        inflight::checkpoint()?;
+        let res = clippo::clip(&args.url, &args.custom_parsers)?;
+        Ok(jedi::to_val(&res)?)
+    });
+    reg.add("ping", |_turtl, _args: NoArgs| {
+        info!("ping!");
+        messaging::ui_event("pong", &Value::Null)?;
+        Ok(Value::String(String::from("pong")))
+    });
+    describe_results(reg);
+}
+
+/// Describe what each of core's commands returns, for the API schema. Keep
+/// this in sync with the commands above!
+fn describe_results(reg: &mut Registry) {
+    let empty = json!({"type": "object", "maxProperties": 0});
+    let boolean = json!({"type": "boolean"});
+    let string = json!({"type": "string"});
+    let model = json!({"type": "object", "properties": {"id": {"type": "string"}}});
+    let models = json!({"type": "array", "items": model.clone()});
+    let tags = json!({
+        "type": "array",
+        "items": {"type": "array", "items": [{"type": "string"}, {"type": "integer"}]},
+    });
+    let results = vec![
+        ("user:login", model.clone()),
+        ("user:login-from-token", model.clone()),
+        ("user:login-from-saved", model.clone()),
+        ("user:join", model.clone()),
+        ("user:can-migrate", boolean.clone()),
+        ("user:join-migrate", model.clone()),
+        ("user:logout", empty.clone()),
+        ("user:change-password", empty.clone()),
+        ("user:delete-account", empty.clone()),
+        ("user:resend-confirmation", empty.clone()),
+        ("user:get-login-token", string.clone()),
+        ("user:save-login", json!({
+            "type": "object",
+            "properties": {"user_id": {"type": "string"}, "key": {"type": "string", "contentEncoding": "base64"}},
+        })),
+        ("user:find-by-email", model.clone()),
+        ("app:connected", boolean.clone()),
+        ("app:wipe-user-data", empty.clone()),
+        ("app:wipe-app-data", empty.clone()),
+        ("app:api:set-endpoint", empty.clone()),
+        ("app:api:set-old-endpoint", empty.clone()),
+        ("app:api:get-endpoint", string.clone()),
+        ("app:api:get-old-endpoint", string.clone()),
+        ("app:get-config", json!({"type": "object"})),
+        ("app:get-log", string.clone()),
+        ("app:list-commands", json!({"type": "array", "items": {"type": "string"}})),
+        ("app:api-schema", json!({"type": "object"})),
+        ("app:cancel-request", boolean.clone()),
+        ("app:events:subscribe", string.clone()),
+        ("app:events:unsubscribe", boolean.clone()),
+        ("app:debug:channels", json!({
+            "type": "array",
+            "items": {
+                "type": "object",
+                "properties": {
+                    "name": {"type": "string"},
+                    "type": {"type": "string", "enum": ["queue", "broadcast"]},
+                    "messages": {"type": "integer"},
+                    "listeners": {"type": "integer"},
+                    "sent": {"type": "integer"},
+                    "received": {"type": "integer"},
+                    "dropped": {"type": "integer"},
+                    "capacity": {"anyOf": [{"type": "integer"}, {"type": "null"}]},
+                    "overflow": {"anyOf": [{"type": "string", "enum": ["block", "drop_oldest", "drop_newest", "error"]}, {"type": "null"}]},
+                    "last_activity": {"anyOf": [{"type": "integer"}, {"type": "null"}]},
+                },
+            },
+        })),
+        ("batch", json!({"type": "array", "items": {"$ref": "#/components/schemas/Response"}})),
+        ("app:shutdown", empty.clone()),
+        ("sync:start", empty.clone()),
+        ("sync:pause", empty.clone()),
+        ("sync:resume", empty.clone()),
+        ("sync:status", boolean.clone()),
+        ("sync:shutdown", empty.clone()),
+        ("sync:get-pending", json!({"type": "array", "items": {"$ref": "#/components/schemas/SyncRecord"}})),
+        ("sync:unfreeze-item", empty.clone()),
+        ("sync:delete-item", empty.clone()),
+        ("profile:load", json!({
+            "type": "object",
+            "properties": {"user": model.clone(), "spaces": models.clone(), "boards": models.clone(), "invites": models.clone()},
+        })),
+        ("profile:sync:model", json!({})),
+        ("profile:space:set-owner", model.clone()),
+        ("profile:space:edit-member", model.clone()),
+        ("profile:space:delete-member", model.clone()),
+        ("profile:space:leave", model.clone()),
+        ("profile:space:send-invite", model.clone()),
+        ("profile:space:edit-invite", model.clone()),
+        ("profile:space:delete-invite", model.clone()),
+        ("profile:accept-invite", model.clone()),
+        ("profile:delete-invite", empty.clone()),
+        ("profile:get-notes", models.clone()),
+        ("profile:find-notes", json!({
+            "type": "object",
+            "properties": {"notes": models.clone(), "tags": tags.clone(), "total": {"type": "integer"}},
+        })),
+        ("profile:find-tags", json!({"type": "object", "properties": {"tags": tags.clone()}})),
+        ("profile:note:get-file", json!({"type": "string", "contentEncoding": "base64"})),
+        ("profile:export", json!({
+            "type": "object",
+            "properties": {
+                "schema_version": {"type": "integer"},
+                "spaces": models.clone(),
+                "boards": models.clone(),
+                "notes": models.clone(),
+                "files": {"type": "array", "items": {"type": "object"}},
+            },
+        })),
+        ("profile:import", json!({
+            "type": "object",
+            "properties": {"actions": {"type": "array", "items": {"$ref": "#/components/schemas/SyncRecord"}}},
+        })),
+        ("feedback:send", empty.clone()),
+        ("clip", json!({"type": "object"})),
+        ("ping", string.clone()),
+    ];
+    for (name, result) in results {
+        reg.returns(name, result);
+    }
+}
@@ -0,0 +1,167 @@
+//! Keeps track of the requests dispatch is currently running so they can be
+//! cancelled (via `app:cancel-request`) or given a deadline.
+//!
+//! Cancellation is cooperative: we can't just kill a thread that's halfway
+//! through deriving a key. Instead, long-running code calls `checkpoint()` at
+//! points where it's safe to bail out with a `cancelled`/`timeout` error. A
+//! command that finishes without hitting a checkpoint after being cancelled
+//! (or timing out) returns its result like normal, since whatever it did has
+//! already been done.
+//!
+//! A request runs start to finish on a single worker thread, so the request a
+//! thread is working on is tracked in a thread-local. This means commands
+//! don't need to know their message id to call `checkpoint()`.
+
+use ::std::cell::RefCell;
+use ::std::collections::HashMap;
+use ::std::sync::{Arc, RwLock};
+use ::std::sync::atomic::{AtomicBool, Ordering};
+use ::std::time::{Duration, Instant};
+
+use ::error::{TResult, TError};
+
+/// Holds the state of a running request
+pub struct Request {
+    /// The request's message id
+    pub mid: String,
+    /// Whether or not someone has asked that we stop running this request
+    cancelled: AtomicBool,
+    /// When this request should give up, if ever
+    deadline: Option<Instant>,
+}
+
+impl Request {
+    /// Create a new request, optionally with a deadline (in ms from now)
+    fn new(mid: &String, deadline_ms: Option<u64>) -> Request {
+        Request {
+            mid: mid.clone(),
+            cancelled: AtomicBool::new(false),
+            deadline: deadline_ms.map(|x| Instant::now() + Duration::from_millis(x)),
+        }
+    }
+
+    /// Returns an error if this request has been cancelled or has run past its
+    /// deadline
+    pub fn status(&self) -> TResult<()> {
+        if self.cancelled.load(Ordering::SeqCst) {
+            return TErr!(TError::Cancelled(self.mid.clone()));
+        }
+        match self.deadline {
+            Some(deadline) => {
+                if Instant::now() >= deadline {
+                    return TErr!(TError::Timeout(self.mid.clone()));
+                }
+            }
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+lazy_static! {
+    /// Holds all of our running requests, by mid
+    static ref INFLIGHT: RwLock<HashMap<String, Arc<Request>>> = RwLock::new(HashMap::new());
+}
+
+thread_local! {
+    /// The request the current thread is running (if any)
+    static CURRENT: RefCell<Option<Arc<Request>>> = RefCell::new(None);
+}
+
+/// Start tracking a request and mark it as the current thread's request
+pub fn start(mid: &String, deadline_ms: Option<u64>) -> Arc<Request> {
+    let req = Arc::new(Request::new(mid, deadline_ms));
+    {
+        let mut guard = lockw!(*INFLIGHT);
+        if guard.insert(mid.clone(), req.clone()).is_some() {
+            warn!("inflight::start() -- duplicate mid {}, only the newest request can be cancelled", mid);
+        }
+    }
+    CURRENT.with(|cur| { *cur.borrow_mut() = Some(req.clone()); });
+    req
+}
+
+/// Stop tracking a request
+pub fn finish(req: &Arc<Request>) {
+    {
+        let mut guard = lockw!(*INFLIGHT);
+        let ours = match guard.get(&req.mid) {
+            Some(x) => Arc::ptr_eq(x, req),
+            None => false,
+        };
+        if ours { guard.remove(&req.mid); }
+    }
+    CURRENT.with(|cur| { *cur.borrow_mut() = None; });
+}
+
+/// Flag a running request as cancelled. Returns the request if it was found.
+pub fn cancel(mid: &String) -> Option<Arc<Request>> {
+    let guard = lockr!(*INFLIGHT);
+    match guard.get(mid) {
+        Some(req) => {
+            req.cancelled.store(true, Ordering::SeqCst);
+            Some(req.clone())
+        }
+        None => None,
+    }
+}
+
+/// Check if the request the current thread is running has been cancelled or
+/// has timed out, and if so return an error. Long-running code should call
+/// this anywhere it's safe to stop. Does nothing outside of a request.
+pub fn checkpoint() -> TResult<()> {
+    let req = CURRENT.with(|cur| cur.borrow().clone());
+    match req {
+        Some(x) => x.status(),
+        None => Ok(()),
+    }
+}
+
+/// Get the message id of the request the current thread is running, if any
+pub fn current_mid() -> Option<String> {
+    CURRENT.with(|cur| cur.borrow().as_ref().map(|x| x.mid.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::std::thread;
+
+    #[test]
+    fn cancels_requests() {
+        let mid = String::from("inflight-cancel");
+        let req = start(&mid, None);
+        assert_eq!(current_mid(), Some(mid.clone()));
+        assert!(checkpoint().is_ok());
+        assert!(cancel(&String::from("inflight-nope")).is_none());
+        let cancelled = cancel(&mid).unwrap();
+        match checkpoint() {
+            Err(e) => match e.shed() {
+                TError::Cancelled(x) => assert_eq!(x, mid),
+                e => panic!("bad error: {}", e),
+            },
+            Ok(_) => panic!("request wasn't cancelled"),
+        }
+        assert!(cancelled.status().is_err());
+        finish(&req);
+        assert!(checkpoint().is_ok());
+        assert_eq!(current_mid(), None);
+        assert!(cancel(&mid).is_none());
+    }
+
+    #[test]
+    fn times_out_requests() {
+        let mid = String::from("inflight-timeout");
+        let req = start(&mid, Some(10));
+        assert!(checkpoint().is_ok());
+        thread::sleep(Duration::from_millis(20));
+        match checkpoint() {
+            Err(e) => match e.shed() {
+                TError::Timeout(x) => assert_eq!(x, mid),
+                e => panic!("bad error: {}", e),
+            },
+            Ok(_) => panic!("request didn't time out"),
+        }
+        finish(&req);
+    }
+}
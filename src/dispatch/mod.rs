@@ -0,0 +1,298 @@
+//! Dispatch takes messages sent from our wonderful UI and runs the needed core
+//! code to generate the response. Essentially, it's the RPC endpoint for core.
+//!
+//! Each message sent in is in the following format (JSON):
+//! 
+//!     ["<message id>", "<command>", arg1, arg2, ...]
+//!
+//! where the arg\* can be any valid JSON object. The Message ID is passed in
+//! when responding so the client knows which request we are responding to.
+//!
+//! Messages can also be sent as an object with named arguments:
+//!
+//!     {"id": "<message id>", "cmd": "<command>", "args": {"arg1": ..., ...}}
+//!
+//! The argument names are the field names of each command's argument struct.
+//! `args` can be left out for commands that don't take any arguments.
+//!
+//! The object format also takes an optional `deadline` field: the number of
+//! milliseconds the request is allowed to run for before it gives up with a
+//! `timeout` error. Running requests can also be cancelled using the
+//! `app:cancel-request` command (see `dispatch::inflight`).
+//!
+//! Commands live in a registry (see `dispatch::registry`), and core's built-in
+//! commands (and their arguments) are defined in `dispatch::commands`. A
+//! machine-readable description of every command and event can be had via the
+//! `app:api-schema` command (see `dispatch::schema`).
+
+pub mod registry;
+mod commands;
+mod batch;
+pub mod inflight;
+pub mod pool;
+pub mod schema;
+
+use ::jedi::{self, Value, DeserializeOwned};
+use ::error::{TResult, TError};
+use ::util;
+use ::turtl::Turtl;
+use ::models::space::Space;
+use ::sync::sync_model;
+use ::sync;
+use ::messaging::{self, Event};
+use ::wire::{self, Encoding};
+
+/// Does our actual message dispatching
+fn dispatch(cmd: &String, turtl: &Turtl, args: Value) -> TResult<Value> {
+    registry::call(cmd, turtl, args)
+}
+
+/// Register a custom command with the dispatcher. This lets embedders add
+/// their own commands without having to patch core. The handler receives the
+/// command's arguments deserialized into `A`.
+pub fn register_command<A, F>(name: &str, handler: F)
+    where A: DeserializeOwned + 'static,
+          F: Fn(&Turtl, A) -> TResult<Value> + Send + Sync + 'static
+{
+    registry::register(name, handler)
+}
+
+/// List the names of all the commands the dispatcher knows about
+pub fn list_commands() -> Vec<String> {
+    registry::list()
+}
+
+/// Event dispatching. This acts as a way for parts of the app that don't have
+/// access to the Turtl object to trigger events.
+fn dispatch_event(cmd: &String, turtl: &Turtl, data: Value) -> TResult<()> {
+    info!("dispatch::dispatch_event() -- {}", cmd);
+    match cmd.as_ref() {
+        "sync:connected" => {
+            let yesno: bool = jedi::from_val(data)?;
+            let mut connguard = lockw!(turtl.connected);
+            let cur_yesno = *connguard;
+            *connguard = yesno;
+            if cur_yesno != yesno {
+                // only send the ui event if we've changed state
+                messaging::ui_event("sync:connected", &yesno)
+                    .unwrap_or_else(|e| error!("dispatch::dispatch_event() -- error sending connected UI event: {}", e));
+            }
+        }
+        "sync:incoming" => {
+            sync::incoming::process_incoming_sync(turtl)?;
+        }
+        "user:edit" => {
+            let mut user_guard = lockw!(turtl.user);
+            user_guard.merge_fields(&data)?;
+        }
+        "user:change-password:logout" => {
+            messaging::ui_event("user:change-password:logout", &json!({}))?;
+            util::sleep(3000);
+            turtl.logout()?;
+        }
+        "space:delete" => {
+            let space_id: String = jedi::get(&["0"], &data)?;
+            let skip_remote_sync: bool = match jedi::get_opt(&["1"], &data) {
+                Some(x) => x,
+                None => false,
+            };
+            sync_model::delete_model::<Space>(turtl, &space_id, skip_remote_sync)?;
+        }
+        _ => {
+            warn!("dispatch_event() -- encountered unknown event: {}", cmd);
+        }
+    }
+    Ok(())
+}
+
+/// A request we've pulled out of an incoming message
+#[derive(Debug)]
+pub struct Message {
+    /// The message id
+    mid: String,
+    /// The command we're running
+    cmd: String,
+    /// The command's arguments (array or object)
+    args: Value,
+    /// How long (in ms) the request can run before it times out
+    deadline: Option<u64>,
+    /// How the message was encoded
+    encoding: Encoding,
+}
+
+impl Message {
+    /// Get this message's id
+    pub fn mid(&self) -> &String {
+        &self.mid
+    }
+
+    /// Get this message's command
+    pub fn cmd(&self) -> &String {
+        &self.cmd
+    }
+
+    /// Get this message's encoding
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+}
+
+/// Something we've been asked to run: either an internal event, or a request
+/// from the UI.
+#[derive(Debug)]
+pub enum Incoming {
+    Event(Event),
+    Request(Message),
+}
+
+/// Split a request into its parts. Handles both the positional array format and
+/// the named-argument object format.
+fn parse_request(data: Value, encoding: Encoding) -> TResult<Message> {
+    match data {
+        Value::Object(mut obj) => {
+            let mid: String = match obj.remove("id").map(|x| jedi::from_val(x)) {
+                Some(Ok(x)) => x,
+                _ => return TErr!(TError::MissingField(String::from("missing mid (id)"))),
+            };
+            let cmd: String = match obj.remove("cmd").map(|x| jedi::from_val(x)) {
+                Some(Ok(x)) => x,
+                _ => return TErr!(TError::MissingField(String::from("missing cmd (cmd)"))),
+            };
+            let args = match obj.remove("args") {
+                Some(Value::Null) | None => json!({}),
+                Some(x) => x,
+            };
+            let deadline: Option<u64> = match obj.remove("deadline") {
+                Some(Value::Null) | None => None,
+                Some(x) => match jedi::from_val(x) {
+                    Ok(x) => Some(x),
+                    Err(_) => return TErr!(TError::BadValue(String::from("deadline must be a positive integer (ms)"))),
+                },
+            };
+            Ok(Message { mid: mid, cmd: cmd, args: args, deadline: deadline, encoding: encoding })
+        }
+        Value::Array(mut parts) => {
+            // grab the request id from the data
+            let mid: String = match parts.get(0).map(|x| jedi::from_val(x.clone())) {
+                Some(Ok(x)) => x,
+                _ => return TErr!(TError::MissingField(String::from("missing mid (0)"))),
+            };
+            // grab the command from the data
+            let cmd: String = match parts.get(1).map(|x| jedi::from_val(x.clone())) {
+                Some(Ok(x)) => x,
+                _ => return TErr!(TError::MissingField(String::from("missing cmd (1)"))),
+            };
+            // everything after the mid/cmd is our args
+            let args = Value::Array(parts.split_off(2));
+            Ok(Message { mid: mid, cmd: cmd, args: args, deadline: None, encoding: encoding })
+        }
+        _ => TErr!(TError::BadValue(String::from("message must be an array or object"))),
+    }
+}
+
+/// Parse a raw message (JSON or msgpack) into something we can run
+pub fn parse(msg: &[u8]) -> TResult<Incoming> {
+    if msg.starts_with(b"::ev") {
+        let event: Event = jedi::parse_bytes(&msg[4..])?;
+        return Ok(Incoming::Event(event));
+    }
+    let (data, encoding) = wire::decode(msg)?;
+    Ok(Incoming::Request(parse_request(data, encoding)?))
+}
+
+/// Run a parsed message, sending the response (if any) back to the caller
+pub fn run(turtl: &Turtl, incoming: Incoming) -> TResult<()> {
+    let Message { mid, cmd, args, deadline, encoding } = match incoming {
+        Incoming::Event(Event {e, d}) => return dispatch_event(&e, turtl, d),
+        Incoming::Request(x) => x,
+    };
+    info!("dispatch({}): {}", mid, cmd);
+
+    let req = inflight::start(&mid, deadline);
+    let res = dispatch(&cmd, turtl, args);
+    inflight::finish(&req);
+    // a cancelled (or timed out) request only gets a cancelled/timeout error if
+    // the command actually stopped at a checkpoint. if it ran to completion,
+    // whatever it did is done and the caller gets the real result (this is
+    // only here for diagnostics).
+    if let (Err(e), true) = (req.status(), res.is_ok()) {
+        info!("dispatch({}): {} -- finished despite: {}, sending result", mid, cmd, e);
+    }
+
+    match res {
+        Ok(val) => {
+            match turtl.msg_success(&mid, val, encoding) {
+                Err(e) => error!("dispatch::process() -- problem sending response (mid {}): {}", mid, e),
+                _ => {},
+            }
+        },
+        Err(e) => {
+            match turtl.msg_error(&mid, &e, encoding) {
+                Err(e) => error!("dispatch:process() -- problem sending (error) response (mod {}): {}", mid, e),
+                _ => {},
+            }
+        },
+    }
+    Ok(())
+}
+
+/// process a message from the messaging system. this is the main communication
+/// heart of turtl core.
+pub fn process(turtl: &Turtl, msg: &[u8]) -> TResult<()> {
+    let incoming = parse(msg)?;
+    run(turtl, incoming)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_requests() {
+        let msg = parse_request(json!(["12", "user:login", "andrew", "passw0rd"]), Encoding::Json).unwrap();
+        assert_eq!(msg.mid, "12");
+        assert_eq!(msg.cmd, "user:login");
+        assert_eq!(msg.args, json!(["andrew", "passw0rd"]));
+        assert_eq!(msg.deadline, None);
+
+        let msg = parse_request(json!(["13", "ping"]), Encoding::Json).unwrap();
+        assert_eq!(msg.mid, "13");
+        assert_eq!(msg.cmd, "ping");
+        assert_eq!(msg.args, json!([]));
+
+        let msg = parse_request(json!({"id": "14", "cmd": "user:login", "args": {"username": "andrew", "password": "passw0rd"}}), Encoding::Json).unwrap();
+        assert_eq!(msg.mid, "14");
+        assert_eq!(msg.cmd, "user:login");
+        assert_eq!(msg.args, json!({"username": "andrew", "password": "passw0rd"}));
+        assert_eq!(msg.deadline, None);
+
+        let msg = parse_request(json!({"id": "15", "cmd": "ping", "deadline": 5000}), Encoding::Json).unwrap();
+        assert_eq!(msg.args, json!({}));
+        assert_eq!(msg.deadline, Some(5000));
+
+        assert!(parse_request(json!(["16"]), Encoding::Json).is_err());
+        assert!(parse_request(json!({"cmd": "ping"}), Encoding::Json).is_err());
+        assert!(parse_request(json!({"id": "17", "cmd": "ping", "deadline": -4}), Encoding::Json).is_err());
+        assert!(parse_request(json!("ping"), Encoding::Json).is_err());
+    }
+
+    #[test]
+    fn parses_encoded_messages() {
+        let json = br#"{"id": "18", "cmd": "ping"}"#;
+        let msgpack = wire::encode(json!({"id": "18", "cmd": "ping"}), Encoding::Msgpack).unwrap();
+        for &(ref bytes, encoding) in &[(Vec::from(&json[..]), Encoding::Json), (msgpack, Encoding::Msgpack)] {
+            match parse(bytes).unwrap() {
+                Incoming::Request(msg) => {
+                    assert_eq!(msg.mid(), "18");
+                    assert_eq!(msg.cmd(), "ping");
+                    assert_eq!(msg.encoding(), encoding);
+                }
+                _ => panic!("expected a request"),
+            }
+        }
+        match parse(br#"::ev{"e": "sync:incoming", "d": null}"#).unwrap() {
+            Incoming::Event(ev) => assert_eq!(ev.e, "sync:incoming"),
+            _ => panic!("expected an event"),
+        }
+    }
+}
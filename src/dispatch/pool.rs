@@ -0,0 +1,304 @@
+//! A fixed-size pool of worker threads that runs incoming messages. This used
+//! to be a thread per message, which is great right up until a sync storm or a
+//! chatty UI decides to spawn a few thousand of them.
+//!
+//! The pool has two lanes: priority and normal. Priority messages (`ping`,
+//! `app:connected`, etc) always get picked up before normal ones, and some of
+//! the workers are reserved for the priority lane only, so a handful of long
+//! `profile:export` calls can never starve them. Each lane has a maximum depth,
+//! and once a lane is full new requests are turned away with a `queue_full`
+//! error instead of piling up forever.
+
+use ::std::collections::VecDeque;
+use ::std::panic::{self, AssertUnwindSafe};
+use ::std::sync::{Arc, Mutex, Condvar};
+use ::std::thread;
+
+use ::config;
+use ::error::{TResult, TError};
+use ::turtl::Turtl;
+use ::dispatch::{self, Incoming};
+
+/// Which queue a job goes into
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Lane {
+    Priority,
+    Normal,
+}
+
+/// Our job queues
+struct Queues<T> {
+    priority: VecDeque<T>,
+    normal: VecDeque<T>,
+    shutdown: bool,
+}
+
+/// State shared between the pool and its workers
+struct Shared<T> {
+    queues: Mutex<Queues<T>>,
+    cond: Condvar,
+}
+
+/// A pool of worker threads that runs jobs of type `T`
+pub struct Pool<T> {
+    shared: Arc<Shared<T>>,
+    /// Max number of jobs that can wait in each lane
+    depth: usize,
+    workers: Mutex<Vec<thread::JoinHandle<()>>>,
+}
+
+impl<T: Send + 'static> Pool<T> {
+    /// Create a new pool with `workers` threads, `priority_workers` of which
+    /// will only run jobs from the priority lane. Each job is handed to
+    /// `handler`.
+    pub fn new<F>(name: &str, workers: usize, priority_workers: usize, depth: usize, handler: F) -> TResult<Pool<T>>
+        where F: Fn(T) + Send + Sync + 'static
+    {
+        let workers = if workers < 1 { 1 } else { workers };
+        // always leave at least one worker for the normal lane
+        let priority_workers = if priority_workers >= workers { workers - 1 } else { priority_workers };
+        let shared = Arc::new(Shared {
+            queues: Mutex::new(Queues {
+                priority: VecDeque::new(),
+                normal: VecDeque::new(),
+                shutdown: false,
+            }),
+            cond: Condvar::new(),
+        });
+        let handler = Arc::new(handler);
+        let mut handles = Vec::with_capacity(workers);
+        for i in 0..workers {
+            let priority_only = i < priority_workers;
+            let shared = shared.clone();
+            let handler = handler.clone();
+            let handle = thread::Builder::new().name(format!("{}:{}", name, i)).spawn(move || {
+                worker(shared, priority_only, handler);
+            })?;
+            handles.push(handle);
+        }
+        Ok(Pool {
+            shared: shared,
+            depth: depth,
+            workers: Mutex::new(handles),
+        })
+    }
+
+    /// Queue a job. If the lane is full, the job is handed back.
+    pub fn submit(&self, lane: Lane, job: T) -> Result<(), T> {
+        self.push(lane, job, true)
+    }
+
+    /// Queue a job, ignoring the lane's max depth. Used for internal messages
+    /// that we can't just drop on the floor.
+    pub fn submit_unbounded(&self, lane: Lane, job: T) {
+        // can't fail when unbounded (except after shutdown, in which case we
+        // don't care)
+        let _ = self.push(lane, job, false);
+    }
+
+    fn push(&self, lane: Lane, job: T, bounded: bool) -> Result<(), T> {
+        {
+            let mut queues = lock!(self.shared.queues);
+            if queues.shutdown { return Err(job); }
+            let queue = match lane {
+                Lane::Priority => &mut queues.priority,
+                Lane::Normal => &mut queues.normal,
+            };
+            if bounded && queue.len() >= self.depth {
+                return Err(job);
+            }
+            queue.push_back(job);
+        }
+        // wake everyone: a priority-only worker may grab this first and go
+        // right back to sleep if it's a normal job
+        self.shared.cond.notify_all();
+        Ok(())
+    }
+
+    /// How many jobs are waiting in each lane (priority, normal)
+    pub fn depth(&self) -> (usize, usize) {
+        let queues = lock!(self.shared.queues);
+        (queues.priority.len(), queues.normal.len())
+    }
+
+    /// Stop all workers. Jobs that are running finish, jobs that are still
+    /// queued are thrown out.
+    pub fn shutdown(&self) {
+        let dropped = {
+            let mut queues = lock!(self.shared.queues);
+            queues.shutdown = true;
+            let dropped = queues.priority.len() + queues.normal.len();
+            queues.priority.clear();
+            queues.normal.clear();
+            dropped
+        };
+        if dropped > 0 {
+            warn!("Pool.shutdown() -- dropping {} queued jobs", dropped);
+        }
+        self.shared.cond.notify_all();
+        let handles = {
+            let mut guard = lock!(self.workers);
+            guard.drain(..).collect::<Vec<_>>()
+        };
+        let current = thread::current().id();
+        for handle in handles {
+            // if a job is shutting us down, don't wait on ourselves
+            if handle.thread().id() == current { continue; }
+            match handle.join() {
+                Err(_) => error!("Pool.shutdown() -- worker panicked"),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Grab jobs and run them until the pool shuts down
+fn worker<T, F>(shared: Arc<Shared<T>>, priority_only: bool, handler: Arc<F>)
+    where F: Fn(T) + Send + Sync + 'static
+{
+    loop {
+        let job = {
+            let mut queues = lock!(shared.queues);
+            loop {
+                if queues.shutdown { return; }
+                if let Some(job) = queues.priority.pop_front() { break job; }
+                if !priority_only {
+                    if let Some(job) = queues.normal.pop_front() { break job; }
+                }
+                queues = match shared.cond.wait(queues) {
+                    Ok(x) => x,
+                    Err(e) => e.into_inner(),
+                };
+            }
+        };
+        // a panicking job shouldn't take a worker down with it
+        match panic::catch_unwind(AssertUnwindSafe(|| handler(job))) {
+            Err(_) => error!("pool::worker() -- job panicked"),
+            _ => {}
+        }
+    }
+}
+
+/// Grab a dispatch config value, falling back to a default
+fn config_or<T: ::jedi::DeserializeOwned>(key: &str, default: T) -> T {
+    match config::get(&["dispatch", key]) {
+        Ok(x) => x,
+        Err(_) => default,
+    }
+}
+
+/// The dispatch pool, along with which commands run in the priority lane
+pub struct DispatchPool {
+    pool: Pool<Incoming>,
+    priority_commands: Vec<String>,
+}
+
+impl DispatchPool {
+    /// Create a dispatch pool from our `dispatch` config
+    pub fn new(turtl: Arc<Turtl>) -> TResult<DispatchPool> {
+        let workers: usize = config_or("workers", 4);
+        let priority_workers: usize = config_or("priority_workers", 1);
+        let depth: usize = config_or("queue_depth", 256);
+        let priority_commands: Vec<String> = config_or("priority_commands", vec![
+            String::from("ping"),
+            String::from("app:connected"),
+            String::from("app:cancel-request"),
+        ]);
+        info!("DispatchPool::new() -- {} workers ({} priority), queue depth {}", workers, priority_workers, depth);
+        let pool = Pool::new("dispatch:msg", workers, priority_workers, depth, move |incoming: Incoming| {
+            match dispatch::run(turtl.as_ref(), incoming) {
+                Ok(..) => {},
+                Err(e) => error!("dispatch::run() -- error processing: {}", e),
+            }
+        })?;
+        Ok(DispatchPool {
+            pool: pool,
+            priority_commands: priority_commands,
+        })
+    }
+
+    /// Parse a message and queue it. If the queue is full, the caller gets a
+    /// `queue_full` error back for their request.
+    pub fn queue(&self, turtl: &Turtl, msg: Vec<u8>) -> TResult<()> {
+        let incoming = dispatch::parse(&msg)?;
+        let (mid, encoding, lane) = match incoming {
+            // internal events always get run
+            Incoming::Event(..) => {
+                self.pool.submit_unbounded(Lane::Normal, incoming);
+                return Ok(());
+            }
+            Incoming::Request(ref req) => {
+                let lane = if self.priority_commands.contains(req.cmd()) { Lane::Priority } else { Lane::Normal };
+                (req.mid().clone(), req.encoding(), lane)
+            }
+        };
+        match self.pool.submit(lane, incoming) {
+            Ok(_) => Ok(()),
+            Err(_) => {
+                warn!("DispatchPool.queue() -- {:?} queue full, rejecting request {}", lane, mid);
+                turtl.msg_error(&mid, &twrap!(TError::QueueFull(format!("{:?}", lane).to_lowercase())), encoding)
+            }
+        }
+    }
+
+    /// Shut down the pool
+    pub fn shutdown(&self) {
+        self.pool.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::std::sync::mpsc;
+    use ::std::time::Duration;
+
+    #[test]
+    fn runs_jobs() {
+        let (tx, rx) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        let pool = Pool::new("test:pool", 2, 0, 16, move |x: u32| {
+            lock!(tx).send(x * 2).unwrap();
+        }).unwrap();
+        for i in 0..10 {
+            pool.submit(Lane::Normal, i).unwrap();
+        }
+        let mut res = (0..10).map(|_| rx.recv_timeout(Duration::from_secs(5)).unwrap()).collect::<Vec<_>>();
+        res.sort();
+        assert_eq!(res, (0..10).map(|x| x * 2).collect::<Vec<_>>());
+        pool.shutdown();
+    }
+
+    #[test]
+    fn priority_lane_is_never_starved() {
+        let (block_tx, block_rx) = mpsc::channel::<()>();
+        let block_rx = Mutex::new(block_rx);
+        let (done_tx, done_rx) = mpsc::channel();
+        let done_tx = Mutex::new(done_tx);
+        // one normal worker, one priority worker
+        let pool = Pool::new("test:pool", 2, 1, 2, move |x: &'static str| {
+            if x == "slow" {
+                lock!(block_rx).recv().unwrap();
+            }
+            lock!(done_tx).send(x).unwrap();
+        }).unwrap();
+        pool.submit(Lane::Normal, "slow").unwrap();
+        // wait for the slow job to get picked up
+        for _ in 0..500 {
+            if pool.depth().1 == 0 { break; }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(pool.depth().1, 0);
+        // the slow job is hogging our normal worker, so these pile up...
+        pool.submit(Lane::Normal, "normal1").unwrap();
+        pool.submit(Lane::Normal, "normal2").unwrap();
+        // ...until there's no more room
+        assert!(pool.submit(Lane::Normal, "normal3").is_err());
+        // but ping still gets through
+        pool.submit(Lane::Priority, "ping").unwrap();
+        assert_eq!(done_rx.recv_timeout(Duration::from_secs(5)).unwrap(), "ping");
+        block_tx.send(()).unwrap();
+        assert_eq!(done_rx.recv_timeout(Duration::from_secs(5)).unwrap(), "slow");
+        pool.shutdown();
+    }
+}
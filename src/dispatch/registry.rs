@@ -0,0 +1,274 @@
+//! The command registry holds every RPC command the dispatcher knows how to
+//! run. Each command has a name, a typed argument struct, and a handler that
+//! receives the deserialized arguments.
+//!
+//! Argument structs are plain serde structs. Serde happily deserializes a
+//! struct from a JSON array (fields are read in declaration order), so the
+//! positional `["<mid>", "<cmd>", arg1, arg2, ...]` format maps straight onto
+//! the struct without any `jedi::get(&["2"], &data)` shenanigans. Trailing
+//! arguments can be made optional by marking their fields `#[serde(default)]`.
+//! Positional arguments past the end of the struct are ignored (the old
+//! hand-rolled dispatch never looked at them, so some UIs send them).
+//!
+//! The same structs also deserialize from JSON objects, which is how the named
+//! `{"id": ..., "cmd": ..., "args": {...}}` format works: the argument names are
+//! just the struct's field names.
+//!
+//! Since the argument structs are typed, the registry can also describe each
+//! command's arguments for the API schema (see `dispatch::schema`). Commands
+//! can optionally describe what they return as well.
+
+use ::std::collections::BTreeMap;
+use ::std::sync::{Arc, RwLock};
+
+use ::jedi::{self, Value, DeserializeOwned};
+
+use ::error::{TResult, TError};
+use ::turtl::Turtl;
+use ::dispatch::commands;
+use ::dispatch::schema::{self, Param};
+
+/// A boxed command handler. Takes the raw argument value (usually an array)
+/// and takes care of deserializing it into whatever the command wants.
+pub type Handler = Box<Fn(&Turtl, Value) -> TResult<Value> + Send + Sync>;
+
+/// An empty argument struct, for commands that don't take any arguments.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct NoArgs {}
+
+/// A registered command.
+pub struct Command {
+    /// The command's name (ie "user:login")
+    pub name: String,
+    /// The function that runs our command
+    handler: Handler,
+    /// Describes the command's arguments
+    params: fn() -> Vec<Param>,
+}
+
+impl Command {
+    /// Run this command with the given (raw) arguments
+    pub fn run(&self, turtl: &Turtl, args: Value) -> TResult<Value> {
+        (self.handler)(turtl, args)
+    }
+
+    /// Describe this command's arguments
+    pub fn params(&self) -> Vec<Param> {
+        (self.params)()
+    }
+}
+
+/// Holds our commands, keyed by name.
+pub struct Registry {
+    commands: BTreeMap<String, Arc<Command>>,
+    /// JSON schemas describing what each command returns, by command name
+    results: BTreeMap<String, Value>,
+}
+
+impl Registry {
+    /// Create an empty registry
+    pub fn new() -> Registry {
+        Registry {
+            commands: BTreeMap::new(),
+            results: BTreeMap::new(),
+        }
+    }
+
+    /// Add a command to the registry. If a command with the same name already
+    /// exists, it is replaced.
+    pub fn add<A, F>(&mut self, name: &str, handler: F)
+        where A: DeserializeOwned + 'static,
+              F: Fn(&Turtl, A) -> TResult<Value> + Send + Sync + 'static
+    {
+        let cmd_name = String::from(name);
+        let num_fields = schema::num_fields::<A>();
+        let wrapped = move |turtl: &Turtl, args: Value| -> TResult<Value> {
+            let parsed: A = parse_args(&cmd_name, args, num_fields)?;
+            handler(turtl, parsed)
+        };
+        let command = Command {
+            name: String::from(name),
+            handler: Box::new(wrapped),
+            params: schema::describe_args::<A>,
+        };
+        if self.commands.insert(String::from(name), Arc::new(command)).is_some() {
+            warn!("Registry.add() -- replacing existing command {}", name);
+        }
+    }
+
+    /// Set the JSON schema describing what a command returns
+    pub fn returns(&mut self, name: &str, result: Value) {
+        self.results.insert(String::from(name), result);
+    }
+
+    /// Remove a command from the registry
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.results.remove(name);
+        self.commands.remove(name).is_some()
+    }
+
+    /// Grab a command by name
+    pub fn get(&self, name: &str) -> Option<Arc<Command>> {
+        self.commands.get(name).map(|x| x.clone())
+    }
+
+    /// Get the names of all our registered commands, sorted
+    pub fn names(&self) -> Vec<String> {
+        self.commands.keys().map(|x| x.clone()).collect::<Vec<_>>()
+    }
+
+    /// Describe all our commands: (name, arguments, result schema), sorted by
+    /// name
+    pub fn describe(&self) -> Vec<(String, Vec<Param>, Option<Value>)> {
+        self.commands.iter()
+            .map(|(name, cmd)| (name.clone(), cmd.params(), self.results.get(name).map(|x| x.clone())))
+            .collect::<Vec<_>>()
+    }
+}
+
+lazy_static! {
+    /// Our global command registry, pre-loaded with all of core's commands.
+    static ref REGISTRY: RwLock<Registry> = {
+        let mut registry = Registry::new();
+        commands::register(&mut registry);
+        RwLock::new(registry)
+    };
+}
+
+/// Deserialize a command's arguments into its argument struct, returning a
+/// uniform error if the arguments don't match what the command expects. If
+/// the struct has `num_fields` fields, any positional arguments past those are
+/// dropped.
+fn parse_args<A: DeserializeOwned>(cmd: &String, mut args: Value, num_fields: Option<usize>) -> TResult<A> {
+    if let (Some(num), &mut Value::Array(ref mut vals)) = (num_fields, &mut args) {
+        if vals.len() > num {
+            debug!("registry::parse_args() -- {}: ignoring {} extra argument(s)", cmd, vals.len() - num);
+            vals.truncate(num);
+        }
+    }
+    match jedi::from_val(args) {
+        Ok(x) => Ok(x),
+        Err(e) => TErr!(TError::BadValue(format!("{}: bad arguments: {}", cmd, e))),
+    }
+}
+
+/// Register a command in the global registry
+pub fn register<A, F>(name: &str, handler: F)
+    where A: DeserializeOwned + 'static,
+          F: Fn(&Turtl, A) -> TResult<Value> + Send + Sync + 'static
+{
+    let mut guard = lockw!(*REGISTRY);
+    guard.add(name, handler);
+}
+
+/// Remove a command from the global registry
+pub fn unregister(name: &str) -> bool {
+    let mut guard = lockw!(*REGISTRY);
+    guard.remove(name)
+}
+
+/// List all commands in the global registry
+pub fn list() -> Vec<String> {
+    let guard = lockr!(*REGISTRY);
+    guard.names()
+}
+
+/// Set the JSON schema describing what a command in the global registry
+/// returns
+pub fn returns(name: &str, result: Value) {
+    let mut guard = lockw!(*REGISTRY);
+    guard.returns(name, result);
+}
+
+/// Describe all the commands in the global registry
+pub fn describe() -> Vec<(String, Vec<Param>, Option<Value>)> {
+    let guard = lockr!(*REGISTRY);
+    guard.describe()
+}
+
+/// Find a command by name and run it.
+pub fn call(cmd: &String, turtl: &Turtl, args: Value) -> TResult<Value> {
+    // don't hold the registry lock while the command runs, otherwise commands
+    // can't list or register other commands
+    let command = {
+        let guard = lockr!(*REGISTRY);
+        guard.get(cmd)
+    };
+    match command {
+        Some(x) => x.run(turtl, args),
+        None => TErr!(TError::MissingCommand(cmd.clone())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct TestArgs {
+        name: String,
+        #[serde(default)]
+        count: Option<i64>,
+    }
+
+    fn parse<A: DeserializeOwned>(cmd: &String, args: Value) -> TResult<A> {
+        parse_args(cmd, args, schema::num_fields::<A>())
+    }
+
+    #[test]
+    fn parses_positional_args() {
+        let cmd = String::from("test:cmd");
+        let args: TestArgs = parse(&cmd, json!(["larry", 3])).unwrap();
+        assert_eq!(args.name, "larry");
+        assert_eq!(args.count, Some(3));
+        let args: TestArgs = parse(&cmd, json!(["larry"])).unwrap();
+        assert_eq!(args.count, None);
+        parse::<NoArgs>(&cmd, json!([])).unwrap();
+    }
+
+    #[test]
+    fn parses_named_args() {
+        let cmd = String::from("test:cmd");
+        let args: TestArgs = parse(&cmd, json!({"name": "larry", "count": 3})).unwrap();
+        assert_eq!(args.name, "larry");
+        assert_eq!(args.count, Some(3));
+        let args: TestArgs = parse(&cmd, json!({"name": "larry"})).unwrap();
+        assert_eq!(args.count, None);
+        parse::<NoArgs>(&cmd, json!({})).unwrap();
+        assert!(parse::<TestArgs>(&cmd, json!({"count": 3})).is_err());
+        assert!(parse::<TestArgs>(&cmd, json!({"name": "larry", "extra": 1})).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_args() {
+        let cmd = String::from("test:cmd");
+        assert!(parse::<TestArgs>(&cmd, json!([])).is_err());
+        assert!(parse::<TestArgs>(&cmd, json!([17])).is_err());
+    }
+
+    #[test]
+    fn ignores_extra_positional_args() {
+        let cmd = String::from("test:cmd");
+        let args: TestArgs = parse(&cmd, json!(["larry", 3, "extra"])).unwrap();
+        assert_eq!(args.name, "larry");
+        assert_eq!(args.count, Some(3));
+        parse::<NoArgs>(&cmd, json!(["extra"])).unwrap();
+        // non-struct arguments get everything
+        let args: Value = parse(&cmd, json!(["larry", 3])).unwrap();
+        assert_eq!(args, json!(["larry", 3]));
+    }
+
+    #[test]
+    fn lists_commands() {
+        let commands = list();
+        assert!(commands.contains(&String::from("ping")));
+        assert!(commands.contains(&String::from("app:list-commands")));
+        assert!(commands.contains(&String::from("user:login")));
+        register("test:registry:custom", |_turtl, _args: NoArgs| { Ok(json!(true)) });
+        assert!(list().contains(&String::from("test:registry:custom")));
+        assert!(unregister("test:registry:custom"));
+        assert!(!list().contains(&String::from("test:registry:custom")));
+    }
+}
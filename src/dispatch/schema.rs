@@ -0,0 +1,483 @@
+//! Builds a machine-readable description of core's API: every dispatch
+//! command (its arguments and what it returns), every UI event core sends, and
+//! every type of error a request can fail with. The format is loosely based on
+//! OpenRPC, with JSON Schema used to describe values.
+//!
+//! Command arguments aren't described by hand. Each command's argument struct
+//! is run through a "probe" deserializer that pretends to be a message and
+//! writes down what the struct asks it for (a string, an optional integer, an
+//! array of objects, etc). This way the schema can't drift from the structs
+//! that actually parse the arguments. Return values and event payloads don't
+//! have anything to probe, so those are listed in `dispatch::commands` and in
+//! `events()` below.
+//!
+//! The schema is available at runtime via the `app:api-schema` command, and
+//! can be dumped to a file at build time with `make api-schema`.
+
+use ::std::error::Error;
+use ::std::fmt;
+
+use ::serde::de::{self, Deserializer, Visitor, SeqAccess, MapAccess, DeserializeSeed, IntoDeserializer};
+use ::jedi::{self, Value, DeserializeOwned};
+
+use ::error::{TResult, ERROR_CODES};
+use ::dispatch::registry;
+
+/// Describes one argument of a command
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Param {
+    /// The argument's name, used in the named (object) message format
+    pub name: String,
+    /// Whether or not the argument has to be passed
+    pub required: bool,
+    /// A JSON schema describing the argument's value
+    pub schema: Value,
+}
+
+/// The error our probe deserializer returns. We keep track of "you didn't
+/// give me enough fields" errors separately, since that's how we find out
+/// which arguments are required.
+#[derive(Debug)]
+struct ProbeError {
+    msg: String,
+    missing: Option<usize>,
+}
+
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+impl Error for ProbeError {
+    fn description(&self) -> &str { self.msg.as_str() }
+}
+
+impl de::Error for ProbeError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        ProbeError { msg: format!("{}", msg), missing: None }
+    }
+
+    fn invalid_length(len: usize, exp: &de::Expected) -> Self {
+        ProbeError { msg: format!("invalid length {}, expected {}", len, exp), missing: Some(len) }
+    }
+}
+
+/// A deserializer that hands out dummy values and records the JSON schema of
+/// whatever was asked for into `out`.
+struct Probe<'a> {
+    out: &'a mut Value,
+    /// For the top-level struct only: where to put its field names (left as
+    /// `None` if the arguments aren't a struct)
+    fields: Option<&'a mut Option<Vec<&'static str>>>,
+    /// For the top-level struct only: how many fields to hand out before
+    /// pretending the rest were left off
+    limit: Option<usize>,
+}
+
+/// Schemas we never filled in (because the value's deserializer bailed before
+/// getting to them) can be anything
+fn or_any(schema: Value) -> Value {
+    if schema.is_null() { json!({}) } else { schema }
+}
+
+impl<'a> Probe<'a> {
+    fn new(out: &'a mut Value) -> Probe<'a> {
+        Probe { out: out, fields: None, limit: None }
+    }
+}
+
+/// Implements a deserialize_* method that records `$schema` and hands the
+/// visitor a dummy value
+macro_rules! probe_primitive {
+    ($method:ident, $schema:expr, $visit:ident) => {
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ProbeError> {
+            *self.out = $schema;
+            visitor.$visit()
+        }
+    };
+    ($method:ident, $schema:expr, $visit:ident, $val:expr) => {
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ProbeError> {
+            *self.out = $schema;
+            visitor.$visit($val)
+        }
+    };
+}
+
+impl<'de, 'a> Deserializer<'de> for Probe<'a> {
+    type Error = ProbeError;
+
+    // we don't know what the caller wants, so hand it something that most
+    // custom deserializers (ints stored as strings, etc) will accept
+    probe_primitive!(deserialize_any, json!({}), visit_u64, 0);
+    probe_primitive!(deserialize_ignored_any, json!({}), visit_unit);
+    probe_primitive!(deserialize_bool, json!({"type": "boolean"}), visit_bool, false);
+    probe_primitive!(deserialize_i8, json!({"type": "integer"}), visit_i64, 0);
+    probe_primitive!(deserialize_i16, json!({"type": "integer"}), visit_i64, 0);
+    probe_primitive!(deserialize_i32, json!({"type": "integer"}), visit_i64, 0);
+    probe_primitive!(deserialize_i64, json!({"type": "integer"}), visit_i64, 0);
+    probe_primitive!(deserialize_u8, json!({"type": "integer", "minimum": 0}), visit_u64, 0);
+    probe_primitive!(deserialize_u16, json!({"type": "integer", "minimum": 0}), visit_u64, 0);
+    probe_primitive!(deserialize_u32, json!({"type": "integer", "minimum": 0}), visit_u64, 0);
+    probe_primitive!(deserialize_u64, json!({"type": "integer", "minimum": 0}), visit_u64, 0);
+    probe_primitive!(deserialize_f32, json!({"type": "number"}), visit_f64, 0.0);
+    probe_primitive!(deserialize_f64, json!({"type": "number"}), visit_f64, 0.0);
+    probe_primitive!(deserialize_char, json!({"type": "string", "maxLength": 1}), visit_char, 'a');
+    probe_primitive!(deserialize_str, json!({"type": "string"}), visit_str, "");
+    probe_primitive!(deserialize_string, json!({"type": "string"}), visit_str, "");
+    probe_primitive!(deserialize_identifier, json!({"type": "string"}), visit_str, "");
+    probe_primitive!(deserialize_bytes, json!({"type": "string", "contentEncoding": "base64"}), visit_bytes, &[]);
+    probe_primitive!(deserialize_byte_buf, json!({"type": "string", "contentEncoding": "base64"}), visit_bytes, &[]);
+    probe_primitive!(deserialize_unit, json!({"type": "null"}), visit_unit);
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, ProbeError> {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ProbeError> {
+        let mut inner = Value::Null;
+        let res = visitor.visit_some(Probe::new(&mut inner));
+        *self.out = json!({"anyOf": [or_any(inner), {"type": "null"}]});
+        res
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, ProbeError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ProbeError> {
+        let mut items = vec![Value::Null];
+        let res = visitor.visit_seq(ProbeSeq { schemas: &mut items, index: 0 });
+        *self.out = json!({"type": "array", "items": or_any(items.pop().unwrap_or(Value::Null))});
+        res
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(self, len: usize, visitor: V) -> Result<V::Value, ProbeError> {
+        let mut items = vec![Value::Null; len];
+        let res = visitor.visit_seq(ProbeSeq { schemas: &mut items, index: 0 });
+        *self.out = json!({"type": "array", "items": items.into_iter().map(or_any).collect::<Vec<_>>(), "minItems": len, "maxItems": len});
+        res
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(self, _name: &'static str, len: usize, visitor: V) -> Result<V::Value, ProbeError> {
+        self.deserialize_tuple(len, visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ProbeError> {
+        let mut values = Value::Null;
+        let res = visitor.visit_map(ProbeMap { out: &mut values, done: false });
+        *self.out = json!({"type": "object", "additionalProperties": or_any(values)});
+        res
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(self, _name: &'static str, fields: &'static [&'static str], visitor: V) -> Result<V::Value, ProbeError> {
+        let mut schemas = vec![Value::Null; fields.len()];
+        let res = visitor.visit_seq(ProbeFields { schemas: &mut schemas, index: 0, limit: self.limit });
+        let mut properties = jedi::Map::new();
+        for (field, schema) in fields.iter().zip(schemas.into_iter()) {
+            properties.insert(String::from(*field), or_any(schema));
+        }
+        *self.out = json!({"type": "object", "properties": properties});
+        if let Some(out_fields) = self.fields {
+            *out_fields = Some(Vec::from(fields));
+        }
+        res
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, variants: &'static [&'static str], visitor: V) -> Result<V::Value, ProbeError> {
+        *self.out = json!({"type": "string", "enum": variants});
+        match variants.first() {
+            Some(x) => visitor.visit_enum((*x).into_deserializer()),
+            None => Err(de::Error::custom("enum has no variants")),
+        }
+    }
+}
+
+/// Hands out one probe per schema slot (used for seqs and tuples)
+struct ProbeSeq<'a> {
+    schemas: &'a mut Vec<Value>,
+    index: usize,
+}
+
+impl<'de, 'a> SeqAccess<'de> for ProbeSeq<'a> {
+    type Error = ProbeError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, ProbeError> {
+        if self.index >= self.schemas.len() { return Ok(None); }
+        let val = seed.deserialize(Probe::new(&mut self.schemas[self.index]))?;
+        self.index += 1;
+        Ok(Some(val))
+    }
+}
+
+/// Hands out one probe per struct field, optionally pretending the fields
+/// after `limit` were left off
+struct ProbeFields<'a> {
+    schemas: &'a mut Vec<Value>,
+    index: usize,
+    limit: Option<usize>,
+}
+
+impl<'de, 'a> SeqAccess<'de> for ProbeFields<'a> {
+    type Error = ProbeError;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>, ProbeError> {
+        if self.index >= self.schemas.len() { return Ok(None); }
+        match self.limit {
+            Some(limit) if self.index >= limit => return Ok(None),
+            _ => {}
+        }
+        let val = seed.deserialize(Probe::new(&mut self.schemas[self.index]))?;
+        self.index += 1;
+        Ok(Some(val))
+    }
+}
+
+/// Hands out a single map entry so we can find out what the values look like
+struct ProbeMap<'a> {
+    out: &'a mut Value,
+    done: bool,
+}
+
+impl<'de, 'a> MapAccess<'de> for ProbeMap<'a> {
+    type Error = ProbeError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>, ProbeError> {
+        if self.done { return Ok(None); }
+        self.done = true;
+        let mut key = Value::Null;
+        Ok(Some(seed.deserialize(Probe::new(&mut key))?))
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, ProbeError> {
+        seed.deserialize(Probe::new(&mut *self.out))
+    }
+}
+
+/// Probe an argument struct, optionally leaving off the fields after `limit`.
+/// Returns the struct's schema, its field names (if it is in fact a struct),
+/// and the probe's result.
+fn probe<A: DeserializeOwned>(limit: Option<usize>) -> (Value, Option<Vec<&'static str>>, Result<A, ProbeError>) {
+    let mut schema = Value::Null;
+    let mut fields = None;
+    let res = {
+        let probe = Probe {
+            out: &mut schema,
+            fields: Some(&mut fields),
+            limit: limit,
+        };
+        A::deserialize(probe)
+    };
+    (schema, fields, res)
+}
+
+/// Describe the arguments of a command that takes an `A`. Arguments are
+/// returned in the order they're expected in the positional message format.
+///
+/// An argument is required unless the struct gives it a default. Note that
+/// `Option` arguments without a default are still required, although they can
+/// be `null`.
+pub fn describe_args<A: DeserializeOwned>() -> Vec<Param> {
+    let (schema, fields, res) = probe::<A>(None);
+    match res {
+        Err(e) => debug!("schema::describe_args() -- probe stopped early, schema may be incomplete: {}", e),
+        _ => {}
+    }
+    let properties = match schema.get("properties") {
+        Some(&Value::Object(ref x)) => x.clone(),
+        _ => jedi::Map::new(),
+    };
+    fields.unwrap_or(Vec::new()).iter().enumerate()
+        .map(|(i, field)| {
+            // leave off everything from this field on. if the struct complains
+            // that this exact field is missing, it's required.
+            let required = match probe::<A>(Some(i)).2 {
+                Err(ProbeError { missing: Some(x), .. }) => x == i,
+                _ => false,
+            };
+            Param {
+                name: String::from(*field),
+                required: required,
+                schema: properties.get(*field).map(|x| x.clone()).unwrap_or(json!({})),
+            }
+        })
+        .collect::<Vec<_>>()
+}
+
+/// How many fields an argument struct has, or `None` if the arguments aren't a
+/// struct (ie, a `Value` or a tuple)
+pub fn num_fields<A: DeserializeOwned>() -> Option<usize> {
+    probe::<A>(None).1.map(|x| x.len())
+}
+
+/// Lists the UI events core sends, along with a description and the schema
+/// of each event's payload
+pub fn events() -> Vec<(&'static str, &'static str, Value)> {
+    let null = json!({"type": "null"});
+    let object = json!({"type": "object"});
+    vec![
+        ("messaging:ready", "Core is ready to take requests", json!({"type": "boolean"})),
+        ("pong", "Sent in response to a ping", null.clone()),
+        ("progress", "Progress of a long-running operation (see `progress`)", json!({
+            "type": "object",
+            "properties": {
+                "op": {"type": "string"},
+                "name": {"type": "string"},
+                "step": {"type": "string"},
+                "done": {"type": "integer"},
+                "total": {"type": ["integer", "null"]},
+                "bytes": {"type": "integer"},
+                "total_bytes": {"type": ["integer", "null"]},
+                "finished": {"type": "boolean"},
+            },
+        })),
+        ("user:login", "A user logged in", null.clone()),
+        ("user:logout", "The current user logged out", null.clone()),
+        ("user:logout:clear-cookie", "The UI should clear any saved login", null.clone()),
+        ("user:change-password:logout", "The user's password was changed elsewhere and they were logged out", object.clone()),
+        ("user:delete", "The current user's account was deleted", null.clone()),
+        ("migration-event", "Progress from migrating an old account", json!({
+            "type": "object",
+            "properties": {
+                "event": {"type": "string"},
+                "args": {},
+            },
+        })),
+        ("profile:loaded", "The user's profile was loaded", null.clone()),
+        ("profile:indexed", "The user's notes were indexed and can be searched", null.clone()),
+        ("profile:import:tally", "How many items an import has processed", json!({"type": "integer"})),
+        ("sync:connected", "Whether or not sync can talk to the API", json!({"type": "boolean"})),
+        ("sync:update", "An incoming sync item was applied", json!({"$ref": "#/components/schemas/SyncRecord"})),
+        ("sync:outgoing:complete", "All pending outgoing sync items were sent", null.clone()),
+        ("sync:outgoing:failure", "Outgoing sync items that failed", json!({"type": "array", "items": {"$ref": "#/components/schemas/SyncRecord"}})),
+        ("sync:file:downloaded", "A note's file finished downloading", json!({"type": "object", "properties": {"note_id": {"type": "string"}}})),
+        ("sync:file:uploaded", "A note's file finished uploading", json!({"type": "object", "properties": {"note_id": {"type": "string"}}})),
+    ]
+}
+
+/// Build the full API schema
+pub fn export() -> TResult<Value> {
+    let methods = registry::describe()
+        .into_iter()
+        .map(|(name, params, result)| {
+            json!({
+                "name": name,
+                "paramStructure": "either",
+                "params": params,
+                "result": {"name": "result", "schema": result.unwrap_or(json!({}))},
+            })
+        })
+        .collect::<Vec<_>>();
+    let events = events()
+        .into_iter()
+        .map(|(name, summary, schema)| json!({"name": name, "summary": summary, "schema": schema}))
+        .collect::<Vec<_>>();
+    let mut errors = jedi::Map::new();
+    for code in ERROR_CODES {
+        errors.insert(String::from(code.name), json!({
+            "type": code.name,
+            "code": code.num,
+            "retryable": code.retryable,
+            "summary": code.summary,
+        }));
+    }
+    Ok(json!({
+        "openrpc": "1.0.0",
+        "info": {
+            "title": "Turtl core",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "methods": methods,
+        "x-events": events,
+        "components": {
+            "errors": errors,
+            "schemas": {
+                "Response": {
+                    "type": "object",
+                    "properties": {
+                        "id": {"type": "string"},
+                        "e": {"type": "integer", "enum": [0, 1]},
+                        "d": {},
+                    },
+                },
+                "Error": {
+                    "type": "object",
+                    "properties": {
+                        "type": {"type": "string"},
+                        "code": {"type": "integer"},
+                        "retryable": {"type": "boolean"},
+                        "message": {},
+                        "details": {"type": ["object", "null"]},
+                    },
+                },
+                "SyncRecord": {"type": "object"},
+            },
+        },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    #[allow(dead_code)]
+    struct Nested {
+        name: String,
+        tags: Vec<String>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    #[allow(dead_code)]
+    struct TestArgs {
+        id: String,
+        count: Option<u32>,
+        nested: Nested,
+        #[serde(default)]
+        mode: Option<::wire::Encoding>,
+        #[serde(default)]
+        flag: bool,
+    }
+
+    #[test]
+    fn describes_args() {
+        let params = describe_args::<TestArgs>();
+        let names = params.iter().map(|x| x.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, vec!["id", "count", "nested", "mode", "flag"]);
+        let required = params.iter().map(|x| x.required).collect::<Vec<_>>();
+        assert_eq!(required, vec![true, true, true, false, false]);
+        assert_eq!(params[0].schema, json!({"type": "string"}));
+        assert_eq!(params[1].schema, json!({"anyOf": [{"type": "integer", "minimum": 0}, {"type": "null"}]}));
+        assert_eq!(params[2].schema, json!({
+            "type": "object",
+            "properties": {
+                "name": {"type": "string"},
+                "tags": {"type": "array", "items": {"type": "string"}},
+            },
+        }));
+        assert_eq!(params[3].schema, json!({"anyOf": [{"type": "string", "enum": ["json", "msgpack"]}, {"type": "null"}]}));
+        assert_eq!(params[4].schema, json!({"type": "boolean"}));
+        assert_eq!(describe_args::<registry::NoArgs>(), Vec::new());
+    }
+
+    #[test]
+    fn exports_schema() {
+        let schema = export().unwrap();
+        let methods: Vec<Value> = jedi::get(&["methods"], &schema).unwrap();
+        let login = methods.iter()
+            .find(|x| jedi::get_opt::<String>(&["name"], x) == Some(String::from("user:login")))
+            .unwrap();
+        let params: Vec<Value> = jedi::get(&["params"], login).unwrap();
+        let params = params.iter().map(|x| jedi::get::<String>(&["name"], x).unwrap()).collect::<Vec<_>>();
+        assert_eq!(params, vec!["username", "password"]);
+        let ping = methods.iter()
+            .find(|x| jedi::get_opt::<String>(&["name"], x) == Some(String::from("ping")))
+            .unwrap();
+        assert_eq!(jedi::get::<Value>(&["result", "schema"], ping).unwrap(), json!({"type": "string"}));
+        assert!(jedi::get::<Value>(&["components", "errors", "queue_full"], &schema).is_ok());
+        let events: Vec<Value> = jedi::get(&["x-events"], &schema).unwrap();
+        assert!(events.iter().any(|x| jedi::get_opt::<String>(&["name"], x) == Some(String::from("progress"))));
+    }
+}
@@ -1,5 +1,5 @@
 use ::std::error::Error;
-use ::std::io::Error as IoError;
+use ::std::io::{Error as IoError, ErrorKind as IoErrorKind};
 use ::std::convert::From;
 use ::std::sync::Arc;
 
@@ -13,113 +13,195 @@ use ::rusqlite;
 use ::crypto::CryptoError;
 use ::util;
 
-macro_rules! quick_error_obj {
-    ($ty:expr, $err:expr) => {
-        json!({"type": $ty, "message": util::json_or_string(format!("{}", $err))})
+/// Describes a type of error. The name (which is sent as the error's `type`)
+/// and number never change once an error type is released, so UIs can safely
+/// match on them.
+#[derive(Debug, PartialEq)]
+pub struct ErrorCode {
+    /// The error type's name
+    pub name: &'static str,
+    /// The error type's number
+    pub num: u32,
+    /// Whether an operation failing with this error can be tried again. Some
+    /// errors (API errors, IO errors) decide this on a case-by-case basis.
+    pub retryable: bool,
+    /// What this error means
+    pub summary: &'static str,
+}
+
+macro_rules! error_codes {
+    ($( ($name:expr, $num:expr, $retryable:expr, $summary:expr), )*) => {
+        &[ $( ErrorCode { name: $name, num: $num, retryable: $retryable, summary: $summary }, )* ]
     }
 }
 
+/// Every type of error core can return. Add new types at the end, and never
+/// change the name or number of an existing one.
+pub static ERROR_CODES: &'static [ErrorCode] = error_codes![
+    ("generic", 1, false, "Something went wrong"),
+    ("bad_value", 2, false, "A value (usually an argument) was invalid"),
+    ("missing_field", 3, false, "A required field was missing"),
+    ("missing_data", 4, false, "Some data we needed (a space, a note) couldn't be found"),
+    ("missing_command", 5, false, "The command doesn't exist"),
+    ("not_found", 6, false, "The requested item doesn't exist"),
+    ("permission_denied", 7, false, "The user isn't allowed to do that"),
+    ("validation", 8, false, "A model failed validation (see `details.fields`)"),
+    ("connection_required", 9, true, "The command needs a connection to the API"),
+    ("crypto_error", 10, false, "Encryption/decryption failed"),
+    ("json_error", 11, false, "Problem (de)serializing JSON"),
+    ("dumpy_error", 12, false, "Problem with local storage"),
+    ("clippy_error", 13, false, "Problem clipping a URL"),
+    ("migrate_error", 14, false, "Problem migrating an old account"),
+    ("io_error", 15, false, "Filesystem/network error (see `details.kind`)"),
+    ("api", 16, false, "The API returned an error (see `details.status`)"),
+    ("http", 17, false, "An HTTP call failed (see `details.status`)"),
+    ("parse_error", 18, false, "Problem parsing a value"),
+    ("try_again", 19, true, "Temporary failure, try again"),
+    ("not_implemented", 20, false, "Not implemented"),
+    ("cancelled", 21, false, "The request was cancelled via app:cancel-request"),
+    ("timeout", 22, true, "The request ran past its deadline"),
+    ("queue_full", 23, true, "Too many requests are queued, try again later"),
+    ("batch_aborted", 24, false, "A request in a batch failed (see `details.index`)"),
+];
+
+/// Look up an error code by name
+pub fn error_code(name: &str) -> &'static ErrorCode {
+    ERROR_CODES.iter()
+        .find(|x| x.name == name)
+        .unwrap_or(&ERROR_CODES[0])
+}
+
 quick_error! {
     #[derive(Debug)]
     /// Turtl's main error object.
+    ///
+    /// Errors display as JSON objects (see `TError::to_value()`).
     pub enum TError {
         Wrapped(function: &'static str, file: &'static str, line: u32, err: Arc<TError>) {
             description("Turtl wrap error")
-            display("{}", json!({"file": file, "line": line, "err": util::json_or_string(format!("{}", err)), "wrapped": true}))
+            display(me) -> ("{}", me.to_value())
         }
         Boxed(err: Box<Error + Send + Sync>) {
             description(err.description())
-            display("{}", quick_error_obj!("generic", err))
+            display(me) -> ("{}", me.to_value())
         }
         Msg(msg: String) {
             description(msg)
-            display("{}", quick_error_obj!("generic", msg))
+            display(me) -> ("{}", me.to_value())
         }
         BadValue(msg: String) {
             description(msg)
-            display("{}", quick_error_obj!("bad_value", msg))
+            display(me) -> ("{}", me.to_value())
         }
         MissingField(msg: String) {
             description(msg)
-            display("{}", quick_error_obj!("missing_field", msg))
+            display(me) -> ("{}", me.to_value())
         }
         MissingData(msg: String) {
             description(msg)
-            display("{}", quick_error_obj!("missing_data", msg))
+            display(me) -> ("{}", me.to_value())
         }
         MissingCommand(msg: String) {
             description(msg)
-            display("{}", quick_error_obj!("missing_command", msg))
+            display(me) -> ("{}", me.to_value())
         }
         NotFound(msg: String) {
             description(msg)
-            display("{}", quick_error_obj!("not_found", msg))
+            display(me) -> ("{}", me.to_value())
         }
         PermissionDenied(msg: String) {
             description(msg)
-            display("{}", quick_error_obj!("permission_denied", msg))
+            display(me) -> ("{}", me.to_value())
         }
         Validation(objtype: String, errors: Vec<(String, String)>) {
             description("validaton error")
-            display("{}", json!({"type": "validation", "subtype": objtype, "errors": errors}))
+            display(me) -> ("{}", me.to_value())
         }
         ConnectionRequired {
             description("connection required")
-            display("{}", json!({"type": "connection_required"}))
+            display(me) -> ("{}", me.to_value())
         }
         Crypto(err: CryptoError) {
             cause(err)
             description("crypto error")
-            display("{}", quick_error_obj!("crypto_error", err))
+            display(me) -> ("{}", me.to_value())
         }
         JSON(err: JSONError) {
             cause(err)
             description("JSON error")
-            display("{}", quick_error_obj!("json_error", err))
+            display(me) -> ("{}", me.to_value())
         }
         Dumpy(err: DError) {
             cause(err)
             description("Dumpy error")
-            display("{}", quick_error_obj!("dumpy_error", err))
+            display(me) -> ("{}", me.to_value())
         }
         Clippo(err: ClippoError) {
             cause(err)
             description("Clippo error")
-            display("{}", quick_error_obj!("clippy_error", err))
+            display(me) -> ("{}", me.to_value())
         }
         Migrate(err: MigrateError) {
             cause(err)
             description("migrate error")
-            display("{}", quick_error_obj!("migrate_error", err))
+            display(me) -> ("{}", me.to_value())
         }
         Io(err: IoError) {
             cause(err)
             description("io error")
-            display("{}", quick_error_obj!("io_error", err))
+            display(me) -> ("{}", me.to_value())
         }
         Api(status: StatusCode, msg: Value) {
             description("API error")
-            display("{}", json!({"type": "api", "subtype": status.canonical_reason().unwrap_or("unknown"), "message": msg}))
+            display(me) -> ("{}", me.to_value())
         }
         Http(status: StatusCode, msg: Value) {
             description("HTTP error")
-            display("{}", json!({"type": "http", "subtype": status.canonical_reason().unwrap_or("unknown"), "message": msg}))
+            display(me) -> ("{}", me.to_value())
         }
         ParseError(msg: String) {
             description("Parse error")
-            display("{}", quick_error_obj!("parse_error", msg))
+            display(me) -> ("{}", me.to_value())
         }
         TryAgain {
             description("try again")
-            display("{}", json!({"type": "try_again"}))
+            display(me) -> ("{}", me.to_value())
         }
         NotImplemented {
             description("not implemented")
-            display("{}", json!({"type": "not_implemented"}))
+            display(me) -> ("{}", me.to_value())
+        }
+        Cancelled(mid: String) {
+            description("request cancelled")
+            display(me) -> ("{}", me.to_value())
+        }
+        Timeout(mid: String) {
+            description("request timed out")
+            display(me) -> ("{}", me.to_value())
+        }
+        QueueFull(lane: String) {
+            description("queue full")
+            display(me) -> ("{}", me.to_value())
+        }
+        BatchAborted(index: usize, err: Arc<TError>) {
+            description("batch aborted")
+            display(me) -> ("{}", me.to_value())
         }
     }
 }
 
+/// Whether an API/HTTP call that failed with this status might work if tried
+/// again
+fn status_retryable(status: &StatusCode) -> bool {
+    let num = status.to_u16();
+    num >= 500 || num == 429 || num == 408
+}
+
+/// Grab a JSON message from an error (or string)
+fn message<T: ::std::fmt::Display>(err: T) -> Value {
+    util::json_or_string(format!("{}", err))
+}
+
 impl TError {
     /// Shed this TError object's tough, icy outer shell to reveal it's true
     /// sensitive inner-self.
@@ -137,6 +219,124 @@ impl TError {
             _ => self,
         }
     }
+
+    /// Get this error's code. Wrapped errors return their inner error's code.
+    pub fn code(&self) -> &'static ErrorCode {
+        let name = match *self {
+            TError::Wrapped(_, _, _, ref err) => return err.code(),
+            TError::Boxed(..) | TError::Msg(..) => "generic",
+            TError::BadValue(..) => "bad_value",
+            TError::MissingField(..) => "missing_field",
+            TError::MissingData(..) => "missing_data",
+            TError::MissingCommand(..) => "missing_command",
+            TError::NotFound(..) => "not_found",
+            TError::PermissionDenied(..) => "permission_denied",
+            TError::Validation(..) => "validation",
+            TError::ConnectionRequired => "connection_required",
+            TError::Crypto(..) => "crypto_error",
+            TError::JSON(..) => "json_error",
+            TError::Dumpy(..) => "dumpy_error",
+            TError::Clippo(..) => "clippy_error",
+            TError::Migrate(..) => "migrate_error",
+            TError::Io(..) => "io_error",
+            TError::Api(..) => "api",
+            TError::Http(..) => "http",
+            TError::ParseError(..) => "parse_error",
+            TError::TryAgain => "try_again",
+            TError::NotImplemented => "not_implemented",
+            TError::Cancelled(..) => "cancelled",
+            TError::Timeout(..) => "timeout",
+            TError::QueueFull(..) => "queue_full",
+            TError::BatchAborted(..) => "batch_aborted",
+        };
+        error_code(name)
+    }
+
+    /// Whether or not the operation that caused this error can be retried
+    pub fn retryable(&self) -> bool {
+        match *self {
+            TError::Wrapped(_, _, _, ref err) => err.retryable(),
+            TError::BatchAborted(_, ref err) => err.retryable(),
+            TError::Api(ref status, _) | TError::Http(ref status, _) => status_retryable(status),
+            TError::Io(ref err) => {
+                match err.kind() {
+                    IoErrorKind::TimedOut |
+                        IoErrorKind::Interrupted |
+                        IoErrorKind::WouldBlock |
+                        IoErrorKind::ConnectionRefused |
+                        IoErrorKind::ConnectionReset |
+                        IoErrorKind::ConnectionAborted |
+                        IoErrorKind::NotConnected |
+                        IoErrorKind::BrokenPipe => true,
+                    _ => false,
+                }
+            }
+            _ => self.code().retryable,
+        }
+    }
+
+    /// Get any structured details about this error (which field failed
+    /// validation, which request timed out, etc)
+    pub fn details(&self) -> Value {
+        match *self {
+            TError::Wrapped(_, _, _, ref err) => err.details(),
+            TError::Validation(ref objtype, ref errors) => {
+                let fields = errors.iter()
+                    .map(|&(ref field, ref msg)| json!({"field": field, "message": msg}))
+                    .collect::<Vec<_>>();
+                json!({"model": objtype, "fields": fields})
+            }
+            TError::Io(ref err) => json!({"kind": format!("{:?}", err.kind())}),
+            TError::Api(ref status, _) | TError::Http(ref status, _) => json!({"status": status.to_u16()}),
+            TError::Cancelled(ref mid) | TError::Timeout(ref mid) => json!({"mid": mid}),
+            TError::QueueFull(ref lane) => json!({"lane": lane}),
+            TError::BatchAborted(index, _) => json!({"index": index}),
+            _ => Value::Null,
+        }
+    }
+
+    /// Turn this error into a JSON object. Every (unwrapped) error has a
+    /// `type`, `code`, `retryable` and `details` field (see `ErrorCode`), and
+    /// usually a `message`. Wrapped errors look like
+    /// `{"file": ..., "line": ..., "err": {...}, "wrapped": true}`.
+    pub fn to_value(&self) -> Value {
+        let mut val = match *self {
+            TError::Wrapped(_, file, line, ref err) => {
+                return json!({"file": file, "line": line, "err": err.to_value(), "wrapped": true});
+            }
+            TError::Boxed(ref err) => json!({"message": message(err)}),
+            TError::Msg(ref msg) |
+                TError::BadValue(ref msg) |
+                TError::MissingField(ref msg) |
+                TError::MissingData(ref msg) |
+                TError::MissingCommand(ref msg) |
+                TError::NotFound(ref msg) |
+                TError::PermissionDenied(ref msg) |
+                TError::ParseError(ref msg) => json!({"message": message(msg)}),
+            // the mid/lane lives in `details()`
+            TError::Cancelled(..) => json!({"message": "request cancelled"}),
+            TError::Timeout(..) => json!({"message": "request timed out"}),
+            TError::QueueFull(..) => json!({"message": "queue full"}),
+            TError::Validation(ref objtype, ref errors) => json!({"subtype": objtype, "errors": errors}),
+            TError::Crypto(ref err) => json!({"message": message(err)}),
+            TError::JSON(ref err) => json!({"message": message(err)}),
+            TError::Dumpy(ref err) => json!({"message": message(err)}),
+            TError::Clippo(ref err) => json!({"message": message(err)}),
+            TError::Migrate(ref err) => json!({"message": message(err)}),
+            TError::Io(ref err) => json!({"message": message(err)}),
+            TError::Api(ref status, ref msg) | TError::Http(ref status, ref msg) => {
+                json!({"subtype": status.canonical_reason().unwrap_or("unknown"), "message": msg})
+            }
+            TError::BatchAborted(index, ref err) => json!({"index": index, "err": err.to_value()}),
+            TError::ConnectionRequired | TError::TryAgain | TError::NotImplemented => json!({}),
+        };
+        let code = self.code();
+        val["type"] = json!(code.name);
+        val["code"] = json!(code.num);
+        val["retryable"] = json!(self.retryable());
+        val["details"] = self.details();
+        val
+    }
 }
 
 /// Define a macro that, if and when the time is right, returns a static string
@@ -327,3 +527,51 @@ macro_rules! ftry {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::std::io::ErrorKind;
+
+    #[test]
+    fn error_codes_are_unique() {
+        for (i, code) in ERROR_CODES.iter().enumerate() {
+            for other in &ERROR_CODES[(i + 1)..] {
+                assert!(code.name != other.name, "duplicate error name {}", code.name);
+                assert!(code.num != other.num, "duplicate error number {}", code.num);
+            }
+        }
+    }
+
+    #[test]
+    fn structures_errors() {
+        let err = twrap!(TError::Validation(String::from("note"), vec![(String::from("title"), String::from("too long"))]));
+        assert_eq!(err.code().name, "validation");
+        assert!(!err.retryable());
+        let val = err.to_value();
+        assert_eq!(val["wrapped"], json!(true));
+        assert_eq!(val["err"]["type"], json!("validation"));
+        assert_eq!(val["err"]["code"], json!(8));
+        assert_eq!(val["err"]["details"], json!({"model": "note", "fields": [{"field": "title", "message": "too long"}]}));
+        assert_eq!(util::json_or_string(format!("{}", err)), val);
+
+        let val = TError::Msg(String::from("oh no")).to_value();
+        assert_eq!(val, json!({"type": "generic", "code": 1, "retryable": false, "message": "oh no", "details": null}));
+
+        let err = TError::Io(IoError::new(ErrorKind::TimedOut, "slow"));
+        assert!(err.retryable());
+        assert_eq!(err.details(), json!({"kind": "TimedOut"}));
+        assert!(!TError::Io(IoError::new(ErrorKind::NotFound, "where")).retryable());
+
+        assert!(TError::Api(StatusCode::ServiceUnavailable, Value::Null).retryable());
+        assert!(!TError::Api(StatusCode::Forbidden, Value::Null).retryable());
+        assert_eq!(TError::Api(StatusCode::Forbidden, Value::Null).details(), json!({"status": 403}));
+
+        let err = TError::BatchAborted(2, Arc::new(TError::QueueFull(String::from("normal"))));
+        assert!(err.retryable());
+        assert_eq!(err.to_value()["err"]["details"], json!({"lane": "normal"}));
+
+        let val = TError::Timeout(String::from("12")).to_value();
+        assert_eq!(val["message"], json!("request timed out"));
+        assert_eq!(val["details"], json!({"mid": "12"}));
+    }
+}
@@ -26,6 +26,7 @@ extern crate protected_derive;
 #[macro_use]
 extern crate quick_error;
 extern crate regex;
+extern crate rmpv;
 extern crate rusqlite;
 extern crate serde;
 #[macro_use]
@@ -41,12 +42,14 @@ pub mod error;
 mod util;
 mod crypto;
 mod messaging;
+mod wire;
 mod api;
 #[macro_use]
 mod sync;
 #[macro_use]
 mod models;
 mod profile;
+mod progress;
 mod storage;
 mod search;
 mod dispatch;
@@ -56,11 +59,15 @@ mod turtl;
 use ::std::thread;
 use ::std::sync::Arc;
 use ::std::env;
+use ::std::time::Duration;
 
 use ::jedi::Value;
 
 use ::error::TResult;
 
+pub use ::dispatch::{register_command, list_commands};
+pub use ::dispatch::schema::export as api_schema;
+
 /// Init any state/logging/etc the app needs
 pub fn init(config_str: String) -> TResult<()> {
     info!("main::init() -- init with user config {}", config_str);
@@ -127,26 +134,27 @@ pub fn start() -> thread::JoinHandle<()> {
             // create our turtl object
             let turtl = Arc::new(turtl::Turtl::new()?);
 
+            // incoming messages are run on a fixed pool of workers (see the
+            // `dispatch` config section)
+            let pool = Arc::new(dispatch::pool::DispatchPool::new(turtl.clone())?);
+
+            // let UIs in other processes attach, if configured
+            #[cfg(unix)]
+            let _socket = messaging::serve_socket()?;
+
             // start our messaging thread
-            let msg_res = messaging::start(move |msg: String| {
-                let turtl2 = turtl.clone();
-                // spawn a new thread for each message. this lets us process
-                // multiple messages at once without blocking.
-                let res = thread::Builder::new().name(String::from("dispatch:msg")).spawn(move || {
-                    match dispatch::process(turtl2.as_ref(), &msg) {
-                        Ok(..) => {},
-                        Err(e) => error!("dispatch::process() -- error processing: {}", e),
-                    }
-                });
-                match res {
+            let pool2 = pool.clone();
+            let msg_res = messaging::start(move |msg: Vec<u8>| {
+                match pool2.queue(turtl.as_ref(), msg) {
                     Ok(..) => {},
-                    Err(e) => error!("main::start() -- message processor: error spawning thread: {}", e),
+                    Err(e) => error!("main::start() -- error queuing message: {}", e),
                 }
             });
             match msg_res {
                 Ok(..) => {},
                 Err(e) => error!("main::start() -- messaging error: {}", e),
             }
+            pool.shutdown();
             info!("main::start() -- shutting down");
             Ok(())
         };
@@ -163,66 +171,133 @@ pub fn start() -> thread::JoinHandle<()> {
 
 /// Send a message into turtl's dispatcher
 pub fn send(msg: String) -> TResult<()> {
+    send_bytes(Vec::from(msg.as_bytes()))
+}
+
+/// Send a raw (JSON or MessagePack) message into turtl's dispatcher
+pub fn send_bytes(msg: Vec<u8>) -> TResult<()> {
     let channel: String = format!("{}-core-in", config::get::<String>(&["messaging", "reqres"])?);
-    carrier::send_string(channel.as_str(), msg)?;
+    carrier::send(channel.as_str(), msg)?;
     Ok(())
 }
 
-fn recv_impl(event: bool, msg_id: Option<&str>) -> TResult<String> {
-    let chan_switch = if event { "events" } else { "reqres" };
-    let chan_cfg: String = config::get(&["messaging", chan_switch])?;
-    let channel: String = match msg_id {
-        Some(id) => format!("{}-core-out:{}", chan_cfg, id),
-        None => {
-            if event {
-                chan_cfg
-            } else {
-                format!("{}-core-out", chan_cfg)
-            }
-        }
-    };
-    let msg = carrier::recv(channel.as_str())?;
-    Ok(String::from_utf8(msg)?)
+/// Build the name of the channel we receive on. For events, the id is the
+/// name of an event subscriber (see `messaging::subscribe()`).
+fn channel_name(event: bool, chan_cfg: String, id: Option<&str>) -> String {
+    match (event, id) {
+        (true, Some(id)) => format!("{}:{}", chan_cfg, id),
+        (true, None) => chan_cfg,
+        (false, Some(id)) => format!("{}-core-out:{}", chan_cfg, id),
+        (false, None) => format!("{}-core-out", chan_cfg),
+    }
 }
 
-fn recv_nb_impl(event: bool, msg_id: Option<&str>) -> TResult<Option<String>> {
+/// Grab the channel we receive messages (or events) on
+fn recv_channel(event: bool, msg_id: Option<&str>) -> TResult<String> {
     let chan_switch = if event { "events" } else { "reqres" };
     let chan_cfg: String = config::get(&["messaging", chan_switch])?;
-    let channel: String = match msg_id {
-        Some(id) => format!("{}-core-out:{}", chan_cfg, id),
-        None => {
-            if event {
-                chan_cfg
-            } else {
-                format!("{}-core-out", chan_cfg)
-            }
+    Ok(channel_name(event, chan_cfg, msg_id))
+}
+
+/// Turn a message from core into a JSON string. Responses use the encoding of
+/// the request they answer, and events use `messaging.encoding`, so MessagePack
+/// gets converted.
+fn msg_string(msg: Vec<u8>) -> TResult<String> {
+    match wire::Encoding::detect(&msg) {
+        wire::Encoding::Json => Ok(String::from_utf8(msg)?),
+        wire::Encoding::Msgpack => {
+            let (val, _) = wire::decode(&msg)?;
+            Ok(jedi::stringify(&val)?)
         }
-    };
-    let msg = carrier::recv_nb(channel.as_str())?;
-    let mapped = match msg {
-        Some(x) => Some(String::from_utf8(x)?),
-        None => None,
-    };
-    Ok(mapped)
+    }
+}
+
+fn recv_impl(event: bool, msg_id: Option<&str>) -> TResult<Vec<u8>> {
+    let channel = recv_channel(event, msg_id)?;
+    Ok(carrier::recv(channel.as_str())?)
 }
+
+fn recv_nb_impl(event: bool, msg_id: Option<&str>) -> TResult<Option<Vec<u8>>> {
+    let channel = recv_channel(event, msg_id)?;
+    Ok(carrier::recv_nb(channel.as_str())?)
+}
+
+fn recv_timeout_impl(event: bool, msg_id: Option<&str>, timeout_ms: u64) -> TResult<Option<Vec<u8>>> {
+    let channel = recv_channel(event, msg_id)?;
+    match carrier::recv_timeout(channel.as_str(), Duration::from_millis(timeout_ms)) {
+        Ok(x) => Ok(Some(x)),
+        Err(carrier::CError::Timeout) => Ok(None),
+        Err(e) => Err(From::from(e)),
+    }
+}
+
+/// Convert an optional message into an optional JSON string
+fn opt_msg_string(msg: Option<Vec<u8>>) -> TResult<Option<String>> {
+    match msg {
+        Some(x) => Ok(Some(msg_string(x)?)),
+        None => Ok(None),
+    }
+}
+
 /// Receive a turtl message (blocking)
 pub fn recv(msg_id: Option<&str>) -> TResult<String> {
+    msg_string(recv_impl(false, msg_id)?)
+}
+
+/// Receive a turtl message (blocking) as raw bytes, encoded the same way as
+/// the request it answers
+pub fn recv_bytes(msg_id: Option<&str>) -> TResult<Vec<u8>> {
     recv_impl(false, msg_id)
 }
 
 /// Receive a turtl event (blocking)
 pub fn recv_event() -> TResult<String> {
+    msg_string(recv_impl(true, None)?)
+}
+
+/// Receive a turtl event (blocking) as raw bytes, encoded using
+/// `messaging.encoding`
+pub fn recv_event_bytes() -> TResult<Vec<u8>> {
     recv_impl(true, None)
 }
 
 /// Receive a turtl message (non-blocking)
 pub fn recv_nb(msg_id: Option<&str>) -> TResult<Option<String>> {
-    recv_nb_impl(false, msg_id)
+    opt_msg_string(recv_nb_impl(false, msg_id)?)
 }
 
 /// Receive a turtl message (non-blocking)
 pub fn recv_event_nb() -> TResult<Option<String>> {
-    recv_nb_impl(true, None)
+    opt_msg_string(recv_nb_impl(true, None)?)
+}
+
+/// Receive a turtl message, giving up after `timeout_ms` milliseconds (in
+/// which case we return None)
+pub fn recv_timeout(msg_id: Option<&str>, timeout_ms: u64) -> TResult<Option<String>> {
+    opt_msg_string(recv_timeout_impl(false, msg_id, timeout_ms)?)
+}
+
+/// Receive a turtl message as raw bytes, giving up after `timeout_ms`
+/// milliseconds (in which case we return None)
+pub fn recv_timeout_bytes(msg_id: Option<&str>, timeout_ms: u64) -> TResult<Option<Vec<u8>>> {
+    recv_timeout_impl(false, msg_id, timeout_ms)
+}
+
+/// Receive a turtl event, giving up after `timeout_ms` milliseconds (in which
+/// case we return None)
+pub fn recv_event_timeout(timeout_ms: u64) -> TResult<Option<String>> {
+    opt_msg_string(recv_timeout_impl(true, None, timeout_ms)?)
+}
+
+/// Receive a turtl event for the given subscriber (blocking). See the
+/// `app:events:subscribe` command.
+pub fn recv_event_sub(subscriber: &str) -> TResult<String> {
+    msg_string(recv_impl(true, Some(subscriber))?)
+}
+
+/// Receive a turtl event for the given subscriber (non-blocking)
+pub fn recv_event_sub_nb(subscriber: &str) -> TResult<Option<String>> {
+    opt_msg_string(recv_nb_impl(true, Some(subscriber))?)
 }
 
 // -----------------------------------------------------------------------------
@@ -243,17 +318,28 @@ pub mod c_api {
     }
 
     macro_rules! cerror {
-        ($( $arg:tt ),* ) => {{
+        (err: $err:expr, $( $arg:tt ),* ) => {{
             if util::logger::has_init() {
                 error!($( $arg ),*);
             } else {
                 println!($( $arg ),*);
             }
-            let errstr = format!($( $arg ),*);
-            let mut guard = lockw!(*LAST_ERR);
-            *guard = Some(errstr);
-            drop(guard);
-        }}
+            set_lasterr(&$err);
+        }};
+        ($( $arg:tt ),* ) => {{
+            cerror!(err: ::error::TError::Msg(format!($( $arg ),*)), $( $arg ),*);
+        }};
+    }
+
+    /// Store an error for `turtlc_lasterr()` in the same format dispatch uses,
+    /// so callers can handle errors from both the same way
+    fn set_lasterr(err: &::error::TError) {
+        let errstr = match ::jedi::stringify(&::messaging::error_val(err)) {
+            Ok(x) => x,
+            Err(_) => format!("{}", err),
+        };
+        let mut guard = lockw!(*LAST_ERR);
+        *guard = Some(errstr);
     }
 
     #[no_mangle]
@@ -271,7 +357,7 @@ pub mod c_api {
             match init(String::from(&config[..])) {
                 Ok(_) => (),
                 Err(e) => {
-                    cerror!("turtlc_start() -- error: init(): {}", e);
+                    cerror!(err: e, "turtlc_start() -- error: init(): {}", e);
                     return -3;
                 },
             }
@@ -316,7 +402,15 @@ pub mod c_api {
         carrier::c::carrier_send(cstr.as_ptr(), message_bytes, message_len)
     }
 
-    fn turtlc_recv_any(non_block: u8, event: u8, msgid_c: *const c_char, len_c: *mut usize) -> *const u8 {
+    /// Flag a failed receive to the caller via `len_c` (if they gave us one)
+    fn set_err_len(len_c: *mut usize) {
+        if len_c.is_null() { return; }
+        unsafe { *len_c = 1; }
+    }
+
+    /// Receive from one of our channels. If `timeout_ms` is given, we wait at
+    /// most that long (and `non_block` is ignored).
+    fn turtlc_recv_any(non_block: u8, timeout_ms: Option<u64>, event: u8, msgid_c: *const c_char, len_c: *mut usize) -> *const u8 {
         let null = ptr::null_mut();
         let non_block = non_block == 1;
         let is_ev = event == 1;
@@ -325,7 +419,7 @@ pub mod c_api {
             Ok(x) => x,
             Err(e) => {
                 cerror!("turtlc_recv() -- problem grabbing address (messaging.reqres) from config: {}", e);
-                unsafe { *len_c = 1; }
+                set_err_len(len_c);
                 return null;
             }
         };
@@ -337,7 +431,7 @@ pub mod c_api {
                 Ok(x) => x,
                 Err(e) => {
                     cerror!("turtlc_recv() -- bad suffix given: {}", e);
-                    unsafe { *len_c = 1; }
+                    set_err_len(len_c);
                     return null;
                 }
             }
@@ -349,25 +443,88 @@ pub mod c_api {
             Ok(x) => x,
             Err(e) => {
                 cerror!("turtlc_recv() -- bad channel passed: {}", e);
-                unsafe { *len_c = 1; }
+                set_err_len(len_c);
                 return null;
             }
         };
-        if non_block {
-            carrier::c::carrier_recv_nb(cstr.as_ptr(), len_c)
-        } else {
-            carrier::c::carrier_recv(cstr.as_ptr(), len_c)
+        match timeout_ms {
+            Some(ms) => carrier::c::carrier_recv_timeout(cstr.as_ptr(), ms, len_c),
+            None => {
+                if non_block {
+                    carrier::c::carrier_recv_nb(cstr.as_ptr(), len_c)
+                } else {
+                    carrier::c::carrier_recv(cstr.as_ptr(), len_c)
+                }
+            }
         }
     }
 
     #[no_mangle]
     pub extern fn turtlc_recv(non_block: u8, msgid_c: *const c_char, len_c: *mut usize) -> *const u8 {
-        turtlc_recv_any(non_block, 0, msgid_c, len_c)
+        turtlc_recv_any(non_block, None, 0, msgid_c, len_c)
     }
 
     #[no_mangle]
     pub extern fn turtlc_recv_event(non_block: u8, len_c: *mut usize) -> *const u8 {
-        turtlc_recv_any(non_block, 1, ptr::null(), len_c)
+        turtlc_recv_any(non_block, None, 1, ptr::null(), len_c)
+    }
+
+    #[no_mangle]
+    pub extern fn turtlc_recv_timeout(msgid_c: *const c_char, timeout_ms: u64, len_c: *mut usize) -> *const u8 {
+        turtlc_recv_any(0, Some(timeout_ms), 0, msgid_c, len_c)
+    }
+
+    #[no_mangle]
+    pub extern fn turtlc_recv_event_timeout(timeout_ms: u64, len_c: *mut usize) -> *const u8 {
+        turtlc_recv_any(0, Some(timeout_ms), 1, ptr::null(), len_c)
+    }
+
+    #[no_mangle]
+    pub extern fn turtlc_recv_event_sub(non_block: u8, subscriber_c: *const c_char, len_c: *mut usize) -> *const u8 {
+        if subscriber_c.is_null() {
+            cerror!("turtlc_recv_event_sub() -- null subscriber given");
+            set_err_len(len_c);
+            return ptr::null();
+        }
+        turtlc_recv_any(non_block, None, 1, subscriber_c, len_c)
+    }
+
+    /// Subscribe to the events broadcast channel, returning the subscription
+    /// id (0 on error)
+    #[no_mangle]
+    pub extern fn turtlc_events_subscribe() -> u64 {
+        let channel: String = match config::get(&["messaging", "events"]) {
+            Ok(x) => x,
+            Err(e) => {
+                cerror!("turtlc_events_subscribe() -- problem grabbing address (messaging.events) from config: {}", e);
+                return 0;
+            }
+        };
+        let cstr = match CString::new(channel) {
+            Ok(x) => x,
+            Err(e) => {
+                cerror!("turtlc_events_subscribe() -- bad channel: {}", e);
+                return 0;
+            }
+        };
+        carrier::c::carrier_subscribe(cstr.as_ptr())
+    }
+
+    #[no_mangle]
+    pub extern fn turtlc_events_recv(sub_id: u64, timeout_ms: i64, len_c: *mut usize) -> *const u8 {
+        carrier::c::carrier_recv_subscription(sub_id, timeout_ms, len_c)
+    }
+
+    #[no_mangle]
+    pub extern fn turtlc_events_unsubscribe(sub_id: u64) -> i32 {
+        carrier::c::carrier_unsubscribe(sub_id)
+    }
+
+    /// Get a JSON listing of carrier's channels (same format as the
+    /// `app:debug:channels` command). Free with `turtlc_free()`.
+    #[no_mangle]
+    pub extern fn turtlc_channels(len_c: *mut usize) -> *const u8 {
+        carrier::c::carrier_channels(len_c)
     }
 
     #[no_mangle]
@@ -412,7 +569,7 @@ pub mod android {
     use super::*;
     use self::jni::JNIEnv;
     use self::jni::objects::{JObject, JClass, JString};
-    use self::jni::sys::{jint, jbyteArray, jstring};
+    use self::jni::sys::{jint, jlong, jbyteArray, jstring};
     use ::std::ffi::{CString, CStr};
     use ::std::slice;
 
@@ -441,6 +598,15 @@ pub mod android {
         }}
     }
 
+    /// Throw a Java exception. Used where a null return already means something
+    /// (like a timeout) so errors need another way out.
+    fn throw(env: &JNIEnv, msg: &str) {
+        match env.throw_new("java/lang/RuntimeException", msg) {
+            Err(e) => error!("main::jni::throw() -- problem throwing exception ({}): {}", msg, e),
+            _ => {}
+        }
+    }
+
     #[no_mangle]
     pub unsafe extern fn Java_com_lyonbros_turtlcore_TurtlCoreNative_start(env: JNIEnv, _class: JClass, config: JString) -> jint {
         let config_cstring = to_c_string!("main::jni::start()", env, config, -6);
@@ -503,6 +669,34 @@ pub mod android {
         byte_array
     }
 
+    /// Returns null on timeout (same as recv_nb) and throws on error
+    #[no_mangle]
+    pub unsafe extern fn Java_com_lyonbros_turtlcore_TurtlCoreNative_recv_1timeout(env: JNIEnv, _class: JClass, mid: JString, timeout_ms: jlong) -> jbyteArray {
+        let null_array = JObject::null().into_inner();
+        let mid_cstring = to_c_string!("main::jni::recv_timeout()", env, mid, { throw(&env, "main::jni::recv_timeout() -- bad mid given"); null_array });
+        let mut len: usize = 0;
+        let raw_len = &mut len as *mut usize;
+        let timeout_ms = if timeout_ms < 0 { 0 } else { timeout_ms as u64 };
+        let msg_c = c_api::turtlc_recv_timeout(mid_cstring.as_ptr(), timeout_ms, raw_len);
+        if msg_c.is_null() {
+            // a null with a length is an error, not a timeout
+            if len > 0 {
+                throw(&env, "main::jni::recv_timeout() -- error receiving message");
+            }
+            return null_array;
+        }
+        let slice = slice::from_raw_parts(msg_c, len);
+        let byte_array = match env.byte_array_from_slice(slice) {
+            Ok(x) => x,
+            Err(e) => {
+                error!("main::jni::recv_timeout() -- could not convert message to java byte array: {}", e);
+                null_array
+            }
+        };
+        c_api::turtlc_free(msg_c, len);
+        byte_array
+    }
+
     #[no_mangle]
     pub unsafe extern fn Java_com_lyonbros_turtlcore_TurtlCoreNative_recv_1event(env: JNIEnv, _class: JClass) -> jbyteArray {
         let null_array = JObject::null().into_inner();
@@ -545,6 +739,33 @@ pub mod android {
         byte_array
     }
 
+    /// Returns null on timeout (same as recv_event_nb) and throws on error
+    #[no_mangle]
+    pub unsafe extern fn Java_com_lyonbros_turtlcore_TurtlCoreNative_recv_1event_1timeout(env: JNIEnv, _class: JClass, timeout_ms: jlong) -> jbyteArray {
+        let null_array = JObject::null().into_inner();
+        let mut len: usize = 0;
+        let raw_len = &mut len as *mut usize;
+        let timeout_ms = if timeout_ms < 0 { 0 } else { timeout_ms as u64 };
+        let msg_c = c_api::turtlc_recv_event_timeout(timeout_ms, raw_len);
+        if msg_c.is_null() {
+            // a null with a length is an error, not a timeout
+            if len > 0 {
+                throw(&env, "main::jni::recv_event_timeout() -- error receiving message");
+            }
+            return null_array;
+        }
+        let slice = slice::from_raw_parts(msg_c, len);
+        let byte_array = match env.byte_array_from_slice(slice) {
+            Ok(x) => x,
+            Err(e) => {
+                error!("main::jni::recv_event_timeout() -- could not convert message to java byte array: {}", e);
+                null_array
+            }
+        };
+        c_api::turtlc_free(msg_c, len);
+        byte_array
+    }
+
     #[no_mangle]
     pub unsafe extern fn Java_com_lyonbros_turtlcore_TurtlCoreNative_lasterr(env: JNIEnv, _class: JClass) -> jstring {
         let err_c = c_api::turtlc_lasterr();
@@ -639,6 +860,29 @@ mod tests {
         handle.join().unwrap();
     }
 
+    #[test]
+    fn rust_api_msgpack() {
+        let handle = start(String::from(r#"{"messaging":{"reqres_append_mid":true}}"#));
+
+        // responses come back in the encoding the request was sent in
+        send_bytes(wire::encode(json!(["1", "ping"]), wire::Encoding::Msgpack).unwrap()).unwrap();
+        let res_bytes = recv_bytes(Some("1")).unwrap();
+        let (res, encoding) = wire::decode(&res_bytes).unwrap();
+        assert_eq!(encoding, wire::Encoding::Msgpack);
+        assert_eq!(res, json!({"e": 0, "d": "pong"}));
+        recv_event().unwrap();
+
+        // and the string API hands them back as JSON
+        send_bytes(wire::encode(json!(["2", "ping"]), wire::Encoding::Msgpack).unwrap()).unwrap();
+        let res: Value = jedi::parse(&recv(Some("2")).unwrap()).unwrap();
+        assert_eq!(res, json!({"e": 0, "d": "pong"}));
+        recv_event().unwrap();
+
+        send(String::from(r#"["3","app:shutdown"]"#)).unwrap();
+        recv(Some("3")).unwrap();
+        handle.join().unwrap();
+    }
+
     #[test]
     fn c_api() {
         let handle = thread::spawn(|| {
@@ -3,12 +3,39 @@
 //!
 //! This module is essentially the window into the app, essentially acting as an
 //! event bus to/from our remote sender (generally, this is a UI of some sort).
+//!
+//! UI events go out on the `messaging.events` channel. Consumers that only care
+//! about some events (or that don't want to fight another consumer over the
+//! main channel) can subscribe with a list of filters using the
+//! `app:events:subscribe` command. Each subscriber gets its own channel,
+//! `<messaging.events>:<subscriber>`, and only receives events matching its
+//! filters. A filter is either an exact event name (`sync:update`) or a prefix
+//! ending in `*` (`sync:*`, or `*` for everything).
+//!
+//! Every event is also published on the `messaging.events` broadcast channel
+//! (see `carrier::subscribe()`), so any number of consumers can each get a copy
+//! of every event without having to register filters with core first.
+//!
+//! If nobody drains an events channel, events pile up in memory. The
+//! `messaging.events_capacity` config puts a cap on the main events channel and
+//! on each subscriber channel, and `messaging.events_overflow` decides what
+//! happens once a channel is full (see `carrier::Overflow`). Events that don't
+//! fit are logged and dropped, never handed back to whatever sent them.
+//!
+//! Normally the UI links core and talks to it in-process. Setting the
+//! `messaging.unix_socket` config exposes the same channels over a Unix socket
+//! (see `carrier::unix`) so a UI can run in its own process instead.
+
+use ::std::collections::HashMap;
+use ::std::sync::RwLock;
 
 use ::carrier;
 use ::jedi::{self, Value, Serialize};
 
 use ::config;
 use ::error::{TResult, TError};
+use ::util;
+use ::wire;
 
 /// Defines a container for sending responses to the client. We could use a hash
 /// table, but then the elements might serialize out of order. This allows us to
@@ -43,6 +70,28 @@ impl Response {
     }
 }
 
+/// Turn an error into the value we send back to the UI in a `Response`. Unless
+/// the `wrap_errors` config is set, this strips off any file/line wrapping.
+pub fn error_val(err: &TError) -> Value {
+    let errval = util::json_or_string(format!("{}", err));
+    let wrapped = match jedi::get_opt::<bool>(&["wrapped"], &errval) {
+        Some(x) => x,
+        None => false,
+    };
+    let wrap_errors: bool = match config::get(&["wrap_errors"]) {
+        Ok(x) => x,
+        Err(_) => false,
+    };
+    if !wrap_errors && wrapped {
+        match jedi::get(&["err"], &errval) {
+            Ok(x) => x,
+            Err(_) => errval,
+        }
+    } else {
+        errval
+    }
+}
+
 /// Defines a container for sending events to the client. See the `Response`
 /// object for notes.
 #[derive(Serialize, Deserialize, Debug)]
@@ -54,6 +103,113 @@ pub struct Event {
     pub d: Value,
 }
 
+lazy_static! {
+    /// Holds our event subscribers and their filters, by subscriber name
+    static ref SUBSCRIBERS: RwLock<HashMap<String, Vec<String>>> = RwLock::new(HashMap::new());
+}
+
+/// Check if an event name matches any of the given filters
+fn filter_matches(filters: &Vec<String>, name: &str) -> bool {
+    filters.iter().any(|filter| {
+        if filter.ends_with("*") {
+            name.starts_with(&filter[0..(filter.len() - 1)])
+        } else {
+            filter == name
+        }
+    })
+}
+
+/// Get the channel a subscriber receives its events on
+pub fn subscriber_channel(subscriber: &str) -> TResult<String> {
+    let channel: String = config::get(&["messaging", "events"])?;
+    Ok(format!("{}:{}", channel, subscriber))
+}
+
+/// Apply our `events_capacity`/`events_overflow` config to an events channel.
+/// Events are sent from dispatch workers and the sync threads, which can't sit
+/// around waiting for the UI to make room, so `block` isn't allowed here.
+fn limit_events_channel(channel: &str) -> TResult<()> {
+    let capacity: Option<usize> = match config::get(&["messaging", "events_capacity"]) {
+        Ok(x) => x,
+        Err(_) => None,
+    };
+    let capacity = match capacity {
+        Some(x) => x,
+        None => return Ok(()),
+    };
+    let overflow: String = match config::get(&["messaging", "events_overflow"]) {
+        Ok(x) => x,
+        Err(_) => String::from("drop_oldest"),
+    };
+    let overflow: carrier::Overflow = overflow.parse()?;
+    if overflow == carrier::Overflow::Block {
+        return TErr!(TError::BadValue(String::from("messaging::limit_events_channel() -- messaging.events_overflow cannot be \"block\"")));
+    }
+    carrier::set_capacity(channel, capacity, overflow)?;
+    Ok(())
+}
+
+/// If `messaging.unix_socket` is set, serve our channels on a Unix socket at
+/// that path so a UI (or tool) in another process can talk to us. The socket
+/// goes away when the returned server is dropped.
+#[cfg(unix)]
+pub fn serve_socket() -> TResult<Option<carrier::unix::Server>> {
+    let path: Option<String> = match config::get(&["messaging", "unix_socket"]) {
+        Ok(x) => x,
+        Err(_) => None,
+    };
+    match path {
+        Some(path) => {
+            info!("messaging::serve_socket() -- listening on {}", path);
+            Ok(Some(carrier::unix::serve(path)?))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Subscribe to UI events matching the given filters. The events are sent on
+/// `subscriber_channel(subscriber)`. Subscribing again with the same name
+/// replaces that subscriber's filters.
+pub fn subscribe(subscriber: &str, filters: Vec<String>) -> TResult<()> {
+    if subscriber == "" {
+        return TErr!(TError::BadValue(String::from("messaging::subscribe() -- subscriber name cannot be blank")));
+    }
+    {
+        let mut guard = lockw!(*SUBSCRIBERS);
+        guard.insert(String::from(subscriber), filters);
+    }
+    match subscriber_channel(subscriber) {
+        Ok(channel) => limit_events_channel(channel.as_str())?,
+        Err(_) => {}
+    }
+    Ok(())
+}
+
+/// Stop sending events to a subscriber. Returns false if there was no such
+/// subscriber. Any events already sent to the subscriber's channel stay there
+/// until they're received.
+pub fn unsubscribe(subscriber: &str) -> bool {
+    let removed = {
+        let mut guard = lockw!(*SUBSCRIBERS);
+        guard.remove(subscriber).is_some()
+    };
+    // let the channel get cleaned up once it's drained
+    match subscriber_channel(subscriber) {
+        Ok(channel) => carrier::clear_capacity(channel.as_str()),
+        Err(_) => {}
+    }
+    removed
+}
+
+/// List the subscribers (if any) that want to hear about the given event
+fn subscribers_for(name: &str) -> Vec<String> {
+    let guard = lockr!(*SUBSCRIBERS);
+    guard.iter()
+        .filter(|&(_, filters)| filter_matches(filters, name))
+        .map(|(subscriber, _)| subscriber.clone())
+        .collect::<Vec<_>>()
+}
+
 pub struct Messenger {
     /// Whether we're bound or not. Kind of vestigial
     bound: bool,
@@ -99,23 +255,55 @@ impl Messenger {
     }
 
     /// Send an event out to our UI thread. Note that this is a static method!
+    ///
+    /// Events are encoded using the `messaging.encoding` config. The event
+    /// goes out on the main events channel (unless `messaging.events_unfiltered`
+    /// is false) and to every subscriber whose filters match it.
     pub fn event(name: &str, data: Value) -> TResult<()> {
         let channel: String = config::get(&["messaging", "events"])?;
+        let unfiltered: bool = match config::get(&["messaging", "events_unfiltered"]) {
+            Ok(x) => x,
+            Err(_) => true,
+        };
         let event = Event {
             e: String::from(name),
             d: data,
         };
-        let msg = jedi::stringify(&event)?;
+        let msg = wire::encode(jedi::to_val(&event)?, wire::default_encoding())?;
+        for subscriber in subscribers_for(name) {
+            let sub_channel = format!("{}:{}", channel, subscriber);
+            debug!("messaging: event: {} ({})", sub_channel, msg.len());
+            // one backed-up subscriber shouldn't keep the others from
+            // getting their events
+            match carrier::send(sub_channel.as_str(), msg.clone()) {
+                Ok(_) => {}
+                Err(e) => warn!("messaging: event: problem sending {} to {}: {}", name, sub_channel, e),
+            }
+        }
+        // anyone listening on the broadcast channel gets a copy
+        carrier::publish(channel.as_str(), msg.clone());
+        if !unfiltered { return Ok(()); }
         debug!("messaging: event: {} ({})", channel, msg.len());
-        carrier::send_string(channel.as_str(), msg)
-            .map_err(|e| From::from(e))
+        // a full events channel (see `events_overflow`) shouldn't fail
+        // whatever sent the event, so we log and drop it
+        match carrier::send(channel.as_str(), msg) {
+            Ok(_) => {}
+            Err(e) => warn!("messaging: event: problem sending {} to {}: {}", name, channel, e),
+        }
+        Ok(())
     }
 
     /// Blocking receive
     pub fn recv(&self) -> TResult<String> {
+        let bytes = self.recv_bytes()?;
+        String::from_utf8(bytes).map_err(|e| From::from(e))
+    }
+
+    /// Blocking receive, returning the raw message bytes
+    pub fn recv_bytes(&self) -> TResult<Vec<u8>> {
         let bytes = carrier::recv(&self.channel_in[..])?;
         debug!("messaging: recv: {} ({})", self.channel_in, bytes.len());
-        String::from_utf8(bytes).map_err(|e| From::from(e))
+        Ok(bytes)
     }
 
     #[allow(dead_code)]
@@ -138,10 +326,22 @@ impl Messenger {
             .map_err(|e| From::from(e))
     }
 
+    /// Send a (binary) message out
+    pub fn send_bytes(&self, msg: Vec<u8>) -> TResult<()> {
+        debug!("messaging: send: {} ({})", self.channel_out, msg.len());
+        carrier::send(self.channel_out.as_str(), msg)
+            .map_err(|e| From::from(e))
+    }
+
     /// Send a message on the out channel, but suffix the channel
     pub fn send_suffix(&self, suffix: String, msg: String) -> TResult<()> {
+        self.send_suffix_bytes(suffix, Vec::from(msg.as_bytes()))
+    }
+
+    /// Send a (binary) message on the out channel, but suffix the channel
+    pub fn send_suffix_bytes(&self, suffix: String, msg: Vec<u8>) -> TResult<()> {
         debug!("messaging: send_suffix: {}:{} ({})", self.channel_out, suffix, msg.len());
-        carrier::send_string(format!("{}:{}", &self.channel_out, suffix).as_str(), msg)
+        carrier::send(format!("{}:{}", &self.channel_out, suffix).as_str(), msg)
             .map_err(|e| From::from(e))
     }
 
@@ -181,17 +381,19 @@ impl<F: FnOnce(&mut Messenger) + Send + 'static> MsgThunk for F {
 /// messages, running the given callback for each one, until the thread gets the
 /// "ok, quit!" message.
 pub fn start<F>(process: F) -> TResult<()>
-    where F: Fn(String) + Send + Sync + 'static
+    where F: Fn(Vec<u8>) + Send + Sync + 'static
 {
     // create our messenger!
     let mut messenger = Messenger::new();
+    let events: String = config::get(&["messaging", "events"])?;
+    limit_events_channel(events.as_str())?;
     info!("messaging::start() -- main loop");
     ui_event("messaging:ready", &true)?;
     while messenger.is_bound() {
         // grab a message from our remote
-        match messenger.recv() {
+        match messenger.recv_bytes() {
             Ok(x) => {
-                if x == "turtl:internal:msg:shutdown" {
+                if &x[..] == b"turtl:internal:msg:shutdown" {
                     messenger.shutdown();
                     continue;
                 }
@@ -292,5 +494,28 @@ mod tests {
         assert_eq!(grab_locked_bool(&panic), false);
         handle.join().unwrap();
     }
+
+    #[test]
+    fn matches_filters() {
+        let filters = vec![String::from("sync:*"), String::from("user:login")];
+        assert!(filter_matches(&filters, "sync:update"));
+        assert!(filter_matches(&filters, "sync:outgoing:complete"));
+        assert!(filter_matches(&filters, "user:login"));
+        assert!(!filter_matches(&filters, "user:logout"));
+        assert!(!filter_matches(&filters, "profile:loaded"));
+        assert!(filter_matches(&vec![String::from("*")], "profile:loaded"));
+        assert!(!filter_matches(&Vec::new(), "profile:loaded"));
+    }
+
+    #[test]
+    fn tracks_subscribers() {
+        subscribe("test:subscriber", vec![String::from("test:subscribed:*")]).unwrap();
+        assert_eq!(subscribers_for("test:subscribed:event"), vec![String::from("test:subscriber")]);
+        assert!(subscribers_for("test:unsubscribed:event").is_empty());
+        assert!(subscribe("", vec![String::from("*")]).is_err());
+        assert!(unsubscribe("test:subscriber"));
+        assert!(!unsubscribe("test:subscriber"));
+        assert!(subscribers_for("test:subscribed:event").is_empty());
+    }
 }
 
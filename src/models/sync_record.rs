@@ -2,7 +2,7 @@ use ::jedi::{self, Value};
 use ::error::{TResult, TError};
 use ::models::model::Model;
 use ::models::protected::{Protected, Keyfinder};
-use ::storage::Storage;
+use ::storage::{Storage, Query, Filter, IndexVal};
 use ::turtl::Turtl;
 use ::sync::sync_model::SyncModel;
 
@@ -132,7 +132,7 @@ impl SyncRecord {
         let mut args = vec![];
         if let Some(x) = ty {
             let ty_string: String = jedi::parse(&jedi::stringify(&x)?)?;
-            args.push(ty_string+"|");
+            args.push(ty_string);
         }
         db.find("sync", "sync", &args)
     }
@@ -147,14 +147,16 @@ impl SyncRecord {
         }
     }
 
-    /// Given a DB, find all sync records matching `frozen` but NOT matching
-    /// `not_ty`.
+    /// Given a DB, find all sync records NOT matching `not_ty`.
     pub fn allbut(db: &mut Storage, not_ty: &Vec<SyncType>) -> TResult<Vec<SyncRecord>> {
-        let syncs = SyncRecord::find(db, None)?
-            .into_iter()
-            .filter(|x| !not_ty.contains(&x.ty))
-            .collect::<Vec<SyncRecord>>();
-        Ok(syncs)
+        let mut exclude = Vec::with_capacity(not_ty.len());
+        for ty in not_ty {
+            let ty_string: String = jedi::parse(&jedi::stringify(ty)?)?;
+            exclude.push(Filter::Leading(String::from("sync"), vec![IndexVal::from(ty_string)]));
+        }
+        let query = Query::new("sync")
+            .filter(Filter::Not(Box::new(Filter::Or(exclude))));
+        db.query(&query)
     }
 
     /// Static method for grabbing pending sync items. Mainly for the UI's
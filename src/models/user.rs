@@ -16,6 +16,7 @@ use ::sync::sync_model::{self, SyncModel, MemorySaver};
 use ::sync::incoming::SyncIncoming;
 use ::messaging;
 use ::migrate::MigrateResult;
+use ::dispatch::inflight;
 use ::std::path::PathBuf;
 use ::std::io::prelude::*;
 use ::std::fs;
@@ -215,6 +216,8 @@ impl User {
     pub fn login(turtl: &Turtl, username: String, password: String, version: u16) -> TResult<()> {
         let username = username.to_lowercase();
         let (key, auth) = generate_auth(&username, &password, version)?;
+        // key derivation is slow, so give the caller a chance to bail
+        inflight::checkpoint()?;
         do_login(turtl, &username, key, auth)
             .or_else(|e| {
                 turtl.api.clear_auth();
@@ -25,6 +25,12 @@ use ::lib_permissions::Permission;
 use ::config;
 use ::crypto;
 use ::messaging;
+use ::dispatch::inflight;
+use ::progress::Progress;
+use ::storage::{Query, Filter, IndexVal};
+
+/// How many notes `Profile::export()` decrypts at once
+const EXPORT_NOTES_PAGE_SIZE: i32 = 250;
 
 /// A structure holding a collection of objects that represent's a user's
 /// Turtl data profile.
@@ -95,6 +101,7 @@ impl Profile {
     /// Export the current Turtl profile
     pub fn export(turtl: &Turtl) -> TResult<Export> {
         info!("Profile::export() -- running export");
+        let mut progress = Progress::new("profile:export");
         let mut export = Export::default();
         export.schema_version = 2;
         let profile_guard = lockr!(turtl.profile);
@@ -122,13 +129,31 @@ impl Profile {
             })
             .collect::<Vec<_>>();
         export.boards = cloner(&profile_guard.boards)?;
-        let mut notes_encrypted = db.all(Note::tablename())?;
-        turtl.find_models_keys(&mut notes_encrypted)?;
-        export.notes = protected::map_deserialize(turtl, notes_encrypted)?;
+        let total = db.count(&Query::new(Note::tablename()))?;
+        progress.step("notes", Some(total as u64));
+        export.notes = Vec::with_capacity(total as usize);
+        // pull out and decrypt a page of notes at a time so we don't have
+        // every encrypted note in memory along with its decrypted copy
+        let mut offset = 0;
+        loop {
+            inflight::checkpoint()?;
+            let query = Query::new(Note::tablename()).limit(EXPORT_NOTES_PAGE_SIZE).offset(offset);
+            let mut notes: Vec<Note> = db.query(&query)?;
+            if notes.len() == 0 { break; }
+            offset += notes.len() as i32;
+            turtl.find_models_keys(&mut notes)?;
+            let notes = protected::map_deserialize(turtl, notes)?;
+            progress.inc(notes.len() as u64);
+            export.notes.extend(notes);
+        }
         export.files = Vec::with_capacity(export.notes.len());
+        progress.step("files", Some(export.notes.len() as u64));
         for note in &export.notes {
+            inflight::checkpoint()?;
+            progress.inc(1);
             match FileData::load_file(turtl, note) {
                 Ok(binary) => {
+                    progress.add_bytes(binary.len() as u64);
                     let mut filedata = FileData::default();
                     filedata.set_id(note.id_or_else()?);
                     filedata.data = Some(binary);
@@ -137,6 +162,7 @@ impl Profile {
                 Err(_) => {}    // we beleeze in nuzzing, lebowzki.
             }
         }
+        progress.finish();
         Ok(export)
     }
 
@@ -149,7 +175,15 @@ impl Profile {
     /// id. This can be done in one pass since the references are hierarchical,
     /// luckily. Also, we don't have to update key references because those are
     /// fully regenerated on each save >=]
+    ///
+    /// The import is all or nothing: if any part of it fails, whatever it wrote
+    /// to the db (including the outgoing syncs it queued) is rolled back.
     pub fn import(turtl: &Turtl, mode: ImportMode, export: Export) -> TResult<ImportResult> {
+        turtl.db_transaction(|| Profile::import_impl(turtl, mode, export))
+    }
+
+    /// Does the actual work of an import (see `import()`)
+    fn import_impl(turtl: &Turtl, mode: ImportMode, export: Export) -> TResult<ImportResult> {
         let client_id = {
             let key = format!("{}/{}", config::get::<String>(&["api", "endpoint"])?, turtl.user_id()?);
             crypto::to_hex(&crypto::sha256(key.as_bytes())?)?
@@ -177,15 +211,24 @@ impl Profile {
             // note that by destroying the spaces, we destroy the profile. this
             // includes keychains, boards, notes, etc (etc meaning "actually,
             // that's it" here).
+            let user_id = turtl.user_id()?;
+            // spaces index their user_id by its JSON type: usually an
+            // integer, but some ids are strings, so match either form
+            let mut user_id_filters = vec![Filter::Eq(String::from("user_id"), vec![IndexVal::from(user_id.clone())])];
+            match user_id.parse::<i64>() {
+                Ok(x) => user_id_filters.push(Filter::Eq(String::from("user_id"), vec![IndexVal::Int(x)])),
+                Err(_) => {}
+            }
             let spaces: Vec<Space> = {
                 let mut db_guard = lock!(turtl.db);
                 let db = match db_guard.as_mut() {
                     Some(x) => x,
                     None => return TErr!(TError::MissingField(String::from("turtl.db"))),
                 };
-                db.all::<Space>(Space::tablename())?
+                let query = Query::new(Space::tablename())
+                    .filter(Filter::Or(user_id_filters));
+                db.query::<Space>(&query)?
             };
-            let user_id = turtl.user_id()?;
             for space in spaces {
                 // it would be a bad (read: terrible) idea to remove a space
                 // that doesn't belong to us. the API won't let us, and it will
@@ -211,7 +254,7 @@ impl Profile {
         // ok, now that we got rid of that dead weight, let's start our import.
         let Export { spaces, boards, notes, files, .. } = export;
 
-        struct Counter { count: u32 }
+        struct Counter { count: u32, progress: Progress }
         
         // define a function that runs our sync dispatcher for the incoming
         // import models. note that this runs all of our permission checks for
@@ -247,7 +290,10 @@ impl Profile {
                 if exists {
                     // if the model already exists and we're only loading
                     // missing items, skip importing this model
-                    if mode == &ImportMode::Restore { continue; }
+                    if mode == &ImportMode::Restore {
+                        counter.progress.inc(1);
+                        continue;
+                    }
                     sync_record.action = SyncAction::Edit;
                 } else {
                     sync_record.action = SyncAction::Add;
@@ -257,6 +303,7 @@ impl Profile {
                 sync_model::dispatch(turtl, sync_record)?;
                 // tally ho, good chap
                 counter.count += 1;
+                counter.progress.inc(1);
                 messaging::ui_event("profile:import:tally", &counter.count)?;
             }
             Ok(())
@@ -286,7 +333,9 @@ impl Profile {
             Ok(())
         }
 
-        let mut counter = Counter { count: 0 };
+        let total = (spaces.len() + boards.len() + notes.len()) as u64;
+        let mut counter = Counter { count: 0, progress: Progress::new("profile:import") };
+        counter.progress.step("import", Some(total));
         saver(turtl, &mode, &client_id, spaces, SyncType::Space, |x, _map, _old_id| { x.data() }, &mut id_change_map, &mut result, &mut counter)?;
         saver(turtl, &mode, &client_id, boards, SyncType::Board, |x, id_change_map, _old_id| {
             let mut data = x.data()?;
@@ -305,6 +354,7 @@ impl Profile {
             }
             Ok(data)
         }, &mut id_change_map, &mut result, &mut counter)?;
+        counter.progress.finish();
         Ok(result)
     }
 }
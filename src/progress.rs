@@ -0,0 +1,229 @@
+//! Lets long-running operations (exports, imports, migrations, uploads, etc)
+//! tell the UI how far along they are.
+//!
+//! Progress is sent out as `progress` UI events that look like:
+//!
+//!     {
+//!         "op": "<operation id>",
+//!         "name": "profile:export",
+//!         "step": "files",
+//!         "done": 3,
+//!         "total": 10,
+//!         "bytes": 4096,
+//!         "total_bytes": null,
+//!         "finished": false,
+//!         "error": false
+//!     }
+//!
+//! If the operation is running as part of a dispatch request, the operation id
+//! is the request's message id, so the UI can match progress to the call it
+//! made. Otherwise it's generated.
+//!
+//! If a `Progress` is dropped without `finish()` being called (the operation
+//! errored out or panicked) it sends a last event with `finished` and `error`
+//! both set, so the UI isn't left waiting on an operation that's gone.
+//!
+//! Events are throttled so an operation updating its count a few thousand
+//! times a second doesn't flood the UI. Starting a new step or finishing the
+//! operation always sends an event.
+
+use ::std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use ::std::time::{Duration, Instant};
+
+use ::jedi::Value;
+
+use ::messaging;
+use ::dispatch::inflight;
+
+/// Used to generate operation ids for operations that aren't tied to a request
+static OP_COUNTER: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// How often (at most) we send progress events for an operation
+const THROTTLE_MS: u64 = 250;
+
+/// Tracks the progress of an operation
+pub struct Progress {
+    /// This operation's id
+    id: String,
+    /// The kind of operation this is ("profile:export")
+    name: String,
+    /// Which step of the operation we're on ("files")
+    step: String,
+    /// How many items are done in the current step
+    done: u64,
+    /// How many items are in the current step (if known)
+    total: Option<u64>,
+    /// How many bytes have been processed in the current step
+    bytes: u64,
+    /// How many bytes are in the current step (if known)
+    total_bytes: Option<u64>,
+    /// When we last sent an event
+    last_sent: Option<Instant>,
+    /// Whether we've sent our final event
+    finished: bool,
+}
+
+impl Progress {
+    /// Start tracking an operation. Uses the current request's message id as
+    /// the operation id if there is one.
+    pub fn new(name: &str) -> Progress {
+        let id = match inflight::current_mid() {
+            Some(x) => x,
+            None => format!("{}:{}", name, OP_COUNTER.fetch_add(1, Ordering::SeqCst)),
+        };
+        Progress::with_id(id, name)
+    }
+
+    /// Start tracking an operation with a specific id
+    pub fn with_id(id: String, name: &str) -> Progress {
+        Progress {
+            id: id,
+            name: String::from(name),
+            step: String::from(""),
+            done: 0,
+            total: None,
+            bytes: 0,
+            total_bytes: None,
+            last_sent: None,
+            finished: false,
+        }
+    }
+
+    /// Move on to the next step, resetting our counts
+    pub fn step(&mut self, step: &str, total: Option<u64>) {
+        self.step = String::from(step);
+        self.done = 0;
+        self.total = total;
+        self.bytes = 0;
+        self.total_bytes = None;
+        self.send(false, true);
+    }
+
+    /// Set how many bytes we're expecting in this step
+    pub fn set_total_bytes(&mut self, total_bytes: u64) {
+        self.total_bytes = Some(total_bytes);
+    }
+
+    /// Mark `num` items as done
+    pub fn inc(&mut self, num: u64) {
+        self.done += num;
+        self.send(false, false);
+    }
+
+    /// Mark `num` bytes as processed
+    pub fn add_bytes(&mut self, num: u64) {
+        self.bytes += num;
+        self.send(false, false);
+    }
+
+    /// All done!
+    pub fn finish(mut self) {
+        self.finished = true;
+        self.send(false, true);
+    }
+
+    /// Get this progress' current state as a Value
+    fn data(&self, error: bool) -> Value {
+        json!({
+            "op": self.id,
+            "name": self.name,
+            "step": self.step,
+            "done": self.done,
+            "total": self.total,
+            "bytes": self.bytes,
+            "total_bytes": self.total_bytes,
+            "finished": self.finished,
+            "error": error,
+        })
+    }
+
+    /// Whether enough time has passed since our last event to send another
+    fn throttled(&self) -> bool {
+        match self.last_sent {
+            Some(x) => x.elapsed() < Duration::from_millis(THROTTLE_MS),
+            None => false,
+        }
+    }
+
+    /// Send a progress event out to the UI. Progress is nice to have, so any
+    /// errors are logged and otherwise ignored.
+    fn send(&mut self, error: bool, force: bool) {
+        if !force && self.throttled() { return; }
+        self.last_sent = Some(Instant::now());
+        match messaging::ui_event("progress", &self.data(error)) {
+            Ok(_) => {}
+            Err(e) => warn!("Progress.send() -- problem sending progress for {} ({}): {}", self.name, self.id, e),
+        }
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if self.finished { return; }
+        self.finished = true;
+        self.send(true, true);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ::carrier;
+    use ::wire;
+
+    #[test]
+    fn tracks_progress() {
+        let mut progress = Progress::with_id(String::from("op-1"), "profile:export");
+        assert!(!progress.throttled());
+        progress.step("files", Some(3));
+        assert!(progress.throttled());
+        progress.inc(1);
+        progress.add_bytes(4096);
+        progress.inc(1);
+        assert_eq!(progress.data(false), json!({
+            "op": "op-1",
+            "name": "profile:export",
+            "step": "files",
+            "done": 2,
+            "total": 3,
+            "bytes": 4096,
+            "total_bytes": null,
+            "finished": false,
+            "error": false,
+        }));
+        progress.step("notes", None);
+        assert_eq!(progress.done, 0);
+        assert_eq!(progress.bytes, 0);
+        assert_eq!(progress.total, None);
+        progress.finish();
+    }
+
+    #[test]
+    fn fails_unfinished_progress() {
+        messaging::subscribe("test:progress", vec![String::from("progress")]).unwrap();
+        let channel = messaging::subscriber_channel("test:progress").unwrap();
+        {
+            let mut progress = Progress::with_id(String::from("op-2"), "profile:import");
+            progress.step("notes", Some(10));
+        }
+        // other tests send progress too, so skip anything that isn't ours
+        let mut last = Value::Null;
+        while last["d"]["finished"] != json!(true) {
+            let bytes = carrier::recv_timeout(channel.as_str(), Duration::from_secs(5)).unwrap();
+            let (event, _) = wire::decode(&bytes).unwrap();
+            if event["d"]["op"] == json!("op-2") { last = event; }
+        }
+        messaging::unsubscribe("test:progress");
+        assert_eq!(last["d"]["step"], json!("notes"));
+        assert_eq!(last["d"]["error"], json!(true));
+    }
+
+    #[test]
+    fn generates_ids() {
+        let progress1 = Progress::new("user:join-migrate");
+        let progress2 = Progress::new("user:join-migrate");
+        assert!(progress1.id.starts_with("user:join-migrate:"));
+        assert!(progress1.id != progress2.id);
+    }
+}
@@ -1,4 +1,10 @@
 use ::jedi::Value;
+use ::dumpy::{Dumpy, Migration};
+
+/// The version of our schema. Bump this whenever the schema changes: tables
+/// whose indexes changed get reindexed automatically, and if the data itself
+/// needs changing, add a `Migration` for the new version to `get_migrations()`.
+pub const SCHEMA_VERSION: u32 = 1;
 
 /// Get the app schema.
 pub fn get_schema() -> Value {
@@ -44,3 +50,19 @@ pub fn get_schema() -> Value {
         "user": {}
     })
 }
+
+/// Get the migrations that bring an older user db up to `SCHEMA_VERSION`.
+pub fn get_migrations() -> Vec<Migration> {
+    vec![
+        // the first versioned schema. dbs from before we tracked versions have
+        // no record of which indexes they were built with, so they get fully
+        // reindexed, which backfills any indexes added after their objects
+        // were stored.
+        Migration::new(1),
+    ]
+}
+
+/// Get the (versioned) dumpy for the user db
+pub fn get_dumpy() -> Dumpy {
+    Dumpy::versioned(get_schema(), SCHEMA_VERSION, get_migrations())
+}
@@ -1,20 +1,29 @@
 //! The storage module stores things. Don't worry, those things are encrypted.
 //! Probably.
 
-use ::std::sync::{Arc, RwLock};
+use ::std::sync::{Arc, RwLock, Mutex, MutexGuard, Condvar, LockResult, PoisonError};
 use ::std::mem;
+use ::std::panic::{self, AssertUnwindSafe};
+use ::std::thread::{self, ThreadId};
+use ::std::fs;
+use ::std::path::Path;
 
-use ::crypto;
+use ::crypto::{self, Key};
 use ::rusqlite::{self, Connection};
 use ::jedi::{self, Value};
 use ::dumpy::Dumpy;
+pub use ::dumpy::{Query, Filter, Order, IndexVal};
 use ::config;
 
 use ::models::model::{self};
 use ::models::protected::Protected;
 use ::models::storable::Storable;
 
-use ::error::TResult;
+use ::error::{TResult, TError};
+
+/// Mixed with the user's master key to get the key for their database. Bump
+/// the version if the derivation ever changes.
+const DB_KEY_CONTEXT: &'static [u8] = b"turtl:storage:v1";
 
 /// Given a db filename, return the foll path we'll use for the db file
 pub fn db_location(db_name: &String) -> TResult<String> {
@@ -46,6 +55,77 @@ pub fn setup_client_id(storage: Arc<RwLock<Storage>>) -> TResult<()> {
     model::set_client_id(id)
 }
 
+/// Whether the per-user database should be encrypted (see `storage.encrypt` in
+/// the config)
+pub fn encryption_enabled() -> bool {
+    config::get::<bool>(&["storage", "encrypt"]).unwrap_or(false)
+}
+
+/// Derive the key we encrypt a database with from the user's master key,
+/// returned as hex (which is what SQLCipher wants for a raw key)
+pub fn db_key(master_key: &Key) -> TResult<String> {
+    let keydata = master_key.data();
+    if keydata.len() < crypto::HMAC_KEYLEN {
+        return TErr!(TError::BadValue(String::from("master key is too short to derive a db key from")));
+    }
+    let key = crypto::hmac(&keydata[0..crypto::HMAC_KEYLEN], DB_KEY_CONTEXT)?;
+    Ok(crypto::to_hex(&key)?)
+}
+
+/// Make sure the sqlite we're linked against can actually encrypt. Plain
+/// sqlite silently ignores `PRAGMA key`, which would leave us writing
+/// cleartext while thinking it's encrypted.
+fn assert_cipher(conn: &Connection) -> TResult<()> {
+    let mut stmt = conn.prepare("PRAGMA cipher_version")?;
+    let mut rows = stmt.query(&[])?;
+    let has_cipher = rows.next().is_some();
+    match has_cipher {
+        true => Ok(()),
+        false => TErr!(TError::BadValue(String::from("`storage.encrypt` is on, but core wasn't built with the `sqlcipher` feature"))),
+    }
+}
+
+/// Unlock a connection with the given (hex) key
+fn apply_key(conn: &Connection, key_hex: &str) -> TResult<()> {
+    conn.execute_batch(&format!("PRAGMA key = \"x'{}'\";", key_hex))?;
+    // sqlcipher doesn't check the key until we actually read something
+    conn.query_row("SELECT count(*) FROM sqlite_master", &[], |_| ())
+        .map_err(|e| twrap!(TError::Msg(format!("couldn't unlock database: {}", e))))?;
+    Ok(())
+}
+
+/// Check if the db at `location` is a readable, unencrypted sqlite file
+fn is_plaintext(location: &String) -> TResult<bool> {
+    let conn = Connection::open(location)?;
+    let res = conn.query_row("SELECT count(*) FROM sqlite_master", &[], |_| ()).is_ok();
+    conn.close()?;
+    Ok(res)
+}
+
+/// Copy an unencrypted db into a new encrypted one, then swap the encrypted
+/// one into its place.
+fn encrypt_existing(location: &String, key_hex: &str) -> TResult<()> {
+    let tmp_location = format!("{}.encrypting", location);
+    if Path::new(&tmp_location).exists() {
+        // left over from an encryption that didn't finish
+        fs::remove_file(&tmp_location)?;
+    }
+    {
+        let conn = Connection::open(location)?;
+        assert_cipher(&conn)?;
+        let attach = format!(
+            "ATTACH DATABASE '{}' AS encrypted KEY \"x'{}'\"; SELECT sqlcipher_export('encrypted'); DETACH DATABASE encrypted;",
+            tmp_location.replace("'", "''"),
+            key_hex,
+        );
+        conn.execute_batch(&attach)?;
+        conn.close()?;
+    }
+    fs::rename(&tmp_location, location)?;
+    info!("storage::encrypt_existing() -- encrypted {}", location);
+    Ok(())
+}
+
 /// This structure holds state for persisting (encrypted) data to disk.
 pub struct Storage {
     pub conn: Connection,
@@ -55,6 +135,38 @@ pub struct Storage {
 impl Storage {
     /// Make a Storage lol
     pub fn new(location: &String, schema: Value) -> TResult<Storage> {
+        Storage::with_dumpy(location, Dumpy::new(schema), None)
+    }
+
+    /// Make a Storage where the entire database (not just the model data in
+    /// it) is encrypted with a key derived from `master_key`. If `location`
+    /// holds an unencrypted database, it's encrypted first.
+    ///
+    /// In-memory databases never touch the disk, so they're left alone.
+    pub fn new_encrypted(location: &String, schema: Value, master_key: &Key) -> TResult<Storage> {
+        Storage::with_dumpy(location, Dumpy::new(schema), Some(master_key))
+    }
+
+    /// Make a Storage using an existing (possibly versioned) dumpy, encrypting
+    /// the database if given a master key (see `new_encrypted()`). The store
+    /// is migrated to the dumpy's schema version when opened.
+    pub fn with_dumpy(location: &String, dumpy: Dumpy, master_key: Option<&Key>) -> TResult<Storage> {
+        let master_key = match master_key {
+            Some(x) => x,
+            None => return Storage::open(location, dumpy, None),
+        };
+        if location == ":memory:" {
+            return Storage::open(location, dumpy, None);
+        }
+        let key_hex = db_key(master_key)?;
+        if Path::new(location).exists() && is_plaintext(location)? {
+            info!("Storage::with_dumpy() -- found unencrypted db at {}, encrypting", location);
+            encrypt_existing(location, &key_hex)?;
+        }
+        Storage::open(location, dumpy, Some(&key_hex))
+    }
+
+    fn open(location: &String, dumpy: Dumpy, key_hex: Option<&String>) -> TResult<Storage> {
         // open in multi-threaded mode: we can have the same db open in multiple
         // threads as long as each thread has its own connection:
         //   https://www.sqlite.org/threadsafe.html
@@ -68,9 +180,12 @@ impl Storage {
         } else {
             Connection::open_with_flags(location, flags)
         }?;
+        if let Some(key_hex) = key_hex {
+            assert_cipher(&conn)?;
+            apply_key(&conn, key_hex)?;
+        }
 
-        // set up dumpy
-        let dumpy = Dumpy::new(schema);
+        // set up dumpy (and migrate the db if we need to)
         dumpy.init(&conn)?;
 
         Ok(Storage {
@@ -139,6 +254,20 @@ impl Storage {
         Ok(jedi::from_val(Value::Array(self.dumpy.find(&self.conn, &String::from(table), &String::from(index), vals)?))?)
     }
 
+    /// Grab the values matching a query, ie
+    ///
+    ///     db.query::<Note>(&Query::new("notes").filter(Filter::Eq(String::from("board_id"), vec![IndexVal::from(board_id)])).limit(10))
+    pub fn query<T>(&self, query: &Query) -> TResult<Vec<T>>
+        where T: Protected + Storable
+    {
+        Ok(jedi::from_val(Value::Array(self.dumpy.query(&self.conn, query)?))?)
+    }
+
+    /// Count the values matching a query (ignoring its limit/offset)
+    pub fn count(&self, query: &Query) -> TResult<i64> {
+        Ok(self.dumpy.count(&self.conn, query)?)
+    }
+
     /// Get ALL objects in a table with the given IDs
     pub fn by_id<T>(&self, table: &str, ids: &Vec<String>) -> TResult<Vec<T>>
         where T: Protected + Storable
@@ -160,6 +289,74 @@ impl Storage {
         Ok(self.dumpy.kv_delete(&self.conn, key)?)
     }
 
+    /// Run `run` in a transaction. If it returns an error (or panics), every
+    /// change it made is rolled back, otherwise they're all committed.
+    /// Transactions nest: one started inside another only rolls back its own
+    /// changes.
+    ///
+    ///     db.transaction(|tx| {
+    ///         tx.save(&note)?;
+    ///         tx.kv_set("sync_id", &sync_id)
+    ///     })?;
+    pub fn transaction<F, T>(&mut self, run: F) -> TResult<T>
+        where F: FnOnce(&mut Storage) -> TResult<T>
+    {
+        self.savepoint()?;
+        let res = panic::catch_unwind(AssertUnwindSafe(|| run(self)));
+        self.finish_savepoint(res)
+    }
+
+    /// Open a savepoint. Outside of a transaction this starts one, inside of
+    /// one it nests, so we never have to track how deep we are. Prefer
+    /// `transaction()` unless the work can't hold onto the Storage.
+    pub fn savepoint(&self) -> TResult<()> {
+        self.conn.execute_batch("SAVEPOINT storage_tx")?;
+        Ok(())
+    }
+
+    /// Commit the innermost savepoint
+    pub fn release(&self) -> TResult<()> {
+        self.conn.execute_batch("RELEASE storage_tx")?;
+        Ok(())
+    }
+
+    /// Undo everything done since the innermost savepoint, and close it out
+    pub fn rollback(&self) -> TResult<()> {
+        self.conn.execute_batch("ROLLBACK TO storage_tx; RELEASE storage_tx")?;
+        Ok(())
+    }
+
+    /// Close out the innermost savepoint given the result of the work done
+    /// under it: commit on success, roll back on error. If the work panicked,
+    /// we roll back and then keep the panic going.
+    pub fn finish_savepoint<T>(&self, res: thread::Result<TResult<T>>) -> TResult<T> {
+        let rollback = || {
+            match self.rollback() {
+                Err(e) => error!("Storage.finish_savepoint() -- problem rolling back: {}", e),
+                _ => {}
+            }
+        };
+        match res {
+            Ok(Ok(val)) => {
+                match self.release() {
+                    Ok(_) => Ok(val),
+                    Err(e) => {
+                        rollback();
+                        Err(e)
+                    }
+                }
+            }
+            Ok(Err(e)) => {
+                rollback();
+                Err(e)
+            }
+            Err(panic) => {
+                rollback();
+                panic::resume_unwind(panic)
+            }
+        }
+    }
+
     /// Close the db connection
     pub fn close(&mut self) -> TResult<()> {
         let mut conn = Connection::open_in_memory()?;
@@ -173,14 +370,138 @@ impl Storage {
 // it around between threads willy-nilly.
 unsafe impl Sync for Storage {}
 
+/// Guards the user db. Works like a `Mutex<Option<Storage>>` (so `lock!()`
+/// works on it like usual) except that a thread can also *claim* the db for a
+/// while (see `claim()`). While the db is claimed, only the claiming thread can
+/// lock it, and everyone else waits their turn.
+///
+/// This lets a transaction span a bunch of separate locks (ie, a batch or an
+/// import) without writes from other commands or the sync threads landing in
+/// the middle of it (and getting rolled back along with it).
+pub struct StorageLock {
+    db: Mutex<Option<Storage>>,
+    /// The thread that has the db claimed, and how many claims it holds
+    owner: Mutex<Option<(ThreadId, usize)>>,
+    /// Signaled whenever a claim is given up
+    released: Condvar,
+}
+
+impl StorageLock {
+    pub fn new(db: Option<Storage>) -> StorageLock {
+        StorageLock {
+            db: Mutex::new(db),
+            owner: Mutex::new(None),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Lock the db, waiting for any other thread's claim on it to be given up
+    pub fn lock(&self) -> LockResult<MutexGuard<Option<Storage>>> {
+        loop {
+            self.wait_for_claims();
+            let (guard, poisoned) = match self.db.lock() {
+                Ok(x) => (x, false),
+                Err(e) => (e.into_inner(), true),
+            };
+            // someone may have claimed the db while we waited on the lock
+            if self.claimed_by_other() { continue; }
+            return if poisoned { Err(PoisonError::new(guard)) } else { Ok(guard) };
+        }
+    }
+
+    /// Claim the db for the current thread until the returned claim is
+    /// dropped. Claims nest, so it's fine to claim the db more than once.
+    ///
+    /// Don't wait on other threads that use the db (ie, joining the sync
+    /// threads) while holding a claim, they'll never get their turn.
+    pub fn claim(&self) -> StorageClaim {
+        let me = thread::current().id();
+        let mut owner = self.owner_guard();
+        loop {
+            let current = *owner;
+            match current {
+                None => {
+                    *owner = Some((me, 1));
+                    break;
+                }
+                Some((id, count)) if id == me => {
+                    *owner = Some((me, count + 1));
+                    break;
+                }
+                _ => {}
+            }
+            owner = match self.released.wait(owner) {
+                Ok(x) => x,
+                Err(e) => e.into_inner(),
+            };
+        }
+        StorageClaim { lock: self }
+    }
+
+    /// Grab our owner lock. A panic while holding it can't leave it in a bad
+    /// state, so we ignore poisoning.
+    fn owner_guard(&self) -> MutexGuard<Option<(ThreadId, usize)>> {
+        match self.owner.lock() {
+            Ok(x) => x,
+            Err(e) => e.into_inner(),
+        }
+    }
+
+    /// Whether a thread other than this one has the db claimed
+    fn claimed_by_other(&self) -> bool {
+        let me = thread::current().id();
+        match *self.owner_guard() {
+            Some((id, _)) => id != me,
+            None => false,
+        }
+    }
+
+    /// Wait until no other thread has the db claimed
+    fn wait_for_claims(&self) {
+        let me = thread::current().id();
+        let mut owner = self.owner_guard();
+        loop {
+            match *owner {
+                Some((id, _)) if id != me => {}
+                _ => return,
+            }
+            owner = match self.released.wait(owner) {
+                Ok(x) => x,
+                Err(e) => e.into_inner(),
+            };
+        }
+    }
+}
+
+/// A thread's claim on the db (see `StorageLock::claim()`), given up when
+/// dropped
+pub struct StorageClaim<'a> {
+    lock: &'a StorageLock,
+}
+
+impl<'a> Drop for StorageClaim<'a> {
+    fn drop(&mut self) {
+        let mut owner = self.lock.owner_guard();
+        let current = *owner;
+        *owner = match current {
+            Some((id, count)) if count > 1 => Some((id, count - 1)),
+            _ => None,
+        };
+        self.lock.released.notify_all();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use ::std::sync::mpsc;
+    use ::std::time::Duration;
+
     use ::jedi::{self, Value};
     use ::rusqlite::types::Value as SqlValue;
 
-    use ::error::TResult;
+    use ::error::{TResult, TError};
     use ::models::model::{self, Model};
     use ::models::protected::Protected;
 
@@ -261,6 +582,31 @@ mod tests {
         assert!(sheeb.is_none());
     }
 
+    #[test]
+    fn queries_models() {
+        model::set_client_id(String::from("c0f4c762af6c42e4079cced2dfe16b4d010b190ad75ade9d83ff8cee0e96586d")).unwrap();
+        let schema = jedi::parse(&String::from(r#"{"shibas":{"indexes":[{"fields":["color"]}]}}"#)).unwrap();
+        let storage = Storage::new(&String::from(":memory:"), schema).unwrap();
+        for &(name, color) in &[("Kofi", "sesame"), ("Sasha", "red"), ("Lucy", "sesame")] {
+            let mut model = Shiba::new_with_id().unwrap();
+            model.generate_key().unwrap();
+            model.color = Some(String::from(color));
+            model.name = Some(String::from(name));
+            model.serialize().unwrap();
+            storage.save(&model).unwrap();
+        }
+        let query = Query::new("shibas")
+            .filter(Filter::Eq(String::from("color"), vec![IndexVal::from("sesame")]))
+            .limit(1);
+        let sheebs: Vec<Shiba> = storage.query(&query).unwrap();
+        assert_eq!(sheebs.len(), 1);
+        assert_eq!(sheebs[0].color, Some(String::from("sesame")));
+        assert_eq!(storage.count(&query).unwrap(), 2);
+        let query = Query::new("shibas").order_by("color", Order::Asc);
+        let sheebs: Vec<Shiba> = storage.query(&query).unwrap();
+        assert_eq!(sheebs[0].color, Some(String::from("red")));
+    }
+
     #[test]
     fn kv_stuff() {
         // ^kv stuff? were the midterms hard?
@@ -271,5 +617,154 @@ mod tests {
         storage.kv_delete("get a job").unwrap();
         assert_eq!(storage.kv_get("get a job").unwrap(), None);
     }
+
+    #[test]
+    fn runs_transactions() {
+        let mut storage = pretest();
+        let res = storage.transaction(|tx| {
+            tx.kv_set("name", &String::from("kofi"))?;
+            tx.kv_get("name")
+        }).unwrap();
+        assert_eq!(res, Some(String::from("kofi")));
+        assert_eq!(storage.kv_get("name").unwrap(), Some(String::from("kofi")));
+
+        // errors roll back
+        let res: TResult<()> = storage.transaction(|tx| {
+            tx.kv_set("name", &String::from("sasha"))?;
+            TErr!(TError::BadValue(String::from("nope")))
+        });
+        assert!(res.is_err());
+        assert_eq!(storage.kv_get("name").unwrap(), Some(String::from("kofi")));
+
+        // nested transactions only roll back their own changes
+        storage.transaction(|tx| {
+            tx.kv_set("name", &String::from("lucy"))?;
+            let res: TResult<()> = tx.transaction(|tx2| {
+                tx2.kv_set("friend", &String::from("sasha"))?;
+                TErr!(TError::BadValue(String::from("nope")))
+            });
+            assert!(res.is_err());
+            tx.transaction(|tx2| tx2.kv_set("toy", &String::from("squeaky")))
+        }).unwrap();
+        assert_eq!(storage.kv_get("name").unwrap(), Some(String::from("lucy")));
+        assert_eq!(storage.kv_get("friend").unwrap(), None);
+        assert_eq!(storage.kv_get("toy").unwrap(), Some(String::from("squeaky")));
+
+        // so do panics
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _: TResult<()> = storage.transaction(|tx| {
+                tx.kv_set("name", &String::from("sasha"))?;
+                panic!("lol");
+            });
+        }));
+        assert!(res.is_err());
+        assert_eq!(storage.kv_get("name").unwrap(), Some(String::from("lucy")));
+        // and we're not stuck in a transaction afterwards (sqlite won't BEGIN
+        // inside of an open transaction)
+        storage.conn.execute_batch("BEGIN TRANSACTION; COMMIT TRANSACTION;").unwrap();
+    }
+
+    #[test]
+    fn claims_the_db() {
+        let lock = Arc::new(StorageLock::new(Some(pretest())));
+        let claim = lock.claim();
+        // claims nest
+        drop(lock.claim());
+        lock!(lock).as_ref().unwrap().kv_set("name", &String::from("kofi")).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let lock2 = lock.clone();
+        let handle = thread::spawn(move || {
+            let name = lock!(lock2).as_ref().unwrap().kv_get("name").unwrap();
+            tx.send(name).unwrap();
+        });
+        // the other thread can't get at the db until we give up our claim
+        assert!(rx.recv_timeout(Duration::from_millis(100)).is_err());
+        drop(claim);
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), Some(String::from("kofi")));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn derives_db_keys() {
+        let master = Key::new(crypto::from_hex(&String::from("a2e3d0c1e1c2f1a9b8d7e6f5a4b3c2d1e0f9a8b7c6d5e4f3a2b1c0d9e8f7a6b5")).unwrap());
+        let key1 = db_key(&master).unwrap();
+        let key2 = db_key(&master).unwrap();
+        assert_eq!(key1, key2);
+        assert_eq!(key1.len(), 64);
+        assert!(db_key(&Key::random().unwrap()).unwrap() != key1);
+        assert!(db_key(&Key::new(vec![1, 2, 3])).is_err());
+    }
+
+    #[test]
+    fn opens_encrypted_dbs() {
+        model::set_client_id(String::from("c0f4c762af6c42e4079cced2dfe16b4d010b190ad75ade9d83ff8cee0e96586d")).unwrap();
+        let schema = jedi::parse(&String::from(r#"{"notes":{"indexes":[{"fields":["user_id"]}]}}"#)).unwrap();
+        // in-memory dbs never hit the disk, so they skip encryption entirely
+        let master = Key::random().unwrap();
+        let storage = Storage::new_encrypted(&String::from(":memory:"), schema, &master).unwrap();
+        storage.kv_set("name", &String::from("kofi")).unwrap();
+        assert_eq!(storage.kv_get("name").unwrap(), Some(String::from("kofi")));
+    }
+
+    #[cfg(not(feature = "sqlcipher"))]
+    #[test]
+    fn refuses_encryption_without_sqlcipher() {
+        let location = format!("{}/turtl-storage-nocipher.sqlite", ::std::env::temp_dir().to_string_lossy());
+        let _ = fs::remove_file(&location);
+        let schema = jedi::parse(&String::from(r#"{}"#)).unwrap();
+        let master = Key::random().unwrap();
+        assert!(Storage::new_encrypted(&location, schema, &master).is_err());
+        let _ = fs::remove_file(&location);
+    }
+
+    #[cfg(feature = "sqlcipher")]
+    #[test]
+    fn encrypts_existing_dbs() {
+        use ::std::io::Read;
+
+        model::set_client_id(String::from("c0f4c762af6c42e4079cced2dfe16b4d010b190ad75ade9d83ff8cee0e96586d")).unwrap();
+        let location = format!("{}/turtl-storage-cipher.sqlite", ::std::env::temp_dir().to_string_lossy());
+        let _ = fs::remove_file(&location);
+        let schema = jedi::parse(&String::from(r#"{"notes":{"indexes":[{"fields":["user_id"]}]}}"#)).unwrap();
+        let header = |location: &String| -> Vec<u8> {
+            let mut buf = vec![0; 15];
+            fs::File::open(location).unwrap().read_exact(&mut buf).unwrap();
+            buf
+        };
+
+        {
+            let mut storage = Storage::new(&location, schema.clone()).unwrap();
+            storage.kv_set("name", &String::from("kofi")).unwrap();
+            storage.close().unwrap();
+        }
+        assert_eq!(header(&location), b"SQLite format 3".to_vec());
+
+        let master = Key::random().unwrap();
+        {
+            let mut storage = Storage::new_encrypted(&location, schema.clone(), &master).unwrap();
+            assert_eq!(storage.kv_get("name").unwrap(), Some(String::from("kofi")));
+            storage.close().unwrap();
+        }
+        assert!(header(&location) != b"SQLite format 3".to_vec());
+        assert!(!Path::new(&format!("{}.encrypting", location)).exists());
+
+        // reopening with the same key works, the wrong key (or no key) doesn't
+        {
+            let mut storage = Storage::new_encrypted(&location, schema.clone(), &master).unwrap();
+            assert_eq!(storage.kv_get("name").unwrap(), Some(String::from("kofi")));
+            storage.close().unwrap();
+        }
+        assert!(Storage::new_encrypted(&location, schema.clone(), &Key::random().unwrap()).is_err());
+        assert!(Storage::new(&location, schema.clone()).is_err());
+        let _ = fs::remove_file(&location);
+    }
+
+    #[test]
+    fn opens_versioned_dbs() {
+        model::set_client_id(String::from("c0f4c762af6c42e4079cced2dfe16b4d010b190ad75ade9d83ff8cee0e96586d")).unwrap();
+        let storage = Storage::with_dumpy(&String::from(":memory:"), ::schema::get_dumpy(), None).unwrap();
+        assert_eq!(storage.kv_get("dumpy:schema_version").unwrap(), Some(format!("{}", ::schema::SCHEMA_VERSION)));
+    }
 }
 
@@ -1,7 +1,7 @@
-use ::std::sync::{Arc, RwLock, Mutex};
+use ::std::sync::{Arc, RwLock};
 use ::sync::{SyncConfig, Syncer};
 use ::sync::sync_model::SyncModel;
-use ::storage::Storage;
+use ::storage::StorageLock;
 use ::api::{Api, ApiReq, Method, Headers};
 use ::messaging;
 use ::error::{TResult, TError};
@@ -26,7 +26,7 @@ pub struct FileSyncIncoming {
 
     /// Holds our user-specific db. This is mainly for persisting k/v data and
     /// for polling for file records that need downloading.
-    db: Arc<Mutex<Option<Storage>>>,
+    db: Arc<StorageLock>,
 
     /// Stores our syn run version
     run_version: i64,
@@ -34,7 +34,7 @@ pub struct FileSyncIncoming {
 
 impl FileSyncIncoming {
     /// Create a new incoming syncer
-    pub fn new(config: Arc<RwLock<SyncConfig>>, api: Arc<Api>, db: Arc<Mutex<Option<Storage>>>) -> Self {
+    pub fn new(config: Arc<RwLock<SyncConfig>>, api: Arc<Api>, db: Arc<StorageLock>) -> Self {
         FileSyncIncoming {
             config: config,
             api: api,
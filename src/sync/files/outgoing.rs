@@ -1,10 +1,11 @@
-use ::std::sync::{Arc, RwLock, Mutex};
+use ::std::sync::{Arc, RwLock};
 use ::sync::{SyncConfig, Syncer};
 use ::sync::sync_model::SyncModel;
 use ::sync::incoming::SyncIncoming;
-use ::storage::Storage;
+use ::storage::StorageLock;
 use ::api::{self, Api, ApiReq};
 use ::messaging;
+use ::progress::Progress;
 use ::error::{TResult, TError};
 use ::models::file::FileData;
 use ::models::sync_record::{SyncType, SyncRecord};
@@ -22,7 +23,7 @@ pub struct FileSyncOutgoing {
 
     /// Holds our user-specific db. This is mainly for persisting k/v data and
     /// for polling for file records that need uploading.
-    db: Arc<Mutex<Option<Storage>>>,
+    db: Arc<StorageLock>,
 
     /// Stores our syn run version
     run_version: i64,
@@ -30,7 +31,7 @@ pub struct FileSyncOutgoing {
 
 impl FileSyncOutgoing {
     /// Create a new outgoing syncer
-    pub fn new(config: Arc<RwLock<SyncConfig>>, api: Arc<Api>, db: Arc<Mutex<Option<Storage>>>) -> Self {
+    pub fn new(config: Arc<RwLock<SyncConfig>>, api: Arc<Api>, db: Arc<StorageLock>) -> Self {
         FileSyncOutgoing {
             config: config,
             api: api,
@@ -83,10 +84,13 @@ impl FileSyncOutgoing {
 
         // define a container function that grabs our file and runs the upload.
         // if anything in here fails, we mark 
-        let upload = |note_id, file| -> TResult<UploadRes> {
+        let upload = |note_id: &String, file| -> TResult<UploadRes> {
             // open our local file. we should test if it's readable/exists
             // before making API calls
             let mut file = fs::File::open(&file)?;
+            let mut progress = Progress::with_id(note_id.clone(), "sync:file:upload");
+            progress.step("upload", Some(1));
+            progress.set_total_bytes(file.metadata()?.len());
             // start our API call to the note file attachment endpoint
             let url = format!("/notes/{}/attachment", note_id);
             let req = ApiReq::new().header("Content-Type", &String::from("application/octet-stream"));
@@ -103,10 +107,14 @@ impl FileSyncOutgoing {
                 if read != written {
                     return TErr!(TError::Msg(format!("problem uploading file: grabbed {} bytes, only sent {} wtf wtf lol", read, written)));
                 }
+                progress.add_bytes(written as u64);
             }
             // write all our output and finalize the API call
             stream.flush()?;
-            self.api.call_end(stream.send(), info)
+            let res = self.api.call_end(stream.send(), info)?;
+            progress.inc(1);
+            progress.finish();
+            Ok(res)
         };
 
         match upload(&note_id, file) {
@@ -1,10 +1,10 @@
-use ::std::sync::{Arc, RwLock, Mutex};
+use ::std::sync::{Arc, RwLock};
 use ::std::io::ErrorKind;
 use ::jedi::{self, Value};
 use ::error::{TResult, TError};
 use ::sync::{SyncConfig, Syncer};
 use ::sync::sync_model::{SyncModel, MemorySaver};
-use ::storage::Storage;
+use ::storage::{Storage, StorageLock};
 use ::api::{Api, ApiReq};
 use ::messaging;
 use ::models;
@@ -72,7 +72,7 @@ pub struct SyncIncoming {
 
     /// Holds our user-specific db. This is mainly for persisting k/v data (such
     /// as our last sync_id).
-    db: Arc<Mutex<Option<Storage>>>,
+    db: Arc<StorageLock>,
 
     /// For each type we get back from an outgoing poll, defines a collection
     /// that is able to handle that incoming item (for instance a "note" coming
@@ -90,7 +90,7 @@ pub struct SyncIncoming {
 
 impl SyncIncoming {
     /// Create a new incoming syncer
-    pub fn new(config: Arc<RwLock<SyncConfig>>, api: Arc<Api>, db: Arc<Mutex<Option<Storage>>>) -> SyncIncoming {
+    pub fn new(config: Arc<RwLock<SyncConfig>>, api: Arc<Api>, db: Arc<StorageLock>) -> SyncIncoming {
         let handlers = Handlers {
             user: models::user::User::new(),
             keychain: models::keychain::KeychainEntry::new(),
@@ -247,15 +247,14 @@ impl SyncIncoming {
 
         info!("SyncIncoming.update_local_db_from_api_sync() -- ignored {} incoming syncs", ignore_count);
         with_db!{ db, self.db,
-            // start a transaction. running incoming sync is all or nothing.
-            db.conn.execute("BEGIN TRANSACTION", &[])?;
-            for rec in &mut records {
-                self.run_sync_item(db, rec)?;
-            }
-            // save our sync id
-            db.kv_set("sync_id", &sync_id.to_string())?;
-            // ok, commit
-            db.conn.execute("COMMIT TRANSACTION", &[])?;
+            // running incoming sync is all or nothing
+            db.transaction(|tx| {
+                for rec in &mut records {
+                    self.run_sync_item(tx, rec)?;
+                }
+                // save our sync id
+                tx.kv_set("sync_id", &sync_id.to_string())
+            })?;
         }
 
         // send our incoming syncs into a queue that the Turtl/dispatch thread
@@ -1,11 +1,11 @@
-use ::std::sync::{Arc, RwLock, Mutex};
+use ::std::sync::{Arc, RwLock};
 
 use ::jedi;
 
 use ::error::TResult;
 use ::sync::{SyncConfig, Syncer};
 use ::sync::incoming::SyncIncoming;
-use ::storage::Storage;
+use ::storage::StorageLock;
 use ::api::{Api, ApiReq};
 use ::messaging;
 use ::models::sync_record::{SyncType, SyncRecord};
@@ -35,7 +35,7 @@ pub struct SyncOutgoing {
     /// Holds our user-specific db. This is mainly for persisting k/v data and
     /// for polling the "outgoing" table for local changes that need to be
     /// synced to our heroic API.
-    db: Arc<Mutex<Option<Storage>>>,
+    db: Arc<StorageLock>,
 
     /// Stores our syn run version
     run_version: i64,
@@ -43,7 +43,7 @@ pub struct SyncOutgoing {
 
 impl SyncOutgoing {
     /// Create a new outgoing syncer
-    pub fn new(config: Arc<RwLock<SyncConfig>>, api: Arc<Api>, db: Arc<Mutex<Option<Storage>>>) -> SyncOutgoing {
+    pub fn new(config: Arc<RwLock<SyncConfig>>, api: Arc<Api>, db: Arc<StorageLock>) -> SyncOutgoing {
         SyncOutgoing {
             config: config,
             api: api,
@@ -69,12 +69,25 @@ impl SyncOutgoing {
     }
 
     /// Delete a sync record from sync (like, when we send it to the API and it
-    /// runs successfully...we don't need it sitting around).
+    /// runs successfully...we don't need it sitting around). Also grabs any
+    /// extra sync_ids created from this sync item (the api keeps close track
+    /// of them) and ignores them on the next incoming sync. this keeps us from
+    /// double-syncing some items.
+    ///
+    /// Both happen in one transaction: if we deleted the record but didn't
+    /// ignore its sync ids (or vice versa) we'd double-sync.
     fn delete_sync_record(&self, sync: &SyncRecord) -> TResult<()> {
         let noid = String::from("<no id>");
         debug!("SyncOutgoing.delete_sync_record() -- delete {} ({:?} / {:?})", sync.id.as_ref().unwrap_or(&noid), sync.action, sync.ty);
-        with_db!{ db, self.db, db.delete(sync)?; }
-        Ok(())
+        with_db!{ db, self.db,
+            db.transaction(|tx| {
+                tx.delete(sync)?;
+                match sync.sync_ids.as_ref() {
+                    Some(x) => SyncIncoming::ignore_on_next(tx, x),
+                    None => Ok(()),
+                }
+            })
+        }
     }
 
     /// Handle each failed sync record, and notify the UI that we have failed
@@ -132,20 +145,10 @@ impl Syncer for SyncOutgoing {
         for sync in &sync_result.success {
             // if the record synced successfully, we delete it here
             let res = self.delete_sync_record(sync);
-            // grab any extra sync_ids created from this sync item (the api
-            // keeps close track of them) and ignore them on the next incoming
-            // sync. this keeps us from double-syncing some items.
-            let res2 = with_db!{ db, self.db,
-                match sync.sync_ids.as_ref() {
-                    Some(x) => SyncIncoming::ignore_on_next(db, x),
-                    None => Ok(()),
-                }
-            };
             // track a failure (if it occurs), but then just keep deleting.
             // we don't want to return and have all these sync items re-run
             // just because one of them failed to delete.
             if res.is_err() && err.is_ok() { err = res; }
-            if res2.is_err() && err.is_ok() { err = res2; }
         }
 
         if sync_result.failures.len() > 0 {
@@ -166,7 +169,8 @@ impl Syncer for SyncOutgoing {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use ::std::sync::{Arc, RwLock, Mutex};
+    use ::std::sync::{Arc, RwLock};
+    use ::storage::Storage;
     use ::models::sync_record::SyncRecord;
     use ::jedi;
     use ::schema;
@@ -179,7 +183,7 @@ mod tests {
         let api = Arc::new(Api::new());
         let dumpy_schema = schema::get_schema();
         let db = Storage::new(&String::from(":memory:"), dumpy_schema).unwrap();
-        let db = Arc::new(Mutex::new(Some(db)));
+        let db = Arc::new(StorageLock::new(Some(db)));
 
         let sync1: SyncRecord = jedi::from_val(json!({"id": "1", "action": "add", "item_id": "69", "user_id": 12, "type": "note"})).unwrap();
         let sync2: SyncRecord = jedi::from_val(json!({"id": "2", "action": "add", "item_id": "69", "user_id": 12, "type": "note"})).unwrap();
@@ -67,34 +67,39 @@ pub trait SyncModel: Protected + Storable + Keyfinder + Sync + Send + 'static {
 
     /// Allows a model to save itself to the outgoing sync database (or perform
     /// any custom needed actual in addition/instead).
+    ///
+    /// The model and its sync record are saved in one transaction, so we never
+    /// end up with a local change that doesn't get synced.
     fn outgoing(&self, action: SyncAction, user_id: &String, db: &mut Storage, skip_remote_sync: bool) -> TResult<()> {
-        match action {
-            SyncAction::Delete => {
-                self.db_delete(db, None)?;
-            }
-            _ => {
-                self.db_save(db, None)?;
+        db.transaction(|tx| {
+            match action {
+                SyncAction::Delete => {
+                    self.db_delete(tx, None)?;
+                }
+                _ => {
+                    self.db_save(tx, None)?;
+                }
             }
-        }
-        if skip_remote_sync { return Ok(()); }
+            if skip_remote_sync { return Ok(()); }
 
-        let mut sync_record = SyncRecord::default();
-        sync_record.generate_id()?;
-        sync_record.action = action;
-        sync_record.user_id = user_id.clone();
-        sync_record.ty = SyncType::from_string(self.model_type())?;
-        sync_record.item_id = self.id_or_else()?;
-        match sync_record.action {
-            SyncAction::Delete => {
-                sync_record.data = Some(json!({
-                    "id": self.id().unwrap().clone(),
-                }));
-            }
-            _ => {
-                sync_record.data = Some(self.data_for_storage()?);
+            let mut sync_record = SyncRecord::default();
+            sync_record.generate_id()?;
+            sync_record.action = action;
+            sync_record.user_id = user_id.clone();
+            sync_record.ty = SyncType::from_string(self.model_type())?;
+            sync_record.item_id = self.id_or_else()?;
+            match sync_record.action {
+                SyncAction::Delete => {
+                    sync_record.data = Some(json!({
+                        "id": self.id().unwrap().clone(),
+                    }));
+                }
+                _ => {
+                    sync_record.data = Some(self.data_for_storage()?);
+                }
             }
-        }
-        sync_record.db_save(db, None)
+            sync_record.db_save(tx, None)
+        })
     }
 
     /// Gives us the option to skip an incoming sync. Some sync records are just
@@ -5,6 +5,7 @@
 use ::std::sync::{Arc, RwLock, Mutex};
 use ::std::ops::Drop;
 use ::std::fs;
+use ::std::panic::{self, AssertUnwindSafe};
 use ::regex::Regex;
 use ::num_cpus;
 use ::jedi::{self, Value};
@@ -13,7 +14,7 @@ use ::error::{TResult, TError};
 use ::crypto::Key;
 use ::util;
 use ::util::thredder::Thredder;
-use ::storage::{self, Storage};
+use ::storage::{self, Storage, StorageLock, Query};
 use ::api::Api;
 use ::profile::Profile;
 use ::models::protected::{self, Keyfinder, Protected};
@@ -27,9 +28,11 @@ use ::models::note::Note;
 use ::models::file::FileData;
 use ::models::sync_record::{SyncRecord, SyncAction};
 use ::messaging::{self, Messenger, Response};
+use ::wire::{self, Encoding};
 use ::sync::{self, SyncConfig, SyncState};
 use ::sync::sync_model::MemorySaver;
 use ::search::Search;
+use ::progress::Progress;
 use ::schema;
 use ::migrate::{self, MigrateResult};
 use ::std::collections::HashMap;
@@ -48,6 +51,9 @@ pub fn data_folder() -> TResult<String> {
     Ok(final_folder)
 }
 
+/// How many notes `Turtl::index_notes()` decrypts at once
+const INDEX_NOTES_PAGE_SIZE: i32 = 250;
+
 /// Defines a container for our app's state. Note that most operations the user
 /// has access to via messaging get this object passed to them.
 pub struct Turtl {
@@ -77,7 +83,7 @@ pub struct Turtl {
     /// named via a function of the user ID and the server we're talking to,
     /// meaning we can have multiple databases that store different things for
     /// different people depending on server/user.
-    pub db: Arc<Mutex<Option<Storage>>>,
+    pub db: Arc<StorageLock>,
     /// Our external API object. Note that most things API-related go through
     /// the Sync system, but there are a handful of operations that Sync doesn't
     /// handle that need API access (invites come to mind). Use sparingly.
@@ -116,7 +122,7 @@ impl Turtl {
             msg: Messenger::new(),
             work: Thredder::new("work", num_workers as u32),
             kv: kv,
-            db: Arc::new(Mutex::new(None)),
+            db: Arc::new(StorageLock::new(None)),
             search: Mutex::new(None),
             sync_config: Arc::new(RwLock::new(SyncConfig::new())),
             sync_state: Arc::new(RwLock::new(None)),
@@ -133,51 +139,36 @@ impl Turtl {
     }
 
     /// Send a message to (presumably) our UI.
-    pub fn remote_send(&self, id: Option<String>, msg: String) -> TResult<()> {
+    pub fn remote_send(&self, id: Option<String>, msg: Vec<u8>) -> TResult<()> {
         match id {
-            Some(id) => self.msg.send_suffix(id, msg),
-            None => self.msg.send(msg),
+            Some(id) => self.msg.send_suffix_bytes(id, msg),
+            None => self.msg.send_bytes(msg),
         }
     }
 
-    /// Send a success response to a remote request
-    pub fn msg_success(&self, mid: &String, data: Value) -> TResult<()> {
+    /// Send a response to a remote request, encoded however the request was
+    fn msg_respond(&self, mid: &String, e: i64, data: Value, encoding: Encoding) -> TResult<()> {
         let reqres_append_mid: bool = config::get(&["messaging", "reqres_append_mid"])?;
         if reqres_append_mid {
-            let res = Response::new(0, data);
-            let msg = jedi::stringify(&res)?;
+            let res = Response::new(e, data);
+            let msg = wire::encode(jedi::to_val(&res)?, encoding)?;
             self.remote_send(Some(mid.clone()), msg)
         } else {
-            let res = Response::new_w_id(mid.clone(), 0, data);
-            let msg = jedi::stringify(&res)?;
+            let res = Response::new_w_id(mid.clone(), e, data);
+            let msg = wire::encode(jedi::to_val(&res)?, encoding)?;
             self.remote_send(None, msg)
         }
     }
 
+    /// Send a success response to a remote request
+    pub fn msg_success(&self, mid: &String, data: Value, encoding: Encoding) -> TResult<()> {
+        self.msg_respond(mid, 0, data, encoding)
+    }
+
     /// Send an error response to a remote request
-    pub fn msg_error(&self, mid: &String, err: &TError) -> TResult<()> {
-        let reqres_append_mid: bool = config::get(&["messaging", "reqres_append_mid"])?;
-        let mut errval = util::json_or_string(format!("{}", err));
-        let wrapped = match jedi::get_opt::<bool>(&["wrapped"], &errval) {
-            Some(x) => x,
-            None => false,
-        };
-        let wrap_errors: bool = match config::get(&["wrap_errors"]) {
-            Ok(x) => x,
-            Err(_) => false,
-        };
-        if !wrap_errors && wrapped {
-            errval = jedi::get(&["err"], &errval)?;
-        }
-        if reqres_append_mid {
-            let res = Response::new(1, errval);
-            let msg = jedi::stringify(&res)?;
-            self.remote_send(Some(mid.clone()), msg)
-        } else {
-            let res = Response::new_w_id(mid.clone(), 1, errval);
-            let msg = jedi::stringify(&res)?;
-            self.remote_send(None, msg)
-        }
+    pub fn msg_error(&self, mid: &String, err: &TError, encoding: Encoding) -> TResult<()> {
+        let errval = messaging::error_val(err);
+        self.msg_respond(mid, 1, errval, encoding)
     }
 
     /// If the `turtl.user` object has a valid ID, set it into `turtl.user_id`
@@ -215,6 +206,47 @@ impl Turtl {
         }
     }
 
+    /// Run `run` in a transaction on the user db: if it returns an error (or
+    /// panics) any changes made to the db in the meantime are rolled back.
+    /// This is for work that locks `turtl.db` itself (ie, dispatching syncs)
+    /// and so can't use `Storage::transaction()`.
+    ///
+    /// The db is claimed for the current thread while `run` runs (see
+    /// `StorageLock::claim()`), so other commands and the sync threads wait
+    /// until we're done instead of having their writes land in (and get
+    /// rolled back with) our transaction. Don't wait on them from `run`.
+    ///
+    /// Note that only the db is transactional: anything `run` changes in
+    /// memory stays changed.
+    pub fn db_transaction<F, T>(&self, run: F) -> TResult<T>
+        where F: FnOnce() -> TResult<T>
+    {
+        let _claim = self.db.claim();
+        {
+            let db_guard = lock!(self.db);
+            match db_guard.as_ref() {
+                Some(db) => db.savepoint()?,
+                None => return TErr!(TError::MissingField(String::from("Turtl.db"))),
+            }
+        }
+        let res = panic::catch_unwind(AssertUnwindSafe(run));
+        // don't use lock!() here, since `run` may have panicked while holding
+        // the db lock and we still want to clean up after it
+        let db_guard = match self.db.lock() {
+            Ok(x) => x,
+            Err(e) => e.into_inner(),
+        };
+        match db_guard.as_ref() {
+            Some(db) => db.finish_savepoint(res),
+            None => {
+                // the db got closed out from under us, taking the transaction
+                // with it
+                if let Err(panic) = res { panic::resume_unwind(panic); }
+                TErr!(TError::MissingField(String::from("Turtl.db (closed mid-transaction)")))
+            }
+        }
+    }
+
     /// Call me after a user logs in
     fn post_login(&self) -> TResult<()> {
         self.set_user_id();
@@ -263,8 +295,19 @@ impl Turtl {
         if login.is_none() {
             return TErr!(TError::PermissionDenied(String::from("login on old server failed")));
         }
+        let mut progress = Progress::new("user:join-migrate");
+        let mut num_items: Option<u64> = None;
         let migrate_data = migrate::migrate(login.unwrap(), |ev, args| {
             debug!("turtl.join_migrate() -- migration event: {}", ev);
+            match ev {
+                "profile-download" => progress.step("download-profile", None),
+                "profile-items" => num_items = jedi::from_val(args.clone()).ok(),
+                "files-pre-download" => progress.step("download-files", jedi::from_val(args.clone()).ok()),
+                "file-download" => progress.inc(1),
+                "decrypt-start" => progress.step("decrypt", num_items),
+                "decrypt-item" => progress.inc(1),
+                _ => {}
+            }
             match messaging::ui_event("migration-event", &json!({"event": ev, "args": args})) {
                 Ok(_) => {}
                 Err(e) => {
@@ -272,7 +315,10 @@ impl Turtl {
                 }
             }
         })?;
-        self.do_join(new_username, new_password, Some(migrate_data))
+        progress.step("join", None);
+        self.do_join(new_username, new_password, Some(migrate_data))?;
+        progress.finish();
+        Ok(())
     }
 
     /// Log a user out
@@ -464,8 +510,18 @@ impl Turtl {
     pub fn create_user_db(&self) -> TResult<Storage> {
         let user_id = self.user_id()?;
         let db_location = self.get_user_db_location(&user_id)?;
-        let dumpy_schema = schema::get_schema();
-        Storage::new(&db_location, dumpy_schema)
+        let dumpy = schema::get_dumpy();
+        if !storage::encryption_enabled() {
+            return Storage::with_dumpy(&db_location, dumpy, None);
+        }
+        let key = {
+            let user_guard = lockr!(self.user);
+            match user_guard.key() {
+                Some(x) => x.clone(),
+                None => return TErr!(TError::MissingField(String::from("user.key"))),
+            }
+        };
+        Storage::with_dumpy(&db_location, dumpy, Some(&key))
     }
 
     /// Close the per-user database.
@@ -726,23 +782,36 @@ impl Turtl {
             return TErr!(TError::MissingData(String::from("Turtl.db")));
         }
         let db = db_guard.as_ref().unwrap();
-        let mut notes: Vec<Note> = db.all("notes")?;
-        self.find_models_keys(&mut notes)?;
-        let notes: Vec<Note> = protected::map_deserialize(self, notes)
-            .or_else(|e| -> TResult<Vec<Note>> {
-                error!("turtl.index_notes() -- there was a problem indexing notes: {}", e);
-                Err(e)
-            })?;
         let mut search = Search::new()?;
-        for note in &notes {
-            match search.index_note(note) {
-                Ok(_) => {},
-                // keep going on error
-                Err(e) => error!("turtl.index_notes() -- problem indexing note {:?}: {}", note.id(), e),
+        let mut progress = Progress::new("turtl:index-notes");
+        let total = db.count(&Query::new("notes"))?;
+        progress.step("index", Some(total as u64));
+        // decrypt and index a page of notes at a time so we never have the
+        // entire notes table in memory at once
+        let mut offset = 0;
+        loop {
+            let query = Query::new("notes").limit(INDEX_NOTES_PAGE_SIZE).offset(offset);
+            let mut notes: Vec<Note> = db.query(&query)?;
+            if notes.len() == 0 { break; }
+            offset += notes.len() as i32;
+            self.find_models_keys(&mut notes)?;
+            let notes: Vec<Note> = protected::map_deserialize(self, notes)
+                .or_else(|e| -> TResult<Vec<Note>> {
+                    error!("turtl.index_notes() -- there was a problem indexing notes: {}", e);
+                    Err(e)
+                })?;
+            for note in &notes {
+                progress.inc(1);
+                match search.index_note(note) {
+                    Ok(_) => {},
+                    // keep going on error
+                    Err(e) => error!("turtl.index_notes() -- problem indexing note {:?}: {}", note.id(), e),
+                }
             }
         }
         let mut search_guard = lock!(self.search);
         *search_guard = Some(search);
+        progress.finish();
         Ok(())
     }
 
@@ -829,7 +898,7 @@ impl Drop for Turtl {
 pub mod tests {
     use super::*;
 
-    use ::std::sync::{RwLock, Mutex};
+    use ::std::sync::RwLock;
 
     use ::jedi;
 
@@ -1061,7 +1130,7 @@ pub mod tests {
         }
 
         let db = turtl.create_user_db().unwrap();
-        turtl.db = Arc::new(Mutex::new(Some(db)));
+        turtl.db = Arc::new(StorageLock::new(Some(db)));
 
         let mut space: Space = jedi::parse(&String::from(r#"{
             "user_id":69,
@@ -1118,7 +1187,7 @@ pub mod tests {
         }
 
         let db = turtl.create_user_db().unwrap();
-        turtl.db = Arc::new(Mutex::new(Some(db)));
+        turtl.db = Arc::new(StorageLock::new(Some(db)));
 
         let mut space: Space = jedi::from_val(json!({
             "user_id":69,
@@ -1135,5 +1204,27 @@ pub mod tests {
         assert_eq!(syncs[0].ty, SyncType::Keychain);
         assert_eq!(syncs[1].ty, SyncType::Space);
     }
+
+    #[test]
+    fn rolls_back_db_transactions() {
+        let turtl = with_test(true);
+        let kv_get = |key: &str| -> Option<String> {
+            let db_guard = lock!(turtl.db);
+            db_guard.as_ref().unwrap().kv_get(key).unwrap()
+        };
+        let kv_set = |key: &str, val: &str| -> TResult<()> {
+            let db_guard = lock!(turtl.db);
+            db_guard.as_ref().unwrap().kv_set(key, &String::from(val))
+        };
+        turtl.db_transaction(|| kv_set("name", "kofi")).unwrap();
+        assert_eq!(kv_get("name"), Some(String::from("kofi")));
+
+        let res: TResult<()> = turtl.db_transaction(|| {
+            kv_set("name", "sasha")?;
+            TErr!(TError::BadValue(String::from("nope")))
+        });
+        assert!(res.is_err());
+        assert_eq!(kv_get("name"), Some(String::from("kofi")));
+    }
 }
 
@@ -0,0 +1,260 @@
+//! Handles the encoding of messages going between core and the UI. Messages
+//! can be sent as JSON (the default) or MessagePack.
+//!
+//! The encoding is negotiated per-message: core looks at the first byte of each
+//! incoming request to figure out how it was encoded (JSON requests always
+//! start with `[` or `{`, MessagePack requests never do) and responds in kind.
+//! Events aren't a response to anything, so they're sent using the encoding in
+//! the `messaging.encoding` config (`json` or `msgpack`).
+//!
+//! Internally, core deals in JSON values. Binary data (like file contents) is
+//! marked using `wire::binary()`, which gets sent as a raw MessagePack `bin` or,
+//! when using JSON, as a base64 string. Going the other way, any `bin` values
+//! in incoming MessagePack are turned into base64 strings, which is what the
+//! models expect for binary fields anyway.
+
+use ::std::io::Cursor;
+
+use ::rmpv::{self, Value as MsgValue};
+use ::jedi::{self, Value};
+
+use ::config;
+use ::crypto;
+use ::error::{TResult, TError};
+
+lazy_static! {
+    /// The key we use to mark binary data in a JSON value. It's salted so user
+    /// data that happens to look like a marker (ie `{"$bin": "..."}` in a note)
+    /// doesn't get sent out as binary.
+    static ref BIN_KEY: String = format!("$bin:{}", crypto::random_hash().unwrap());
+}
+
+/// Describes how a message is encoded
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    Json,
+    Msgpack,
+}
+
+impl Encoding {
+    /// Figure out how a message was encoded by looking at its first
+    /// (non-whitespace) byte
+    pub fn detect(bytes: &[u8]) -> Encoding {
+        let first = bytes.iter().find(|x| match **x {
+            b' ' | b'\t' | b'\n' | b'\r' => false,
+            _ => true,
+        });
+        match first {
+            Some(&b'[') | Some(&b'{') | None => Encoding::Json,
+            _ => Encoding::Msgpack,
+        }
+    }
+}
+
+/// Grab the encoding we use for events from the config
+pub fn default_encoding() -> Encoding {
+    match config::get(&["messaging", "encoding"]) {
+        Ok(x) => x,
+        Err(_) => Encoding::Json,
+    }
+}
+
+/// Mark a chunk of binary data so it gets sent as raw binary when the wire
+/// format supports it
+pub fn binary(data: &[u8]) -> TResult<Value> {
+    let mut obj = jedi::Map::new();
+    obj.insert(BIN_KEY.clone(), Value::String(crypto::to_base64(&Vec::from(data))?));
+    Ok(Value::Object(obj))
+}
+
+/// If the given object is a binary marker, return the base64 it holds
+fn binary_base64(obj: &jedi::Map<String, Value>) -> Option<&String> {
+    if obj.len() != 1 { return None; }
+    match obj.get(BIN_KEY.as_str()) {
+        Some(&Value::String(ref x)) => Some(x),
+        _ => None,
+    }
+}
+
+/// Swap out any binary markers for their base64 strings
+fn unbin(val: Value) -> Value {
+    match val {
+        Value::Object(obj) => {
+            if let Some(base64) = binary_base64(&obj).map(|x| x.clone()) {
+                return Value::String(base64);
+            }
+            Value::Object(obj.into_iter().map(|(k, v)| (k, unbin(v))).collect())
+        }
+        Value::Array(arr) => Value::Array(arr.into_iter().map(unbin).collect()),
+        _ => val,
+    }
+}
+
+/// Convert a JSON value to a MessagePack value
+fn to_msgpack(val: Value) -> TResult<MsgValue> {
+    let msgval = match val {
+        Value::Null => MsgValue::Nil,
+        Value::Bool(x) => MsgValue::Boolean(x),
+        Value::Number(x) => {
+            if let Some(x) = x.as_u64() {
+                MsgValue::from(x)
+            } else if let Some(x) = x.as_i64() {
+                MsgValue::from(x)
+            } else {
+                MsgValue::F64(x.as_f64().unwrap_or(0.0))
+            }
+        }
+        Value::String(x) => MsgValue::from(x),
+        Value::Array(arr) => {
+            let mut vals = Vec::with_capacity(arr.len());
+            for x in arr { vals.push(to_msgpack(x)?); }
+            MsgValue::Array(vals)
+        }
+        Value::Object(obj) => {
+            if let Some(base64) = binary_base64(&obj) {
+                return Ok(MsgValue::Binary(crypto::from_base64(base64)?));
+            }
+            let mut vals = Vec::with_capacity(obj.len());
+            for (k, v) in obj { vals.push((MsgValue::from(k), to_msgpack(v)?)); }
+            MsgValue::Map(vals)
+        }
+    };
+    Ok(msgval)
+}
+
+/// Convert a MessagePack value to a JSON value
+fn from_msgpack(val: MsgValue) -> TResult<Value> {
+    let jsonval = match val {
+        MsgValue::Nil => Value::Null,
+        MsgValue::Boolean(x) => Value::Bool(x),
+        MsgValue::Integer(x) => {
+            if let Some(x) = x.as_u64() {
+                json!(x)
+            } else if let Some(x) = x.as_i64() {
+                json!(x)
+            } else {
+                return TErr!(TError::BadValue(format!("wire::from_msgpack() -- bad integer: {:?}", x)));
+            }
+        }
+        MsgValue::F32(x) => json!(x),
+        MsgValue::F64(x) => json!(x),
+        MsgValue::String(x) => {
+            match x.into_str() {
+                Some(x) => Value::String(x),
+                None => return TErr!(TError::BadValue(String::from("wire::from_msgpack() -- string is not valid utf8"))),
+            }
+        }
+        MsgValue::Binary(x) => Value::String(crypto::to_base64(&x)?),
+        MsgValue::Array(arr) => {
+            let mut vals = Vec::with_capacity(arr.len());
+            for x in arr { vals.push(from_msgpack(x)?); }
+            Value::Array(vals)
+        }
+        MsgValue::Map(map) => {
+            let mut obj = jedi::Map::new();
+            for (k, v) in map {
+                let key = match from_msgpack(k)? {
+                    Value::String(x) => x,
+                    x => return TErr!(TError::BadValue(format!("wire::from_msgpack() -- map keys must be strings (got {})", x))),
+                };
+                obj.insert(key, from_msgpack(v)?);
+            }
+            Value::Object(obj)
+        }
+        MsgValue::Ext(..) => {
+            return TErr!(TError::BadValue(String::from("wire::from_msgpack() -- ext types are not supported")));
+        }
+    };
+    Ok(jsonval)
+}
+
+/// Encode a value for sending out
+pub fn encode(val: Value, encoding: Encoding) -> TResult<Vec<u8>> {
+    match encoding {
+        Encoding::Json => Ok(Vec::from(jedi::stringify(&unbin(val))?.as_bytes())),
+        Encoding::Msgpack => {
+            let msgval = to_msgpack(val)?;
+            let mut bytes = Vec::new();
+            rmpv::encode::write_value(&mut bytes, &msgval)
+                .map_err(|e| twrap!(TError::Msg(format!("wire::encode() -- problem encoding msgpack: {}", e))))?;
+            Ok(bytes)
+        }
+    }
+}
+
+/// Decode an incoming message, returning the value and how it was encoded
+pub fn decode(bytes: &[u8]) -> TResult<(Value, Encoding)> {
+    let encoding = Encoding::detect(bytes);
+    let val = match encoding {
+        Encoding::Json => jedi::parse_bytes(bytes)?,
+        Encoding::Msgpack => {
+            let mut cursor = Cursor::new(bytes);
+            let msgval = rmpv::decode::read_value(&mut cursor)
+                .map_err(|e| twrap!(TError::BadValue(format!("wire::decode() -- problem decoding msgpack: {}", e))))?;
+            from_msgpack(msgval)?
+        }
+    };
+    Ok((val, encoding))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_encoding() {
+        assert_eq!(Encoding::detect(b"[\"1\",\"ping\"]"), Encoding::Json);
+        assert_eq!(Encoding::detect(b"  {\"id\":\"1\"}"), Encoding::Json);
+        let bytes = encode(json!(["1", "ping"]), Encoding::Msgpack).unwrap();
+        assert_eq!(Encoding::detect(&bytes), Encoding::Msgpack);
+        let bytes = encode(json!({"id": "1", "cmd": "ping"}), Encoding::Msgpack).unwrap();
+        assert_eq!(Encoding::detect(&bytes), Encoding::Msgpack);
+    }
+
+    #[test]
+    fn roundtrips() {
+        let val = json!({
+            "id": "12",
+            "cmd": "profile:sync:model",
+            "args": {"action": "add", "type": "note", "data": {"title": "hi", "tags": ["a", "b"], "mod": 1497592545, "lat": -4.5, "has_file": false, "board_id": null}},
+        });
+        for enc in &[Encoding::Json, Encoding::Msgpack] {
+            let bytes = encode(val.clone(), *enc).unwrap();
+            let (decoded, detected) = decode(&bytes).unwrap();
+            assert_eq!(detected, *enc);
+            assert_eq!(decoded, val);
+        }
+    }
+
+    #[test]
+    fn handles_binary() {
+        let data = vec![0, 1, 2, 3, 255, 254];
+        let val = json!({"e": 0, "d": binary(&data).unwrap()});
+
+        // json gets plain old base64
+        let bytes = encode(val.clone(), Encoding::Json).unwrap();
+        let (decoded, _) = decode(&bytes).unwrap();
+        assert_eq!(decoded, json!({"e": 0, "d": crypto::to_base64(&data).unwrap()}));
+
+        // msgpack gets real binary, which comes back in as base64
+        let bytes = encode(val, Encoding::Msgpack).unwrap();
+        let mut cursor = Cursor::new(&bytes[..]);
+        let msgval = rmpv::decode::read_value(&mut cursor).unwrap();
+        let bin = msgval.as_map().unwrap().iter()
+            .find(|&&(ref k, _)| k.as_str() == Some("d"))
+            .map(|&(_, ref v)| v.clone())
+            .unwrap();
+        assert_eq!(bin, MsgValue::Binary(data.clone()));
+        let (decoded, _) = decode(&bytes).unwrap();
+        assert_eq!(decoded, json!({"e": 0, "d": crypto::to_base64(&data).unwrap()}));
+
+        // user data that looks like a marker is left alone
+        let val = json!({"e": 0, "d": {"$bin": crypto::to_base64(&data).unwrap()}});
+        for enc in &[Encoding::Json, Encoding::Msgpack] {
+            let bytes = encode(val.clone(), *enc).unwrap();
+            let (decoded, _) = decode(&bytes).unwrap();
+            assert_eq!(decoded, val);
+        }
+    }
+}